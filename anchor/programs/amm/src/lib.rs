@@ -0,0 +1,274 @@
+#![allow(clippy::result_large_err)]
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+declare_id!("9acwHFFeTw3BbtHxiokKBCpi8bWos2pQYcZuLf4uRYbr");
+
+const BPS_DENOMINATOR: u64 = 10_000;
+
+/// A bare constant-product pool. It has no LP shares; liquidity added is
+/// simply donated to the pool. Good enough to stand in for a real venue
+/// behind the lending program's swap adapter.
+#[program]
+pub mod amm {
+    use super::*;
+
+    pub fn initialize_pool(ctx: Context<InitializePool>, fee_bps: u64) -> Result<()> {
+        require!(fee_bps < BPS_DENOMINATOR, AmmError::InvalidFee);
+        require_keys_neq!(
+            ctx.accounts.mint_a.key(),
+            ctx.accounts.mint_b.key(),
+            AmmError::InvalidMints
+        );
+
+        let pool = &mut ctx.accounts.pool;
+        pool.mint_a = ctx.accounts.mint_a.key();
+        pool.mint_b = ctx.accounts.mint_b.key();
+        pool.fee_bps = fee_bps;
+        pool.bump = ctx.bumps.pool;
+        Ok(())
+    }
+
+    pub fn add_liquidity(ctx: Context<AddLiquidity>, amount_a: u64, amount_b: u64) -> Result<()> {
+        let accounts = &ctx.accounts;
+        transfer_checked(
+            CpiContext::new(
+                accounts.token_program_a.to_account_info(),
+                TransferChecked {
+                    from: accounts.provider_a.to_account_info(),
+                    to: accounts.vault_a.to_account_info(),
+                    mint: accounts.mint_a.to_account_info(),
+                    authority: accounts.provider.to_account_info(),
+                },
+            ),
+            amount_a,
+            accounts.mint_a.decimals,
+        )?;
+        transfer_checked(
+            CpiContext::new(
+                accounts.token_program_b.to_account_info(),
+                TransferChecked {
+                    from: accounts.provider_b.to_account_info(),
+                    to: accounts.vault_b.to_account_info(),
+                    mint: accounts.mint_b.to_account_info(),
+                    authority: accounts.provider.to_account_info(),
+                },
+            ),
+            amount_b,
+            accounts.mint_b.decimals,
+        )
+    }
+
+    /// Sells `amount_in` of `mint_in` for `mint_out`, in either direction.
+    pub fn swap(ctx: Context<Swap>, amount_in: u64, min_amount_out: u64) -> Result<()> {
+        let accounts = &ctx.accounts;
+        let pool = &accounts.pool;
+        let (mint_in, mint_out) = (accounts.mint_in.key(), accounts.mint_out.key());
+        require!(
+            (mint_in == pool.mint_a && mint_out == pool.mint_b)
+                || (mint_in == pool.mint_b && mint_out == pool.mint_a),
+            AmmError::InvalidMints
+        );
+
+        let reserve_in = accounts.vault_in.amount as u128;
+        let reserve_out = accounts.vault_out.amount as u128;
+        require!(reserve_in > 0 && reserve_out > 0, AmmError::EmptyPool);
+
+        let in_after_fee =
+            amount_in as u128 * (BPS_DENOMINATOR - pool.fee_bps) as u128 / BPS_DENOMINATOR as u128;
+        let amount_out = (reserve_out * in_after_fee / (reserve_in + in_after_fee)) as u64;
+        require!(
+            amount_out > 0 && amount_out >= min_amount_out,
+            AmmError::SlippageExceeded
+        );
+
+        transfer_checked(
+            CpiContext::new(
+                accounts.token_program_in.to_account_info(),
+                TransferChecked {
+                    from: accounts.user_in.to_account_info(),
+                    to: accounts.vault_in.to_account_info(),
+                    mint: accounts.mint_in.to_account_info(),
+                    authority: accounts.user.to_account_info(),
+                },
+            ),
+            amount_in,
+            accounts.mint_in.decimals,
+        )?;
+
+        let seeds: &[&[u8]] = &[
+            b"pool",
+            pool.mint_a.as_ref(),
+            pool.mint_b.as_ref(),
+            &[pool.bump],
+        ];
+        transfer_checked(
+            CpiContext::new_with_signer(
+                accounts.token_program_out.to_account_info(),
+                TransferChecked {
+                    from: accounts.vault_out.to_account_info(),
+                    to: accounts.user_out.to_account_info(),
+                    mint: accounts.mint_out.to_account_info(),
+                    authority: pool.to_account_info(),
+                },
+                &[seeds],
+            ),
+            amount_out,
+            accounts.mint_out.decimals,
+        )
+    }
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct Pool {
+    pub mint_a: Pubkey,
+    pub mint_b: Pubkey,
+    pub fee_bps: u64,
+    pub bump: u8,
+}
+
+#[error_code]
+pub enum AmmError {
+    #[msg("Fee must be below 100%")]
+    InvalidFee,
+    #[msg("Mints do not match the pool")]
+    InvalidMints,
+    #[msg("Pool has no liquidity")]
+    EmptyPool,
+    #[msg("Output is below the minimum")]
+    SlippageExceeded,
+}
+
+#[derive(Accounts)]
+pub struct InitializePool<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(mint::token_program = token_program_a)]
+    pub mint_a: InterfaceAccount<'info, Mint>,
+
+    #[account(mint::token_program = token_program_b)]
+    pub mint_b: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Pool::INIT_SPACE,
+        seeds = [b"pool", mint_a.key().as_ref(), mint_b.key().as_ref()],
+        bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(
+        init,
+        payer = payer,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program_a
+    )]
+    pub vault_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = payer,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program_b
+    )]
+    pub vault_b: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct AddLiquidity<'info> {
+    pub provider: Signer<'info>,
+
+    #[account(
+        seeds = [b"pool", pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump = pool.bump,
+        has_one = mint_a,
+        has_one = mint_b
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(mint::token_program = token_program_a)]
+    pub mint_a: InterfaceAccount<'info, Mint>,
+
+    #[account(mint::token_program = token_program_b)]
+    pub mint_b: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, token::mint = mint_a, token::authority = provider)]
+    pub provider_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut, token::mint = mint_b, token::authority = provider)]
+    pub provider_b: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_a,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program_a
+    )]
+    pub vault_a: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_b,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program_b
+    )]
+    pub vault_b: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program_a: Interface<'info, TokenInterface>,
+    pub token_program_b: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct Swap<'info> {
+    pub user: Signer<'info>,
+
+    #[account(
+        seeds = [b"pool", pool.mint_a.as_ref(), pool.mint_b.as_ref()],
+        bump = pool.bump
+    )]
+    pub pool: Account<'info, Pool>,
+
+    #[account(mint::token_program = token_program_in)]
+    pub mint_in: InterfaceAccount<'info, Mint>,
+
+    #[account(mint::token_program = token_program_out)]
+    pub mint_out: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, token::mint = mint_in, token::authority = user)]
+    pub user_in: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut, token::mint = mint_out)]
+    pub user_out: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_in,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program_in
+    )]
+    pub vault_in: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = mint_out,
+        associated_token::authority = pool,
+        associated_token::token_program = token_program_out
+    )]
+    pub vault_out: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program_in: Interface<'info, TokenInterface>,
+    pub token_program_out: Interface<'info, TokenInterface>,
+}
@@ -0,0 +1,120 @@
+use crate::errors::ErrorCode;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+
+/// Pauses every path that adds risk; see `Engine::paused` for what stays open.
+pub fn pause(ctx: Context<Pause>) -> Result<()> {
+    ctx.accounts.engine.paused = true;
+
+    emit!(PauseChanged {
+        by: ctx.accounts.guardian.key(),
+        paused: true,
+    });
+    Ok(())
+}
+
+/// Lifts a pause. Once a multisig is configured unpausing has to go through a
+/// proposal instead.
+pub fn unpause(ctx: Context<Unpause>) -> Result<()> {
+    let engine = &mut ctx.accounts.engine;
+    require!(engine.multisig_threshold == 0, ErrorCode::MultisigRequired);
+    engine.paused = false;
+
+    emit!(PauseChanged {
+        by: ctx.accounts.admin.key(),
+        paused: false,
+    });
+    Ok(())
+}
+
+/// Writes a token's price. Once governance has set an oracle for the token
+/// only that key may update it; until then the oracle updater role does.
+pub fn update_price(ctx: Context<UpdatePrice>, new_price: u64) -> Result<()> {
+    require!(new_price > 0, ErrorCode::InvalidPrice);
+    require!(!ctx.accounts.engine.shutdown, ErrorCode::ShutdownActive);
+
+    let config = &ctx.accounts.config;
+    let oracle = if config.oracle == Pubkey::default() {
+        ctx.accounts.roles.oracle_updater
+    } else {
+        config.oracle
+    };
+    require_keys_eq!(
+        ctx.accounts.oracle_updater.key(),
+        oracle,
+        ErrorCode::UnauthorizedUser
+    );
+
+    let price = &mut ctx.accounts.price;
+    price.price = new_price;
+    price.updated_at = Clock::get()?.unix_timestamp;
+
+    emit!(PriceUpdated {
+        token: price.token_mint,
+        price: new_price,
+    });
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct UpdatePrice<'info> {
+    #[account(seeds = [b"roles", engine.key().as_ref()], bump = roles.bump)]
+    pub roles: Account<'info, Roles>,
+
+    pub oracle_updater: Signer<'info>,
+
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(
+        mut,
+        seeds = [b"price", engine.key().as_ref(), price.token_mint.as_ref()],
+        bump = price.bump
+    )]
+    pub price: Account<'info, Price>,
+
+    #[account(
+        seeds = [b"config", engine.key().as_ref(), price.token_mint.as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+}
+
+#[derive(Accounts)]
+pub struct Pause<'info> {
+    #[account(
+        seeds = [b"roles", engine.key().as_ref()],
+        bump = roles.bump,
+        has_one = guardian @ ErrorCode::UnauthorizedUser
+    )]
+    pub roles: Account<'info, Roles>,
+
+    pub guardian: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+}
+
+#[derive(Accounts)]
+pub struct Unpause<'info> {
+    #[account(
+        seeds = [b"roles", engine.key().as_ref()],
+        bump = roles.bump,
+        has_one = admin @ ErrorCode::UnauthorizedUser
+    )]
+    pub roles: Account<'info, Roles>,
+
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+}
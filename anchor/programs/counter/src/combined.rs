@@ -0,0 +1,215 @@
+use crate::constants::*;
+use crate::deposit::{credit_collateral, open_position, require_collateral_active};
+use crate::engine::{mint_as_engine, require_healthy_debt};
+use crate::errors::ErrorCode;
+use crate::position_nft::position_controller;
+use crate::pricefeeds::convert_collateral_to_usd;
+use crate::recovery::require_mint_keeps_system_cr;
+use crate::redemption::sync_position;
+use crate::repay::burn_debt;
+use crate::shared::transfer_tokens;
+use crate::structs::*;
+use crate::withdraw::{authorize_withdrawal, withdraw_collateral_amount, WithdrawToken};
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_2022::Token2022,
+    token_interface::{Mint, TokenAccount, TokenInterface},
+};
+
+/// Deposits collateral and mints DSC against it at the stored price, so the
+/// health check sees both sides at once.
+pub fn deposit_and_mint(
+    ctx: Context<DepositAndMint>,
+    deposit_amount: u64,
+    mint_amount: u64,
+) -> Result<()> {
+    require!(
+        deposit_amount > 0 && mint_amount > 0,
+        ErrorCode::AmountLessThanZero
+    );
+    require_collateral_active(&ctx.accounts.engine, &ctx.accounts.config)?;
+
+    let accounts = &mut *ctx.accounts;
+    open_position(
+        &mut accounts.user_data,
+        ctx.bumps.user_data,
+        &mut accounts.deposit,
+        ctx.bumps.deposit,
+        accounts.user.key(),
+        accounts.token_mint.key(),
+        accounts.config.key(),
+    )?;
+    require_keys_eq!(
+        position_controller(&accounts.user_data, accounts.position_nft.as_deref())?,
+        accounts.user.key(),
+        ErrorCode::NotPositionHolder
+    );
+
+    transfer_tokens(
+        &accounts.user_token_account,
+        &accounts.vault,
+        &deposit_amount,
+        &accounts.token_mint,
+        &accounts.user,
+        &accounts.token_program,
+    )?;
+    credit_collateral(
+        &mut accounts.user_data,
+        &mut accounts.deposit,
+        &accounts.position_index,
+        &mut accounts.engine,
+        &accounts.price,
+        deposit_amount,
+    )?;
+
+    let new_borrowed = accounts
+        .user_data
+        .borrowed_amount
+        .checked_add(mint_amount)
+        .ok_or(ErrorCode::Overflow)?;
+    require_healthy_debt(
+        &accounts.deposit,
+        &accounts.price,
+        &accounts.engine,
+        new_borrowed,
+    )?;
+    require_mint_keeps_system_cr(
+        &accounts.engine,
+        convert_collateral_to_usd(deposit_amount, &accounts.price)?,
+        mint_amount,
+    )?;
+
+    mint_as_engine(
+        &accounts.engine,
+        &accounts.dsc_mint,
+        &accounts.user_dsc_account,
+        &accounts.dsc_token_program,
+        mint_amount,
+    )?;
+    accounts.user_data.borrowed_amount = new_borrowed;
+
+    sync_position(
+        &accounts.position_index,
+        &mut accounts.engine,
+        &accounts.price,
+        &mut accounts.user_data,
+        accounts.deposit.token_amt,
+        new_borrowed,
+    )
+}
+
+/// Burns the user's DSC and then releases collateral, with the health check
+/// run against the reduced debt.
+pub fn repay_and_withdraw(
+    ctx: &mut Context<WithdrawToken>,
+    repay_amount: u64,
+    withdraw_amount: u64,
+) -> Result<()> {
+    require!(!ctx.accounts.engine.shutdown, ErrorCode::ShutdownActive);
+    authorize_withdrawal(ctx, DELEGATE_REPAY)?;
+
+    burn_debt(
+        &mut ctx.accounts.user_data,
+        &ctx.accounts.dsc_mint,
+        &ctx.accounts.user_dsc_account,
+        &ctx.accounts.user.to_account_info(),
+        &[],
+        &ctx.accounts.dsc_token_program,
+        ctx.accounts.token_mint.key(),
+        repay_amount,
+    )?;
+
+    withdraw_collateral_amount(ctx, withdraw_amount)
+}
+
+#[derive(Accounts)]
+pub struct DepositAndMint<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + UserData::INIT_SPACE,
+        seeds = [b"user", engine.key().as_ref(), user.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + Deposit::INIT_SPACE,
+        seeds = [b"deposit", engine.key().as_ref(), user.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub deposit: Account<'info, Deposit>,
+
+    pub position_nft: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump
+    )]
+    pub price: Account<'info, Price>,
+
+    #[account(
+        mut,
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = position_index.load()?.bump,
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = config,
+        associated_token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = user,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub user_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub dsc_token_program: Program<'info, Token2022>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
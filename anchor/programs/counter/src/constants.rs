@@ -3,3 +3,52 @@ use anchor_lang::prelude::*;
 #[constant]
 // pub const eurc_fee: &str = "91Sfpm86H7ZgngdGfAiVJTNbg42CXBPiurruf29kinMh";
 pub const MAXIMUM_AGE: u64 = 100;
+
+#[constant]
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+// Default split of collateral fees: LPs keep what they had, the rest goes to
+// the treasury.
+pub const DEFAULT_LP_FEE_BPS: u64 = 7_500;
+pub const DEFAULT_TREASURY_FEE_BPS: u64 = 2_500;
+
+pub const MAX_INDEXED_POSITIONS: usize = 200;
+
+pub const MAX_COLLATERALS: usize = 64;
+
+// Collateral statuses. Anything but active blocks deposits and new mints.
+pub const COLLATERAL_ACTIVE: u8 = 0;
+pub const COLLATERAL_FROZEN: u8 = 1;
+pub const COLLATERAL_DELISTED: u8 = 2;
+
+// Redemption fee rates are fixed point with 9 decimals.
+pub const RATE_PRECISION: u64 = 1_000_000_000;
+pub const REDEMPTION_FEE_FLOOR: u64 = 5_000_000;
+// 0.5^(1/720): the base rate halves every 12 hours.
+pub const MINUTE_DECAY_FACTOR: u64 = 999_037_759;
+pub const REDEMPTION_BETA: u64 = 2;
+
+pub const CHI_PRECISION: u128 = 1_000_000_000_000;
+pub const SECONDS_PER_YEAR: u64 = 31_536_000;
+
+pub const DSC_DECIMALS: u8 = 6;
+
+// Governance changes wait two days by default and never more than thirty.
+pub const DEFAULT_TIMELOCK_DELAY: i64 = 172_800;
+pub const MAX_TIMELOCK_DELAY: i64 = 2_592_000;
+
+pub const MAX_MULTISIG_SIGNERS: usize = 8;
+
+// After shutdown, positions get a day to be settled before DSC holders can
+// cash out.
+pub const SHUTDOWN_SETTLEMENT_WAIT: i64 = 86_400;
+
+// Delegate permissions, as bits of `Delegation::permissions`.
+pub const DELEGATE_REPAY: u8 = 1;
+pub const DELEGATE_DEPOSIT: u8 = 1 << 1;
+pub const DELEGATE_MINT: u8 = 1 << 2;
+pub const DELEGATE_WITHDRAW: u8 = 1 << 3;
+
+// What a protection order does once triggered.
+pub const ORDER_REPAY: u8 = 0;
+pub const ORDER_SELL_COLLATERAL: u8 = 1;
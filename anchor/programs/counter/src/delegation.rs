@@ -0,0 +1,191 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::position_nft::position_controller;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount};
+
+/// Installs or replaces the delegate on a position the caller controls.
+/// Replacing it resets the minted counter.
+pub fn set_delegate(
+    ctx: Context<SetDelegate>,
+    delegate: Pubkey,
+    permissions: u8,
+    mint_limit: u64,
+) -> Result<()> {
+    require!(
+        permissions & !(DELEGATE_REPAY | DELEGATE_DEPOSIT | DELEGATE_MINT | DELEGATE_WITHDRAW) == 0,
+        ErrorCode::DelegateNotAllowed
+    );
+    require_keys_neq!(
+        delegate,
+        ctx.accounts.owner.key(),
+        ErrorCode::DelegateNotAllowed
+    );
+    require_keys_eq!(
+        position_controller(
+            &ctx.accounts.user_data,
+            ctx.accounts.position_nft.as_deref()
+        )?,
+        ctx.accounts.owner.key(),
+        ErrorCode::NotPositionHolder
+    );
+
+    let delegation = &mut ctx.accounts.delegation;
+    delegation.owner = ctx.accounts.owner.key();
+    delegation.position = ctx.accounts.user_data.key();
+    delegation.token_mint = ctx.accounts.token_mint.key();
+    delegation.delegate = delegate;
+    delegation.permissions = permissions;
+    delegation.mint_limit = mint_limit;
+    delegation.minted = 0;
+    delegation.bump = ctx.bumps.delegation;
+
+    emit!(DelegateSet {
+        owner: delegation.owner,
+        position: delegation.position,
+        token: delegation.token_mint,
+        delegate,
+        permissions,
+        mint_limit,
+    });
+    Ok(())
+}
+
+pub fn revoke_delegate(ctx: Context<RevokeDelegate>) -> Result<()> {
+    require_keys_eq!(
+        position_controller(
+            &ctx.accounts.user_data,
+            ctx.accounts.position_nft.as_deref()
+        )?,
+        ctx.accounts.owner.key(),
+        ErrorCode::NotPositionHolder
+    );
+
+    emit!(DelegateSet {
+        owner: ctx.accounts.owner.key(),
+        position: ctx.accounts.user_data.key(),
+        token: ctx.accounts.delegation.token_mint,
+        delegate: Pubkey::default(),
+        permissions: 0,
+        mint_limit: 0,
+    });
+    Ok(())
+}
+
+/// Passes for the position's controller, or for a delegate the controller
+/// installed that holds every bit in `permissions`.
+pub fn authorize(
+    actor: Pubkey,
+    owner: Pubkey,
+    delegation: Option<&Delegation>,
+    permissions: u8,
+) -> Result<()> {
+    if actor == owner {
+        return Ok(());
+    }
+    match delegation {
+        Some(d)
+            if d.owner == owner
+                && d.delegate == actor
+                && d.permissions & permissions == permissions =>
+        {
+            Ok(())
+        }
+        _ => err!(ErrorCode::DelegateNotAllowed),
+    }
+}
+
+/// Counts a delegate's mint against its limit. Owner mints are not tracked.
+pub fn record_delegate_mint(
+    actor: Pubkey,
+    owner: Pubkey,
+    delegation: Option<&mut Account<Delegation>>,
+    amount: u64,
+) -> Result<()> {
+    if actor == owner {
+        return Ok(());
+    }
+    let delegation = delegation.ok_or(ErrorCode::DelegateNotAllowed)?;
+    delegation.minted = delegation
+        .minted
+        .checked_add(amount)
+        .ok_or(ErrorCode::Overflow)?;
+    require!(
+        delegation.minted <= delegation.mint_limit,
+        ErrorCode::DelegateMintLimit
+    );
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct SetDelegate<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [
+            b"user",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    pub position_nft: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init_if_needed,
+        payer = owner,
+        space = 8 + Delegation::INIT_SPACE,
+        seeds = [b"delegation", engine.key().as_ref(), user_data.key().as_ref()],
+        bump
+    )]
+    pub delegation: Account<'info, Delegation>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeDelegate<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(
+        seeds = [
+            b"user",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            delegation.token_mint.as_ref(),
+        ],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    pub position_nft: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        close = owner,
+        seeds = [b"delegation", engine.key().as_ref(), user_data.key().as_ref()],
+        bump = delegation.bump
+    )]
+    pub delegation: Account<'info, Delegation>,
+}
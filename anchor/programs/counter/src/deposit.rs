@@ -1,4 +1,8 @@
+use crate::constants::*;
+use crate::delegation::authorize;
 use crate::errors::ErrorCode;
+use crate::position_nft::position_controller;
+use crate::redemption::sync_position;
 use crate::shared::*;
 use crate::structs::*;
 
@@ -8,11 +12,11 @@ use anchor_spl::{
     token_interface::{Mint, TokenAccount, TokenInterface},
 };
 
-pub fn deposit_token(mut ctx: &mut Context<DepositToken>, amount: u64) -> Result<()> {
+pub fn deposit_token(ctx: &mut Context<DepositToken>, amount: u64) -> Result<()> {
     let deposit = &mut ctx.accounts.deposit;
-    let user_key = ctx.accounts.user.key();
+    let owner_key = ctx.accounts.owner.key();
 
-    if deposit.user != Pubkey::default() && deposit.user != user_key {
+    if deposit.user != Pubkey::default() && deposit.user != owner_key {
         return Err(error!(ErrorCode::UnauthorizedUser));
     }
 
@@ -20,36 +24,148 @@ pub fn deposit_token(mut ctx: &mut Context<DepositToken>, amount: u64) -> Result
         return Err(ErrorCode::AmountLessThanZero.into());
     }
 
-    if ctx.accounts.user_data.user == Pubkey::default() {
-        let user_data1 = &mut ctx.accounts.user_data;
-        user_data1.user = ctx.accounts.user.key();
-        user_data1.borrowed_amount = 0;
-        user_data1.primary_token = ctx.accounts.token_mint.key();
-        user_data1.bump = ctx.bumps.user_data;
-        user_data1.hf = u64::MAX;
-        user_data1.token_balance = 0;
-    }
+    require_collateral_active(&ctx.accounts.engine, &ctx.accounts.config)?;
+
+    let bumps = &ctx.bumps;
+    let accounts = &mut ctx.accounts;
+    open_position(
+        &mut accounts.user_data,
+        bumps.user_data,
+        &mut accounts.deposit,
+        bumps.deposit,
+        accounts.owner.key(),
+        accounts.token_mint.key(),
+        accounts.config.key(),
+    )?;
+
+    let controller = position_controller(&accounts.user_data, accounts.position_nft.as_deref())?;
+    authorize(
+        accounts.user.key(),
+        controller,
+        accounts.delegation.as_deref(),
+        DELEGATE_DEPOSIT,
+    )?;
+
+    deposit_tokens_to_vault(ctx, amount)?;
+
+    credit_collateral(
+        &mut ctx.accounts.user_data,
+        &mut ctx.accounts.deposit,
+        &ctx.accounts.position_index,
+        &mut ctx.accounts.engine,
+        &ctx.accounts.price,
+        amount,
+    )
+}
+
+/// Adds the payer's collateral to someone else's existing position, e.g. to
+/// pull it away from liquidation.
+pub fn deposit_for(ctx: Context<DepositFor>, amount: u64) -> Result<()> {
+    require!(amount > 0, ErrorCode::AmountLessThanZero);
+    require_collateral_active(&ctx.accounts.engine, &ctx.accounts.config)?;
+
+    transfer_tokens(
+        &ctx.accounts.payer_token_account,
+        &ctx.accounts.vault,
+        &amount,
+        &ctx.accounts.token_mint,
+        &ctx.accounts.payer,
+        &ctx.accounts.token_program,
+    )?;
+
+    credit_collateral(
+        &mut ctx.accounts.user_data,
+        &mut ctx.accounts.deposit,
+        &ctx.accounts.position_index,
+        &mut ctx.accounts.engine,
+        &ctx.accounts.price,
+        amount,
+    )?;
+
+    emit!(DepositedOnBehalf {
+        payer: ctx.accounts.payer.key(),
+        beneficiary: ctx.accounts.beneficiary.key(),
+        token: ctx.accounts.token_mint.key(),
+        amount,
+    });
+
+    Ok(())
+}
 
-    deposit_tokens_to_vault(&ctx, amount)?;
+/// New deposits and mints need a live, unpaused protocol and an active token.
+pub fn require_collateral_active(engine: &Engine, config: &Config) -> Result<()> {
+    require!(!engine.paused, ErrorCode::ProtocolPaused);
+    require!(!engine.shutdown, ErrorCode::ShutdownActive);
+    require!(
+        config.status == COLLATERAL_ACTIVE,
+        ErrorCode::CollateralNotActive
+    );
+    Ok(())
+}
 
-    if ctx.accounts.deposit.token_amt == 0 {
-        save_deposit(&mut ctx, 0)?;
+/// Fills in a position's user data and deposit record the first time they
+/// are used.
+pub fn open_position(
+    user_data: &mut UserData,
+    user_data_bump: u8,
+    deposit: &mut Deposit,
+    deposit_bump: u8,
+    user: Pubkey,
+    token_mint: Pubkey,
+    config: Pubkey,
+) -> Result<()> {
+    if user_data.user == Pubkey::default() {
+        user_data.user = user;
+        user_data.borrowed_amount = 0;
+        user_data.primary_token = token_mint;
+        user_data.bump = user_data_bump;
+        user_data.hf = u64::MAX;
+        user_data.hf_slot = Clock::get()?.slot;
+        user_data.token_balance = 0;
+        user_data.position_mint = Pubkey::default();
     }
 
-    let deposit = &mut ctx.accounts.deposit;
-    deposit.token_amt = deposit.token_amt.checked_add(amount).unwrap();
+    if deposit.token_amt == 0 {
+        deposit.user = user;
+        deposit.token_mint = token_mint;
+        deposit.token_amt = 0;
+        deposit.bump = deposit_bump;
+        deposit.config_account = config;
+    }
+    Ok(())
+}
 
-    ctx.accounts.user_data.token_balance = ctx
-        .accounts
-        .user_data
+/// Books collateral that has just reached the vault on the position.
+pub fn credit_collateral(
+    user_data: &mut UserData,
+    deposit: &mut Deposit,
+    position_index: &AccountLoader<PositionIndex>,
+    engine: &mut Engine,
+    price: &Price,
+    amount: u64,
+) -> Result<()> {
+    deposit.token_amt = deposit
+        .token_amt
+        .checked_add(amount)
+        .ok_or(ErrorCode::Overflow)?;
+    user_data.token_balance = user_data
         .token_balance
         .checked_add(amount)
-        .unwrap();
+        .ok_or(ErrorCode::Overflow)?;
+
+    sync_position(
+        position_index,
+        engine,
+        price,
+        user_data,
+        deposit.token_amt,
+        user_data.borrowed_amount,
+    )?;
 
     emit!(TokenDeposited {
-        user: ctx.accounts.user.key(),
-        token: ctx.accounts.token_mint.key(),
-        amount: amount as u64,
+        user: user_data.user,
+        token: deposit.token_mint,
+        amount,
     });
 
     Ok(())
@@ -67,21 +183,21 @@ pub fn deposit_tokens_to_vault(ctx: &Context<DepositToken>, token_amt: u64) -> R
     Ok(())
 }
 
-pub fn save_deposit(ctx: &mut Context<DepositToken>, token_amt: u64) -> Result<()> {
-    let deposit = &mut ctx.accounts.deposit;
-    deposit.user = ctx.accounts.user.key();
-    deposit.token_mint = ctx.accounts.token_mint.key();
-    deposit.token_amt = token_amt;
-    deposit.bump = ctx.bumps.deposit;
-    deposit.config_account = ctx.accounts.config.key();
-    Ok(())
-}
-
 #[derive(Accounts)]
 pub struct DepositToken<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
+    /// CHECK: the key the position was opened under; only used as a seed.
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
     #[account(mint::token_program = token_program)]
     pub token_mint: InterfaceAccount<'info, Mint>,
 
@@ -98,7 +214,7 @@ pub struct DepositToken<'info> {
     init_if_needed,
     payer = user,
     space = 8 + UserData::INIT_SPACE,
-    seeds = [b"user", user.key().as_ref(),token_mint.key().as_ref()],
+    seeds = [b"user", engine.key().as_ref(), owner.key().as_ref(),token_mint.key().as_ref()],
     bump
     )]
     pub user_data: Account<'info, UserData>,
@@ -107,18 +223,40 @@ pub struct DepositToken<'info> {
         init_if_needed,
         payer = user,
         space = 8 + Deposit::INIT_SPACE,
-        seeds = [b"deposit", user.key().as_ref(), token_mint.key().as_ref()],
+        seeds = [b"deposit", engine.key().as_ref(), owner.key().as_ref(), token_mint.key().as_ref()],
         bump,
         // constraint = deposit.user == user.key() 
     )]
     pub deposit: Account<'info, Deposit>,
 
     #[account(
-        seeds = [b"config", token_mint.key().as_ref()],
+        seeds = [b"delegation", engine.key().as_ref(), user_data.key().as_ref()],
+        bump = delegation.bump
+    )]
+    pub delegation: Option<Account<'info, Delegation>>,
+
+    pub position_nft: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
         bump = config.bump
     )]
     pub config: Account<'info, Config>,
 
+    #[account(
+        has_one = token_mint,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump,
+    )]
+    pub price: Account<'info, Price>,
+
+    #[account(
+        mut,
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = position_index.load()?.bump,
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+
     #[account(mut)]
     pub vault: InterfaceAccount<'info, TokenAccount>,
 
@@ -126,3 +264,84 @@ pub struct DepositToken<'info> {
     pub system_program: Program<'info, System>,
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
+
+#[derive(Accounts)]
+pub struct DepositFor<'info> {
+    pub payer: Signer<'info>,
+
+    /// CHECK: only used to derive the position being topped up.
+    pub beneficiary: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = payer,
+        token::token_program = token_program
+    )]
+    pub payer_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user",
+            engine.key().as_ref(),
+            beneficiary.key().as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"deposit",
+            engine.key().as_ref(),
+            beneficiary.key().as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = deposit.bump
+    )]
+    pub deposit: Account<'info, Deposit>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump,
+    )]
+    pub price: Account<'info, Price>,
+
+    #[account(
+        mut,
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = position_index.load()?.bump,
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = config,
+        associated_token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
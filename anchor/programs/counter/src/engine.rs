@@ -1,43 +1,168 @@
+use crate::constants::*;
+use crate::delegation::{authorize, record_delegate_mint};
+use crate::deposit::require_collateral_active;
 use crate::errors::ErrorCode;
+use crate::insurance::record_insurance_deposit;
+use crate::position_nft::position_controller;
 use crate::pricefeeds::*;
+use crate::recovery::{below_system_cr, require_mint_keeps_system_cr};
+use crate::redemption::sync_position;
+use crate::shared::{bps_of, percent_of};
 use crate::structs::*;
 // use pyth_solana_receiver_sdk::price_update::PriceUpdateV2;
 
 use anchor_lang::prelude::*;
+use anchor_lang::system_program::{transfer, Transfer};
 use anchor_spl::{
     associated_token::AssociatedToken,
+    token_2022::Token2022,
+    token_2022_extensions::spl_token_metadata_interface::state::TokenMetadata,
     token_interface::{
-        burn, mint_to, transfer_checked, Burn, Mint, MintTo, TokenAccount, TokenInterface,
-        TransferChecked,
+        burn, mint_to, token_metadata_initialize, transfer_checked, Burn, Mint, MintTo,
+        TokenAccount, TokenInterface, TokenMetadataInitialize, TransferChecked,
     },
 };
 
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct DscMetadataArgs {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+}
+
 pub fn initialize_engine(
     ctx: Context<InitializeEngine>,
+    market_id: u64,
     liquidation_threshold: u64,
     min_health_factor: u64,
     liquidation_bonus: u64,
     fee_percent: u64,
+    metadata_args: DscMetadataArgs,
 ) -> Result<()> {
     let engine = &mut ctx.accounts.engine;
-    engine.authority = ctx.accounts.authority.key();
+    engine.market_id = market_id;
     engine.dsc_mint = ctx.accounts.dsc_mint.key();
     engine.liquidation_threshold = liquidation_threshold;
     engine.min_health_factor = min_health_factor;
     engine.liquidation_bonus = liquidation_bonus;
     engine.fee_percent = fee_percent;
+    engine.flash_mint_cap = 0;
+    engine.flash_mint_fee_bps = 0;
+    engine.redemption_base_rate = 0;
+    engine.last_redemption_ts = Clock::get()?.unix_timestamp;
+    engine.liquidation_insurance_bps = 0;
+    engine.swap_program = Pubkey::default();
+    engine.soft_liquidation_band = 0;
+    engine.soft_liquidation_discount_bps = 0;
+    engine.soft_liquidation_max_bps = 0;
+    engine.critical_collateral_ratio = 0;
+    engine.system_collateral_value = 0;
+    engine.system_debt = 0;
+    engine.recovery_mode = false;
+    engine.totals_cursor = 0;
+    engine.pending_collateral_value = 0;
+    engine.pending_debt = 0;
+    engine.timelock_delay = DEFAULT_TIMELOCK_DELAY;
+    engine.action_count = 0;
+    engine.paused = false;
+    engine.multisig_signers = [Pubkey::default(); MAX_MULTISIG_SIGNERS];
+    engine.multisig_signer_count = 0;
+    engine.multisig_threshold = 0;
+    engine.multisig_nonce = 0;
+    engine.proposal_count = 0;
+    engine.shutdown = false;
+    engine.shutdown_ts = 0;
+    engine.shutdown_debt = 0;
+    engine.psm_debt = 0;
     engine.bump = ctx.bumps.engine;
+
+    let authority = ctx.accounts.authority.key();
+    let roles = &mut ctx.accounts.roles;
+    roles.admin = authority;
+    roles.risk_manager = authority;
+    roles.oracle_updater = authority;
+    roles.guardian = authority;
+    roles.fee_collector = authority;
+    roles.bump = ctx.bumps.roles;
+
+    let mut registry = ctx.accounts.registry.load_init()?;
+    registry.count = 0;
+    registry.bump = ctx.bumps.registry;
+    drop(registry);
+
+    // Token-2022 reallocs the mint for the metadata, so top up rent first.
+    let DscMetadataArgs { name, symbol, uri } = metadata_args;
+    let metadata = TokenMetadata {
+        name: name.clone(),
+        symbol: symbol.clone(),
+        uri: uri.clone(),
+        ..Default::default()
+    };
+    let mint_info = ctx.accounts.dsc_mint.to_account_info();
+    let new_len = mint_info.data_len() + metadata.tlv_size_of()?;
+    let top_up = Rent::get()?
+        .minimum_balance(new_len)
+        .saturating_sub(mint_info.lamports());
+    if top_up > 0 {
+        transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.authority.to_account_info(),
+                    to: mint_info.clone(),
+                },
+            ),
+            top_up,
+        )?;
+    }
+
+    let market = market_id.to_le_bytes();
+    let seeds: &[&[u8]] = &[b"engine", &market, &[ctx.bumps.engine]];
+    let signer = &[seeds];
+
+    token_metadata_initialize(
+        CpiContext::new_with_signer(
+            ctx.accounts.token_program.to_account_info(),
+            TokenMetadataInitialize {
+                program_id: ctx.accounts.token_program.to_account_info(),
+                mint: mint_info.clone(),
+                metadata: mint_info,
+                mint_authority: ctx.accounts.engine.to_account_info(),
+                update_authority: ctx.accounts.engine.to_account_info(),
+            },
+            signer,
+        ),
+        name,
+        symbol,
+        uri,
+    )?;
     Ok(())
 }
-pub fn mint_dsc(mut ctx: &mut Context<MintDSC>, amount: u64, new_price: u64) -> Result<()> {
+pub fn mint_dsc(ctx: &mut Context<MintDSC>, amount: u64) -> Result<()> {
     if amount == 0 {
         return Err(ErrorCode::AmountLessThanZero.into());
     }
+    require_collateral_active(&ctx.accounts.engine, &ctx.accounts.config)?;
+    let actor = ctx.accounts.user.key();
+    let controller = position_controller(
+        &ctx.accounts.user_data,
+        ctx.accounts.position_nft.as_deref(),
+    )?;
+    authorize(
+        actor,
+        controller,
+        ctx.accounts.delegation.as_deref(),
+        DELEGATE_MINT,
+    )?;
+    record_delegate_mint(actor, controller, ctx.accounts.delegation.as_mut(), amount)?;
+    require_keys_eq!(
+        ctx.accounts.user_dsc_account.owner,
+        controller,
+        ErrorCode::NotPositionHolder
+    );
     // let amt = amount.checked_div(1000).unwrap();
 
-    // Update the oracle price
-    let price = &mut ctx.accounts.price;
-    price.price = new_price;
+    require_mint_keeps_system_cr(&ctx.accounts.engine, 0, amount)?;
 
     let user_data = &mut ctx.accounts.user_data;
 
@@ -52,55 +177,81 @@ pub fn mint_dsc(mut ctx: &mut Context<MintDSC>, amount: u64, new_price: u64) ->
         .checked_add(amount)
         .ok_or(ErrorCode::Overflow)?;
 
-    // Calculate health factor with the new debt
-    let health_factor = calculate_health_factor_with_debt(
+    require_healthy_debt(
         &ctx.accounts.deposit,
         &ctx.accounts.price,
         &ctx.accounts.engine,
         new_borrowed,
     )?;
-    msg!("Health factor too low: {}", health_factor);
-    msg!(
-        "Required minimum: {}",
-        ctx.accounts.engine.min_health_factor
-    );
 
-    if health_factor < ctx.accounts.engine.min_health_factor {
+    mint_as_engine(
+        &ctx.accounts.engine,
+        &ctx.accounts.dsc_mint,
+        &ctx.accounts.user_dsc_account,
+        &ctx.accounts.dsc_token_program,
+        amount,
+    )?;
+
+    // Update user data with new debt
+    user_data.borrowed_amount = new_borrowed;
+
+    let collateral = ctx.accounts.deposit.token_amt;
+    sync_position(
+        &ctx.accounts.position_index,
+        &mut ctx.accounts.engine,
+        &ctx.accounts.price,
+        user_data,
+        collateral,
+        new_borrowed,
+    )
+}
+
+/// Fails unless the position stays at or above the minimum health factor
+/// with `debt` outstanding.
+pub fn require_healthy_debt(
+    deposit: &Account<Deposit>,
+    price: &Price,
+    engine: &Account<Engine>,
+    debt: u64,
+) -> Result<()> {
+    let health_factor = calculate_health_factor_with_debt(deposit, price, engine, debt)?;
+
+    if health_factor < engine.min_health_factor {
         msg!("Health factor too low: {}", health_factor);
-        msg!(
-            "Required minimum: {}",
-            ctx.accounts.engine.min_health_factor
-        );
+        msg!("Required minimum: {}", engine.min_health_factor);
         return Err(ErrorCode::LessHealthFactor.into());
     }
+    Ok(())
+}
 
-    // Mint DSC tokens to the user using engine as authority
-    let seeds: &[&[u8]] = &[b"engine", &[ctx.accounts.engine.bump]];
+/// Mints from any mint whose authority is the engine PDA: DSC itself, or a
+/// position NFT before its authority is dropped.
+pub fn mint_as_engine<'info>(
+    engine: &Account<'info, Engine>,
+    mint: &InterfaceAccount<'info, Mint>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    token_program: &Program<'info, Token2022>,
+    amount: u64,
+) -> Result<()> {
+    let market = engine.market_id.to_le_bytes();
+    let seeds: &[&[u8]] = &[b"engine", &market, &[engine.bump]];
     let signer = &[seeds];
 
     let cpi_ctx = CpiContext::new_with_signer(
-        ctx.accounts.token_program.to_account_info(),
+        token_program.to_account_info(),
         MintTo {
-            mint: ctx.accounts.dsc_mint.to_account_info(),
-            to: ctx.accounts.user_dsc_account.to_account_info(),
-            authority: ctx.accounts.engine.to_account_info(),
+            mint: mint.to_account_info(),
+            to: to.to_account_info(),
+            authority: engine.to_account_info(),
         },
         signer,
     );
-    mint_to(cpi_ctx, amount)?;
-
-    // Update user data with new debt
-    user_data.borrowed_amount = new_borrowed;
-
-    Ok(())
+    mint_to(cpi_ctx, amount)
 }
 
-pub fn liquidate(
-    mut ctx: &mut Context<Liquidate>,
-    debt_to_cover: u64,
-    new_price: u64,
-) -> Result<()> {
+pub fn liquidate(mut ctx: &mut Context<Liquidate>, debt_to_cover: u64) -> Result<()> {
     require!(debt_to_cover > 0, ErrorCode::AmountLessThanZero);
+    require!(!ctx.accounts.engine.shutdown, ErrorCode::ShutdownActive);
 
     let engine = &ctx.accounts.engine;
     let user_data = &mut ctx.accounts.user_data;
@@ -123,18 +274,21 @@ pub fn liquidate(
         ErrorCode::LiquidatorInsufficientDSC
     );
 
-    let price = &mut ctx.accounts.price;
-    price.price = new_price;
+    let price = &ctx.accounts.price;
 
+    // The `_with_debt` variant rejects anything below 1, which is exactly the
+    // range liquidations are for.
     let initial_health =
-        calculate_health_factor_with_debt(deposit, price, engine, user_data.borrowed_amount)?;
+        calculate_health_factor(deposit.token_amt, user_data.borrowed_amount, price, engine)?;
+    let collateral_value = convert_collateral_to_usd(deposit.token_amt, price)?;
     require!(
-        initial_health < engine.min_health_factor,
+        initial_health < engine.min_health_factor
+            || below_system_cr(engine, collateral_value, user_data.borrowed_amount),
         ErrorCode::NoNeedToLiquidate
     );
 
     let burn_ctx = CpiContext::new(
-        ctx.accounts.token_program.to_account_info(),
+        ctx.accounts.dsc_token_program.to_account_info(),
         Burn {
             mint: ctx.accounts.dsc_mint.to_account_info(),
             from: ctx.accounts.liquidator_dsc_account.to_account_info(),
@@ -144,16 +298,25 @@ pub fn liquidate(
     burn(burn_ctx, debt_to_cover)?;
 
     let dsc_collateral_equiv = convert_dsc_to_collateral(dsc_amt, price)?;
-    let bonus = (dsc_collateral_equiv * 10) / 100;
-    let total_liquidator_reward = dsc_collateral_equiv + bonus;
+    let bonus = percent_of(dsc_collateral_equiv, engine.liquidation_bonus)?;
+    // Without an insurance fund for the token the liquidator keeps the bonus.
+    let insurance_cut = match (&ctx.accounts.insurance_fund, &ctx.accounts.insurance_vault) {
+        (Some(_), Some(_)) => bps_of(bonus, engine.liquidation_insurance_bps)?,
+        _ => 0,
+    };
+    let total_seized = dsc_collateral_equiv
+        .checked_add(bonus)
+        .ok_or(ErrorCode::Overflow)?;
+    let total_liquidator_reward = total_seized - insurance_cut;
 
     require!(
-        deposit.token_amt >= total_liquidator_reward,
+        deposit.token_amt >= total_seized,
         ErrorCode::NotEnoughTokensInCollateral
     );
 
     let seeds = &[
         b"config",
+        ctx.accounts.config.engine.as_ref(),
         ctx.accounts.token_mint.to_account_info().key.as_ref(),
         &[ctx.accounts.config.bump],
     ];
@@ -180,8 +343,39 @@ pub fn liquidate(
 
     transfer_checked(cpi_context, total_liquidator_reward, decimals)?;
 
-    deposit.token_amt -= total_liquidator_reward;
-    user_data.borrowed_amount = user_data.borrowed_amount.saturating_sub(debt_to_cover);
+    if let (Some(fund), Some(insurance_vault)) = (
+        ctx.accounts.insurance_fund.as_mut(),
+        ctx.accounts.insurance_vault.as_ref(),
+    ) {
+        if insurance_cut > 0 {
+            let cpi_context = CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.vault.to_account_info(),
+                    to: insurance_vault.to_account_info(),
+                    mint: ctx.accounts.token_mint.to_account_info(),
+                    authority: ctx.accounts.config.to_account_info(),
+                },
+                signer_seeds,
+            );
+            transfer_checked(cpi_context, insurance_cut, decimals)?;
+            record_insurance_deposit(fund, insurance_cut)?;
+        }
+    }
+
+    deposit.token_amt -= total_seized;
+    let remaining_debt = user_data.borrowed_amount.saturating_sub(debt_to_cover);
+    user_data.borrowed_amount = remaining_debt;
+
+    let remaining_collateral = deposit.token_amt;
+    sync_position(
+        &ctx.accounts.position_index,
+        &mut ctx.accounts.engine,
+        &ctx.accounts.price,
+        user_data,
+        remaining_collateral,
+        remaining_debt,
+    )?;
 
     emit!(TokenLiquidated {
         liquidator: ctx.accounts.liquidator.key(),
@@ -194,32 +388,70 @@ pub fn liquidate(
 }
 
 #[derive(Accounts)]
+#[instruction(market_id: u64)]
 pub struct InitializeEngine<'info> {
     #[account(
         init,
         payer = authority,
         space = 8 + Engine::INIT_SPACE,
-        seeds = [b"engine"],
+        seeds = [b"engine", market_id.to_le_bytes().as_ref()],
         bump
     )]
     pub engine: Account<'info, Engine>,
 
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + Roles::INIT_SPACE,
+        seeds = [b"roles", engine.key().as_ref()],
+        bump
+    )]
+    pub roles: Account<'info, Roles>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + std::mem::size_of::<CollateralRegistry>(),
+        seeds = [b"registry", engine.key().as_ref()],
+        bump
+    )]
+    pub registry: AccountLoader<'info, CollateralRegistry>,
+
     #[account(mut)]
     pub authority: Signer<'info>,
 
-    #[account(mint::token_program = token_program)]
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"dsc_mint", engine.key().as_ref()],
+        bump,
+        mint::decimals = DSC_DECIMALS,
+        mint::authority = engine,
+        mint::freeze_authority = engine,
+        mint::token_program = token_program,
+        extensions::metadata_pointer::authority = engine,
+        extensions::metadata_pointer::metadata_address = dsc_mint,
+    )]
     pub dsc_mint: InterfaceAccount<'info, Mint>,
 
     pub system_program: Program<'info, System>,
-    pub token_program: Interface<'info, TokenInterface>,
+    pub token_program: Program<'info, Token2022>,
 }
 
 #[derive(Accounts)]
 pub struct MintDSC<'info> {
-    #[account(seeds = [b"engine"], bump = engine.bump)]
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
     pub engine: Account<'info, Engine>,
 
-    #[account(mut, seeds = [b"user", user.key().as_ref(),token_mint.key().as_ref()], bump = user_data.bump)]
+    #[account(
+        mut,
+        seeds = [b"user", engine.key().as_ref(), owner.key().as_ref(),token_mint.key().as_ref()],
+        bump = user_data.bump
+    )]
     pub user_data: Account<'info, UserData>,
 
     #[account(mint::token_program=token_program)]
@@ -228,55 +460,86 @@ pub struct MintDSC<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
+    /// CHECK: the key the position was opened under; only used as a seed.
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"delegation", engine.key().as_ref(), user_data.key().as_ref()],
+        bump = delegation.bump
+    )]
+    pub delegation: Option<Account<'info, Delegation>>,
+
+    pub position_nft: Option<InterfaceAccount<'info, TokenAccount>>,
+
     #[account(mut, address = engine.dsc_mint)]
     pub dsc_mint: InterfaceAccount<'info, Mint>,
 
     #[account(
-        seeds = [b"deposit", user.key().as_ref(), token_mint.key().as_ref()],
+        seeds = [b"deposit", engine.key().as_ref(), owner.key().as_ref(), token_mint.key().as_ref()],
         bump = deposit.bump,
     )]
     pub deposit: Account<'info, Deposit>,
 
     #[account(
         has_one = token_mint,
-        seeds = [b"config", token_mint.key().as_ref()],
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
         bump = config.bump
     )]
     pub config: Account<'info, Config>,
 
     // pub price_update: Account<'info, PriceUpdateV2>,
     #[account(
-        mut,
-        seeds = [b"price", token_mint.key().as_ref()],
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
         bump = price.bump,
     )]
     pub price: Account<'info, Price>,
 
     #[account(
-        init_if_needed,
-        payer=user,
-        associated_token::mint=dsc_mint,
-        associated_token::authority=user,
-        associated_token::token_program=token_program
+        mut,
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = position_index.load()?.bump,
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+
+    /// DSC goes to whoever controls the position, checked in the handler.
+    #[account(
+        mut,
+        token::mint = dsc_mint,
+        token::token_program = dsc_token_program
     )]
     pub user_dsc_account: InterfaceAccount<'info, TokenAccount>,
 
     pub system_program: Program<'info, System>,
     pub token_program: Interface<'info, TokenInterface>,
+    pub dsc_token_program: Program<'info, Token2022>,
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
 
 #[derive(Accounts)]
 pub struct Liquidate<'info> {
-    #[account(seeds = [b"engine"], bump = engine.bump)]
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
     pub engine: Account<'info, Engine>,
 
-    #[account(mut,seeds = [b"user", user_data.user.as_ref(),token_mint.key().as_ref()], bump = user_data.bump)]
+    #[account(
+        mut,
+        seeds = [b"user", engine.key().as_ref(), user_data.user.as_ref(),token_mint.key().as_ref()],
+        bump = user_data.bump
+    )]
     pub user_data: Account<'info, UserData>,
 
     #[account(
         mut,
-        seeds = [b"deposit", user_data.user.as_ref(), token_mint.key().as_ref()],
+        seeds = [
+            b"deposit",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
         bump = deposit.bump
     )]
     pub deposit: Account<'info, Deposit>,
@@ -288,11 +551,18 @@ pub struct Liquidate<'info> {
     pub token_mint: InterfaceAccount<'info, Mint>,
 
     #[account(
-        seeds = [b"config", token_mint.key().as_ref()],
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
         bump = config.bump
     )]
     pub config: Account<'info, Config>,
 
+    #[account(
+        mut,
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = position_index.load()?.bump,
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+
     #[account(
         mut,
         associated_token::mint = token_mint,
@@ -301,6 +571,22 @@ pub struct Liquidate<'info> {
     )]
     pub vault: InterfaceAccount<'info, TokenAccount>,
 
+    #[account(
+        mut,
+        has_one = token_mint,
+        seeds = [b"insurance", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = insurance_fund.bump
+    )]
+    pub insurance_fund: Option<Account<'info, InsuranceFund>>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = insurance_fund,
+        associated_token::token_program = token_program
+    )]
+    pub insurance_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
     #[account(
         init_if_needed,
         payer = liquidator,
@@ -317,18 +603,18 @@ pub struct Liquidate<'info> {
         mut,
         associated_token::mint = dsc_mint,
         associated_token::authority = liquidator,
-        associated_token::token_program = token_program
+        associated_token::token_program = dsc_token_program
     )]
     pub liquidator_dsc_account: InterfaceAccount<'info, TokenAccount>,
 
     #[account(
-        mut,
-        seeds = [b"price", token_mint.key().as_ref()],
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
         bump = price.bump,
     )]
     pub price: Account<'info, Price>,
 
     pub system_program: Program<'info, System>,
     pub token_program: Interface<'info, TokenInterface>,
+    pub dsc_token_program: Program<'info, Token2022>,
     pub associated_token_program: Program<'info, AssociatedToken>,
 }
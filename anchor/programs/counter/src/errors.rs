@@ -29,4 +29,106 @@ pub enum ErrorCode {
     TooMuchRepay,
     OverCollateralLimit,
     LiquidatorInsufficientDSC,
+    #[msg("Flash mint amount exceeds the per-call cap")]
+    FlashMintCapExceeded,
+    #[msg("Callback program is not allowed")]
+    InvalidCallbackProgram,
+    #[msg("Basis points value out of range")]
+    InvalidBps,
+    #[msg("PSM cap exceeded for this asset")]
+    PsmCapExceeded,
+    #[msg("Not enough PSM reserves")]
+    PsmInsufficientReserves,
+    #[msg("Position accounts do not match the redemption order")]
+    InvalidRedemptionAccounts,
+    #[msg("Redemption fee exceeds the accepted maximum")]
+    RedemptionFeeTooHigh,
+    #[msg("Not enough savings shares")]
+    InsufficientShares,
+    #[msg("Fee split must add up to 100%")]
+    InvalidFeeSplit,
+    #[msg("No protocol fees to collect")]
+    NothingToCollect,
+    #[msg("Position has no bad debt")]
+    NoBadDebt,
+    #[msg("Not enough funds in the insurance fund")]
+    InsufficientInsuranceFunds,
+    #[msg("Timelock delay has not elapsed")]
+    TimelockNotElapsed,
+    #[msg("Timelock delay out of range")]
+    InvalidTimelockDelay,
+    #[msg("Governance action needs the matching token config")]
+    InvalidGovernanceAccounts,
+    #[msg("Collateral is not active")]
+    CollateralNotActive,
+    #[msg("Protocol is paused")]
+    ProtocolPaused,
+    #[msg("This action needs multisig approval")]
+    MultisigRequired,
+    #[msg("No multisig signer set is configured")]
+    MultisigDisabled,
+    #[msg("Signer is not part of the multisig")]
+    NotMultisigSigner,
+    #[msg("Signer already approved this proposal")]
+    AlreadyApproved,
+    #[msg("Invalid multisig signers or threshold")]
+    InvalidSignerSet,
+    #[msg("Not enough approvals")]
+    ThresholdNotMet,
+    #[msg("Signer set changed since the proposal was made")]
+    StaleProposal,
+    #[msg("Proposal needs the matching accounts")]
+    InvalidMultisigAccounts,
+    #[msg("Protocol has been shut down")]
+    ShutdownActive,
+    #[msg("Protocol is not shut down")]
+    NotShutdown,
+    #[msg("Settlement wait period has not ended")]
+    SettlementWaitActive,
+    #[msg("Position still has unsettled debt")]
+    PositionNotSettled,
+    #[msg("No packed DSC left to cash for this token")]
+    NothingToCash,
+    #[msg("Collateral registry is full")]
+    RegistryFull,
+    #[msg("Invalid collateral status change")]
+    InvalidCollateralStatus,
+    #[msg("Repayment exceeds the outstanding debt")]
+    RepayExceedsDebt,
+    #[msg("Swap program is not the configured one")]
+    InvalidSwapProgram,
+    #[msg("Leverage target is out of range")]
+    InvalidLeverageTarget,
+    #[msg("Swap returned less than the minimum")]
+    SlippageExceeded,
+    #[msg("Delegate lacks the permission for this action")]
+    DelegateNotAllowed,
+    #[msg("Delegate mint limit reached")]
+    DelegateMintLimit,
+    #[msg("Position is already tokenized")]
+    PositionAlreadyTokenized,
+    #[msg("Signer does not hold the position")]
+    NotPositionHolder,
+    #[msg("Invalid order")]
+    InvalidOrder,
+    #[msg("Order has already been executed")]
+    OrderInactive,
+    #[msg("Health factor is above the order trigger")]
+    OrderNotTriggered,
+    #[msg("Soft liquidation is not enabled")]
+    SoftLiquidationDisabled,
+    #[msg("Health factor is outside the soft liquidation band")]
+    NotInSoftLiquidationBand,
+    #[msg("Repayment exceeds the soft liquidation cap")]
+    SoftLiquidationCapExceeded,
+    #[msg("Invalid soft liquidation parameters")]
+    InvalidSoftLiquidationParams,
+    #[msg("Accounts must be the position index and price of every listed token, in order")]
+    InvalidSystemTotalsAccounts,
+    #[msg("Minting would lower the system collateral ratio during recovery mode")]
+    RecoveryModeMint,
+    #[msg("Critical collateral ratio must be zero or at least 100%")]
+    InvalidCriticalRatio,
+    #[msg("Stored price is too old")]
+    StalePrice,
 }
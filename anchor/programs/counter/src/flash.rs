@@ -0,0 +1,132 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program::invoke,
+};
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{burn, mint_to, Burn, Mint, MintTo, TokenAccount, TokenInterface},
+};
+
+/// Mints `amount` DSC to the receiver, hands control to `callback_program`
+/// and burns `amount + fee` from the receiver before returning.
+pub fn flash_mint<'info>(
+    ctx: Context<'_, '_, '_, 'info, FlashMintDSC<'info>>,
+    amount: u64,
+    data: Vec<u8>,
+) -> Result<()> {
+    require!(amount > 0, ErrorCode::AmountLessThanZero);
+
+    let engine = &ctx.accounts.engine;
+    require!(!engine.paused, ErrorCode::ProtocolPaused);
+    require!(!engine.shutdown, ErrorCode::ShutdownActive);
+    require!(
+        amount <= engine.flash_mint_cap,
+        ErrorCode::FlashMintCapExceeded
+    );
+
+    let callback_program = &ctx.accounts.callback_program;
+    require_keys_neq!(
+        callback_program.key(),
+        crate::ID,
+        ErrorCode::InvalidCallbackProgram
+    );
+
+    // Round the fee up so small loans cannot be taken for free.
+    let fee = (amount as u128)
+        .checked_mul(engine.flash_mint_fee_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_add(BPS_DENOMINATOR as u128 - 1)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(BPS_DENOMINATOR as u128)
+        .ok_or(ErrorCode::DivisionError)? as u64;
+    let repay_amount = amount.checked_add(fee).ok_or(ErrorCode::Overflow)?;
+
+    let market = engine.market_id.to_le_bytes();
+    let seeds: &[&[u8]] = &[b"engine", &market, &[engine.bump]];
+    let signer = &[seeds];
+
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        MintTo {
+            mint: ctx.accounts.dsc_mint.to_account_info(),
+            to: ctx.accounts.receiver_dsc_account.to_account_info(),
+            authority: ctx.accounts.engine.to_account_info(),
+        },
+        signer,
+    );
+    mint_to(cpi_ctx, amount)?;
+
+    // The callback gets every remaining account with the privileges it was
+    // passed in with, so the receiver's signature carries through.
+    let metas = ctx
+        .remaining_accounts
+        .iter()
+        .map(|acc| AccountMeta {
+            pubkey: acc.key(),
+            is_signer: acc.is_signer,
+            is_writable: acc.is_writable,
+        })
+        .collect();
+    let ix = Instruction {
+        program_id: callback_program.key(),
+        accounts: metas,
+        data,
+    };
+    let mut infos = ctx.remaining_accounts.to_vec();
+    infos.push(callback_program.to_account_info());
+    invoke(&ix, &infos)?;
+
+    // Fails if the receiver no longer holds the loan plus fee.
+    let burn_ctx = CpiContext::new(
+        ctx.accounts.token_program.to_account_info(),
+        Burn {
+            mint: ctx.accounts.dsc_mint.to_account_info(),
+            from: ctx.accounts.receiver_dsc_account.to_account_info(),
+            authority: ctx.accounts.receiver.to_account_info(),
+        },
+    );
+    burn(burn_ctx, repay_amount)?;
+
+    emit!(FlashMinted {
+        receiver: ctx.accounts.receiver.key(),
+        callback_program: callback_program.key(),
+        amount,
+        fee,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct FlashMintDSC<'info> {
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mut)]
+    pub receiver: Signer<'info>,
+
+    #[account(mut, address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init_if_needed,
+        payer = receiver,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = receiver,
+        associated_token::token_program = token_program
+    )]
+    pub receiver_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: arbitrary program chosen by the receiver; only invoked.
+    #[account(executable)]
+    pub callback_program: UncheckedAccount<'info>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
@@ -0,0 +1,495 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::insurance::withdraw_insurance;
+use crate::recovery::is_recovery_mode;
+use crate::registry::set_collateral_status;
+use crate::savings::drip;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub enum GovernanceAction {
+    SetEngineParams {
+        liquidation_threshold: u64,
+        min_health_factor: u64,
+        liquidation_bonus: u64,
+        fee_percent: u64,
+    },
+    SetCollateralStatus {
+        token_mint: Pubkey,
+        status: u8,
+    },
+    SetOracle {
+        token_mint: Pubkey,
+        oracle: Pubkey,
+    },
+    SetRole {
+        role: Role,
+        holder: Pubkey,
+    },
+    SetTimelockDelay {
+        delay: i64,
+    },
+    SetSwapProgram {
+        program: Pubkey,
+    },
+    SetSoftLiquidation {
+        band: u64,
+        discount_bps: u64,
+        max_repay_bps: u64,
+    },
+    SetCriticalCollateralRatio {
+        ratio_bps: u64,
+    },
+    SetFlashMint {
+        cap: u64,
+        fee_bps: u64,
+    },
+    SetLiquidationInsurance {
+        bps: u64,
+    },
+    SetFeeSplit {
+        token_mint: Pubkey,
+        lp_fee_bps: u64,
+        treasury_fee_bps: u64,
+        insurance_fee_bps: u64,
+        savings_fee_bps: u64,
+    },
+    SetPsmAsset {
+        stable_mint: Pubkey,
+        fee_in_bps: u64,
+        fee_out_bps: u64,
+        cap: u64,
+    },
+    SetSavingsRate {
+        rate_bps: u64,
+    },
+    WithdrawInsurance {
+        token_mint: Pubkey,
+        amount: u64,
+        destination: Pubkey,
+    },
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, InitSpace)]
+pub enum Role {
+    Admin,
+    RiskManager,
+    OracleUpdater,
+    Guardian,
+    FeeCollector,
+}
+
+pub fn queue_action(ctx: Context<QueueAction>, action: GovernanceAction) -> Result<()> {
+    require_keys_eq!(
+        ctx.accounts.proposer.key(),
+        proposer_role(&ctx.accounts.roles, &action),
+        ErrorCode::UnauthorizedUser
+    );
+    check_multisig_bypass(&ctx.accounts.engine, &action)?;
+    validate_action(&action)?;
+
+    let engine = &mut ctx.accounts.engine;
+    let eta = Clock::get()?
+        .unix_timestamp
+        .checked_add(engine.timelock_delay)
+        .ok_or(ErrorCode::Overflow)?;
+
+    let pending = &mut ctx.accounts.pending_action;
+    pending.id = engine.action_count;
+    pending.proposer = ctx.accounts.proposer.key();
+    pending.action = action.clone();
+    pending.eta = eta;
+    pending.bump = ctx.bumps.pending_action;
+
+    engine.action_count = engine
+        .action_count
+        .checked_add(1)
+        .ok_or(ErrorCode::Overflow)?;
+
+    emit!(ActionQueued {
+        id: pending.id,
+        action,
+        eta,
+    });
+
+    Ok(())
+}
+
+/// The role that may queue `action`: risk parameters belong to the risk
+/// manager, everything else to the admin.
+fn proposer_role(roles: &Roles, action: &GovernanceAction) -> Pubkey {
+    match action {
+        GovernanceAction::SetEngineParams { .. }
+        | GovernanceAction::SetCollateralStatus { .. }
+        | GovernanceAction::SetSoftLiquidation { .. }
+        | GovernanceAction::SetCriticalCollateralRatio { .. }
+        | GovernanceAction::SetLiquidationInsurance { .. } => roles.risk_manager,
+        _ => roles.admin,
+    }
+}
+
+/// Applies a queued action once its delay has passed. Permissionless; token
+/// scoped actions need that token's config, and insurance withdrawals the
+/// fund, its vault, the destination, the mint and its token program.
+pub fn execute_action(ctx: Context<ExecuteAction>) -> Result<()> {
+    let pending = &ctx.accounts.pending_action;
+    require!(
+        Clock::get()?.unix_timestamp >= pending.eta,
+        ErrorCode::TimelockNotElapsed
+    );
+
+    check_multisig_bypass(&ctx.accounts.engine, &pending.action)?;
+
+    let engine = &mut ctx.accounts.engine;
+    match pending.action.clone() {
+        GovernanceAction::SetEngineParams {
+            liquidation_threshold,
+            min_health_factor,
+            liquidation_bonus,
+            fee_percent,
+        } => {
+            engine.liquidation_threshold = liquidation_threshold;
+            engine.min_health_factor = min_health_factor;
+            engine.liquidation_bonus = liquidation_bonus;
+            engine.fee_percent = fee_percent;
+        }
+        GovernanceAction::SetCollateralStatus { token_mint, status } => {
+            let config = token_config(&mut ctx.accounts.config, token_mint)?;
+            let registry = ctx
+                .accounts
+                .registry
+                .as_ref()
+                .ok_or(ErrorCode::InvalidGovernanceAccounts)?;
+            set_collateral_status(config, registry, status)?;
+        }
+        GovernanceAction::SetOracle { token_mint, oracle } => {
+            let config = token_config(&mut ctx.accounts.config, token_mint)?;
+            config.oracle = oracle;
+        }
+        GovernanceAction::SetRole { role, holder } => {
+            let roles = &mut ctx.accounts.roles;
+            match role {
+                Role::Admin => roles.admin = holder,
+                Role::RiskManager => roles.risk_manager = holder,
+                Role::OracleUpdater => roles.oracle_updater = holder,
+                Role::Guardian => roles.guardian = holder,
+                Role::FeeCollector => roles.fee_collector = holder,
+            }
+        }
+        GovernanceAction::SetTimelockDelay { delay } => {
+            engine.timelock_delay = delay;
+        }
+        GovernanceAction::SetSwapProgram { program } => {
+            engine.swap_program = program;
+        }
+        GovernanceAction::SetSoftLiquidation {
+            band,
+            discount_bps,
+            max_repay_bps,
+        } => {
+            engine.soft_liquidation_band = band;
+            engine.soft_liquidation_discount_bps = discount_bps;
+            engine.soft_liquidation_max_bps = max_repay_bps;
+        }
+        GovernanceAction::SetCriticalCollateralRatio { ratio_bps } => {
+            engine.critical_collateral_ratio = ratio_bps;
+            engine.recovery_mode = is_recovery_mode(engine);
+        }
+        GovernanceAction::SetFlashMint { cap, fee_bps } => {
+            engine.flash_mint_cap = cap;
+            engine.flash_mint_fee_bps = fee_bps;
+        }
+        GovernanceAction::SetLiquidationInsurance { bps } => {
+            engine.liquidation_insurance_bps = bps;
+        }
+        GovernanceAction::SetFeeSplit {
+            token_mint,
+            lp_fee_bps,
+            treasury_fee_bps,
+            insurance_fee_bps,
+            savings_fee_bps,
+        } => {
+            let config = token_config(&mut ctx.accounts.config, token_mint)?;
+            config.lp_fee_bps = lp_fee_bps;
+            config.treasury_fee_bps = treasury_fee_bps;
+            config.insurance_fee_bps = insurance_fee_bps;
+            config.savings_fee_bps = savings_fee_bps;
+        }
+        GovernanceAction::SetPsmAsset {
+            stable_mint,
+            fee_in_bps,
+            fee_out_bps,
+            cap,
+        } => {
+            let psm_asset = match &mut ctx.accounts.psm_asset {
+                Some(psm_asset) if psm_asset.stable_mint == stable_mint => psm_asset,
+                _ => return err!(ErrorCode::InvalidGovernanceAccounts),
+            };
+            psm_asset.fee_in_bps = fee_in_bps;
+            psm_asset.fee_out_bps = fee_out_bps;
+            psm_asset.cap = cap;
+        }
+        GovernanceAction::SetSavingsRate { rate_bps } => {
+            let savings = ctx
+                .accounts
+                .savings
+                .as_mut()
+                .ok_or(ErrorCode::InvalidGovernanceAccounts)?;
+            // Accrue at the old rate up to now before switching.
+            drip(savings, Clock::get()?.unix_timestamp)?;
+            savings.rate_bps = rate_bps;
+        }
+        GovernanceAction::WithdrawInsurance {
+            token_mint,
+            amount,
+            destination,
+        } => {
+            let (Some(fund), Some(vault), Some(to), Some(mint), Some(token_program)) = (
+                ctx.accounts.insurance_fund.as_mut(),
+                ctx.accounts.insurance_vault.as_ref(),
+                ctx.accounts.destination.as_ref(),
+                ctx.accounts.token_mint.as_ref(),
+                ctx.accounts.token_program.as_ref(),
+            ) else {
+                return err!(ErrorCode::InvalidGovernanceAccounts);
+            };
+            require!(
+                fund.token_mint == token_mint
+                    && mint.key() == token_mint
+                    && vault.key() == fund.vault
+                    && to.key() == destination,
+                ErrorCode::InvalidGovernanceAccounts
+            );
+            withdraw_insurance(fund, vault, to, mint, token_program, amount)?;
+        }
+    }
+
+    emit!(ActionExecuted {
+        id: pending.id,
+        executor: ctx.accounts.executor.key(),
+    });
+
+    Ok(())
+}
+
+pub fn cancel_action(ctx: Context<CancelAction>) -> Result<()> {
+    emit!(ActionCancelled {
+        id: ctx.accounts.pending_action.id,
+        guardian: ctx.accounts.guardian.key(),
+    });
+    Ok(())
+}
+
+/// Rejects parameters that could never be applied, before the action waits
+/// out its delay.
+fn validate_action(action: &GovernanceAction) -> Result<()> {
+    match *action {
+        GovernanceAction::SetTimelockDelay { delay } => require!(
+            (0..=MAX_TIMELOCK_DELAY).contains(&delay),
+            ErrorCode::InvalidTimelockDelay
+        ),
+        GovernanceAction::SetSoftLiquidation {
+            discount_bps,
+            max_repay_bps,
+            ..
+        } => require!(
+            discount_bps < BPS_DENOMINATOR && max_repay_bps <= BPS_DENOMINATOR,
+            ErrorCode::InvalidSoftLiquidationParams
+        ),
+        GovernanceAction::SetCriticalCollateralRatio { ratio_bps } => require!(
+            ratio_bps == 0 || ratio_bps >= BPS_DENOMINATOR,
+            ErrorCode::InvalidCriticalRatio
+        ),
+        GovernanceAction::SetFlashMint { fee_bps: bps, .. }
+        | GovernanceAction::SetLiquidationInsurance { bps }
+        | GovernanceAction::SetSavingsRate { rate_bps: bps } => {
+            require!(bps <= BPS_DENOMINATOR, ErrorCode::InvalidBps)
+        }
+        GovernanceAction::SetFeeSplit {
+            lp_fee_bps,
+            treasury_fee_bps,
+            insurance_fee_bps,
+            savings_fee_bps,
+            ..
+        } => {
+            let total = lp_fee_bps
+                .checked_add(treasury_fee_bps)
+                .and_then(|t| t.checked_add(insurance_fee_bps))
+                .and_then(|t| t.checked_add(savings_fee_bps))
+                .ok_or(ErrorCode::Overflow)?;
+            require!(total == BPS_DENOMINATOR, ErrorCode::InvalidFeeSplit);
+        }
+        GovernanceAction::SetPsmAsset {
+            fee_in_bps,
+            fee_out_bps,
+            ..
+        } => require!(
+            fee_in_bps <= BPS_DENOMINATOR && fee_out_bps <= BPS_DENOMINATOR,
+            ErrorCode::InvalidBps
+        ),
+        GovernanceAction::WithdrawInsurance { amount, .. } => {
+            require!(amount > 0, ErrorCode::AmountLessThanZero)
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Handing over the admin role is multisig-only once a signer set exists.
+fn check_multisig_bypass(engine: &Engine, action: &GovernanceAction) -> Result<()> {
+    if let GovernanceAction::SetRole {
+        role: Role::Admin, ..
+    } = action
+    {
+        require!(engine.multisig_threshold == 0, ErrorCode::MultisigRequired);
+    }
+    Ok(())
+}
+
+fn token_config<'a, 'info>(
+    config: &'a mut Option<Account<'info, Config>>,
+    token_mint: Pubkey,
+) -> Result<&'a mut Account<'info, Config>> {
+    match config {
+        Some(config) if config.token_mint == token_mint => Ok(config),
+        _ => Err(ErrorCode::InvalidGovernanceAccounts.into()),
+    }
+}
+
+#[derive(Accounts)]
+pub struct QueueAction<'info> {
+    /// Which role may queue is checked per action in the handler.
+    #[account(seeds = [b"roles", engine.key().as_ref()], bump = roles.bump)]
+    pub roles: Account<'info, Roles>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = 8 + PendingAction::INIT_SPACE,
+        seeds = [
+            b"pending_action",
+            engine.key().as_ref(),
+            engine.action_count.to_le_bytes().as_ref(),
+        ],
+        bump
+    )]
+    pub pending_action: Account<'info, PendingAction>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteAction<'info> {
+    pub executor: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mut, seeds = [b"roles", engine.key().as_ref()], bump = roles.bump)]
+    pub roles: Account<'info, Roles>,
+
+    #[account(
+        mut,
+        has_one = proposer,
+        close = proposer,
+        seeds = [
+            b"pending_action",
+            engine.key().as_ref(),
+            pending_action.id.to_le_bytes().as_ref(),
+        ],
+        bump = pending_action.bump
+    )]
+    pub pending_action: Account<'info, PendingAction>,
+
+    /// CHECK: only receives the rent of the closed action.
+    #[account(mut)]
+    pub proposer: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config", engine.key().as_ref(), config.token_mint.as_ref()],
+        bump = config.bump
+    )]
+    pub config: Option<Account<'info, Config>>,
+
+    #[account(mut, seeds = [b"registry", engine.key().as_ref()], bump = registry.load()?.bump)]
+    pub registry: Option<AccountLoader<'info, CollateralRegistry>>,
+
+    #[account(
+        mut,
+        seeds = [b"psm", engine.key().as_ref(), psm_asset.stable_mint.as_ref()],
+        bump = psm_asset.bump
+    )]
+    pub psm_asset: Option<Account<'info, PsmAsset>>,
+
+    #[account(mut, seeds = [b"savings", engine.key().as_ref()], bump = savings.bump)]
+    pub savings: Option<Account<'info, DscSavings>>,
+
+    #[account(
+        mut,
+        seeds = [b"insurance", engine.key().as_ref(), insurance_fund.token_mint.as_ref()],
+        bump = insurance_fund.bump
+    )]
+    pub insurance_fund: Option<Account<'info, InsuranceFund>>,
+
+    #[account(mut)]
+    pub insurance_vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub destination: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
+
+#[derive(Accounts)]
+pub struct CancelAction<'info> {
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(
+        seeds = [b"roles", engine.key().as_ref()],
+        bump = roles.bump,
+        has_one = guardian @ ErrorCode::UnauthorizedUser
+    )]
+    pub roles: Account<'info, Roles>,
+
+    pub guardian: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = proposer,
+        close = proposer,
+        seeds = [
+            b"pending_action",
+            engine.key().as_ref(),
+            pending_action.id.to_le_bytes().as_ref(),
+        ],
+        bump = pending_action.bump
+    )]
+    pub pending_action: Account<'info, PendingAction>,
+
+    /// CHECK: only receives the rent of the closed action.
+    #[account(mut)]
+    pub proposer: UncheckedAccount<'info>,
+}
@@ -0,0 +1,424 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::pricefeeds::*;
+use crate::redemption::sync_position;
+use crate::repay::burn_debt;
+use crate::shared::{percent_of, send_from_vault};
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_2022::Token2022,
+    token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+pub fn init_insurance_fund(ctx: Context<InitInsuranceFund>) -> Result<()> {
+    let fund = &mut ctx.accounts.insurance_fund;
+    fund.engine = ctx.accounts.engine.key();
+    fund.token_mint = ctx.accounts.token_mint.key();
+    fund.vault = ctx.accounts.insurance_vault.key();
+    fund.balance = 0;
+    fund.bump = ctx.bumps.insurance_fund;
+    Ok(())
+}
+
+/// Moves the insurance share of collected fees out of the collateral vault
+/// into the insurance fund. Permissionless.
+pub fn sweep_insurance_fees(ctx: Context<SweepInsuranceFees>) -> Result<()> {
+    let amount = ctx.accounts.config.insurance_collected;
+    require!(amount > 0, ErrorCode::NothingToCollect);
+
+    let binding = ctx.accounts.token_mint.key();
+    let seeds = &[
+        b"config",
+        ctx.accounts.config.engine.as_ref(),
+        binding.as_ref(),
+        &[ctx.accounts.config.bump],
+    ];
+    let signer_seeds = &[&seeds[..]];
+
+    let transfer_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.insurance_vault.to_account_info(),
+            mint: ctx.accounts.token_mint.to_account_info(),
+            authority: ctx.accounts.config.to_account_info(),
+        },
+        signer_seeds,
+    );
+    transfer_checked(transfer_ctx, amount, ctx.accounts.token_mint.decimals)?;
+
+    ctx.accounts.config.insurance_collected = 0;
+    record_insurance_deposit(&mut ctx.accounts.insurance_fund, amount)
+}
+
+/// Closes out an underwater position. The caller repays its whole debt and
+/// receives its collateral plus the liquidation bonus, with the insurance
+/// fund paying whatever the collateral cannot cover. Only runs on a fresh
+/// oracle price.
+pub fn cover_bad_debt(ctx: Context<CoverBadDebt>) -> Result<()> {
+    require!(!ctx.accounts.engine.shutdown, ErrorCode::ShutdownActive);
+
+    let price = &ctx.accounts.price;
+    let age = Clock::get()?
+        .unix_timestamp
+        .saturating_sub(price.updated_at);
+    require!(age <= MAXIMUM_AGE as i64, ErrorCode::StalePrice);
+
+    let debt = ctx.accounts.user_data.borrowed_amount;
+    let collateral = ctx.accounts.deposit.token_amt;
+    require!(
+        convert_collateral_to_usd(collateral, price)? < debt,
+        ErrorCode::NoBadDebt
+    );
+
+    let debt_in_collateral = convert_dsc_to_collateral(debt, price)?;
+    let owed = debt_in_collateral
+        .checked_add(percent_of(
+            debt_in_collateral,
+            ctx.accounts.engine.liquidation_bonus,
+        )?)
+        .ok_or(ErrorCode::Overflow)?;
+    let shortfall = owed.saturating_sub(collateral);
+    require!(
+        shortfall <= ctx.accounts.insurance_fund.balance,
+        ErrorCode::InsufficientInsuranceFunds
+    );
+
+    burn_debt(
+        &mut ctx.accounts.user_data,
+        &ctx.accounts.dsc_mint,
+        &ctx.accounts.liquidator_dsc_account,
+        &ctx.accounts.liquidator.to_account_info(),
+        &[],
+        &ctx.accounts.dsc_token_program,
+        ctx.accounts.token_mint.key(),
+        debt,
+    )?;
+
+    if collateral > 0 {
+        send_from_vault(
+            &ctx.accounts.config,
+            &ctx.accounts.vault,
+            &ctx.accounts.liquidator_token_account,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.token_program,
+            collateral,
+        )?;
+    }
+    if shortfall > 0 {
+        send_from_insurance(
+            &ctx.accounts.insurance_fund,
+            &ctx.accounts.insurance_vault,
+            &ctx.accounts.liquidator_token_account,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.token_program,
+            shortfall,
+        )?;
+    }
+
+    ctx.accounts.deposit.token_amt = 0;
+    let user_data = &mut ctx.accounts.user_data;
+    user_data.token_balance = user_data.token_balance.saturating_sub(collateral);
+    sync_position(
+        &ctx.accounts.position_index,
+        &mut ctx.accounts.engine,
+        &ctx.accounts.price,
+        user_data,
+        0,
+        0,
+    )?;
+
+    let fund = &mut ctx.accounts.insurance_fund;
+    fund.balance -= shortfall;
+
+    emit!(InsuranceFundWithdrawn {
+        token: fund.token_mint,
+        destination: ctx.accounts.liquidator_token_account.key(),
+        amount: shortfall,
+        balance: fund.balance,
+        bad_debt: true,
+    });
+
+    Ok(())
+}
+
+/// Pays `amount` out of the insurance fund to `destination`. Only reachable
+/// through a timelocked governance action.
+pub fn withdraw_insurance<'info>(
+    fund: &mut Account<'info, InsuranceFund>,
+    insurance_vault: &InterfaceAccount<'info, TokenAccount>,
+    destination: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<()> {
+    require!(
+        amount <= fund.balance,
+        ErrorCode::InsufficientInsuranceFunds
+    );
+
+    send_from_insurance(
+        fund,
+        insurance_vault,
+        destination,
+        mint,
+        token_program,
+        amount,
+    )?;
+    fund.balance -= amount;
+
+    emit!(InsuranceFundWithdrawn {
+        token: fund.token_mint,
+        destination: destination.key(),
+        amount,
+        balance: fund.balance,
+        bad_debt: false,
+    });
+
+    Ok(())
+}
+
+pub fn record_insurance_deposit(fund: &mut InsuranceFund, amount: u64) -> Result<()> {
+    fund.balance = fund
+        .balance
+        .checked_add(amount)
+        .ok_or(ErrorCode::Overflow)?;
+
+    emit!(InsuranceFundDeposited {
+        token: fund.token_mint,
+        amount,
+        balance: fund.balance,
+    });
+    Ok(())
+}
+
+fn send_from_insurance<'info>(
+    fund: &Account<'info, InsuranceFund>,
+    from: &InterfaceAccount<'info, TokenAccount>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<()> {
+    let seeds = &[
+        b"insurance",
+        fund.engine.as_ref(),
+        fund.token_mint.as_ref(),
+        &[fund.bump],
+    ];
+    let signer_seeds = &[&seeds[..]];
+
+    let transfer_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        TransferChecked {
+            from: from.to_account_info(),
+            to: to.to_account_info(),
+            mint: mint.to_account_info(),
+            authority: fund.to_account_info(),
+        },
+        signer_seeds,
+    );
+    transfer_checked(transfer_ctx, amount, mint.decimals)
+}
+
+#[derive(Accounts)]
+pub struct InitInsuranceFund<'info> {
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(
+        seeds = [b"roles", engine.key().as_ref()],
+        bump = roles.bump,
+        has_one = admin @ ErrorCode::UnauthorizedUser
+    )]
+    pub roles: Account<'info, Roles>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + InsuranceFund::INIT_SPACE,
+        seeds = [b"insurance", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        init,
+        payer = admin,
+        associated_token::mint = token_mint,
+        associated_token::authority = insurance_fund,
+        associated_token::token_program = token_program
+    )]
+    pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct SweepInsuranceFees<'info> {
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        has_one = token_mint,
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = config,
+        associated_token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        has_one = token_mint,
+        seeds = [b"insurance", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = insurance_fund.bump
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = insurance_fund,
+        associated_token::token_program = token_program
+    )]
+    pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CoverBadDebt<'info> {
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"deposit",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = deposit.bump
+    )]
+    pub deposit: Account<'info, Deposit>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump
+    )]
+    pub price: Account<'info, Price>,
+
+    #[account(
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = position_index.load()?.bump,
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = config,
+        associated_token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        has_one = token_mint,
+        seeds = [b"insurance", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = insurance_fund.bump
+    )]
+    pub insurance_fund: Account<'info, InsuranceFund>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = insurance_fund,
+        associated_token::token_program = token_program
+    )]
+    pub insurance_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = liquidator,
+        associated_token::mint = token_mint,
+        associated_token::authority = liquidator,
+        associated_token::token_program = token_program
+    )]
+    pub liquidator_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut, address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = liquidator,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub liquidator_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub dsc_token_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
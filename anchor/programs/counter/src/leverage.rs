@@ -0,0 +1,366 @@
+use crate::constants::*;
+use crate::deposit::{credit_collateral, require_collateral_active};
+use crate::engine::{mint_as_engine, require_healthy_debt};
+use crate::errors::ErrorCode;
+use crate::position_nft::position_controller;
+use crate::pricefeeds::*;
+use crate::recovery::require_mint_keeps_system_cr;
+use crate::redemption::sync_position;
+use crate::repay::burn_debt;
+use crate::shared::{send_from_vault, transfer_tokens};
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    hash::hash,
+    instruction::{AccountMeta, Instruction},
+    program::{invoke, invoke_signed},
+};
+use anchor_spl::{
+    token_2022::Token2022,
+    token_interface::{Mint, TokenAccount, TokenInterface},
+};
+
+/// Mints enough DSC to bring the position to `target_ltv_bps` once the DSC
+/// has been swapped into collateral and deposited, all in one instruction.
+/// The remaining accounts are handed to the swap program untouched.
+pub fn open_leverage<'info>(
+    ctx: Context<'_, '_, '_, 'info, Leverage<'info>>,
+    target_ltv_bps: u64,
+    min_collateral_out: u64,
+) -> Result<()> {
+    require!(!ctx.accounts.engine.paused, ErrorCode::ProtocolPaused);
+    require_collateral_active(&ctx.accounts.engine, &ctx.accounts.config)?;
+    require!(
+        target_ltv_bps > 0 && target_ltv_bps < BPS_DENOMINATOR,
+        ErrorCode::InvalidLeverageTarget
+    );
+
+    let accounts = &mut *ctx.accounts;
+    require_position_holder(accounts)?;
+    let debt = accounts.user_data.borrowed_amount;
+
+    // Solve (debt + x) / (value + x) = target for x, valuing the swapped
+    // collateral at the oracle price.
+    let value = convert_collateral_to_usd(accounts.deposit.token_amt, &accounts.price)? as u128;
+    let target_debt = value * target_ltv_bps as u128;
+    let current_debt = debt as u128 * BPS_DENOMINATOR as u128;
+    require!(target_debt > current_debt, ErrorCode::InvalidLeverageTarget);
+    let mint_amount =
+        u64::try_from((target_debt - current_debt) / (BPS_DENOMINATOR - target_ltv_bps) as u128)
+            .map_err(|_| ErrorCode::MathOverflow)?;
+    require!(mint_amount > 0, ErrorCode::InvalidLeverageTarget);
+
+    mint_as_engine(
+        &accounts.engine,
+        &accounts.dsc_mint,
+        &accounts.user_dsc_account,
+        &accounts.dsc_token_program,
+        mint_amount,
+    )?;
+
+    let received = swap_via_adapter(
+        &accounts.swap_program,
+        ctx.remaining_accounts,
+        &mut accounts.user_token_account,
+        mint_amount,
+        min_collateral_out,
+        None,
+    )?;
+
+    transfer_tokens(
+        &accounts.user_token_account,
+        &accounts.vault,
+        &received,
+        &accounts.token_mint,
+        &accounts.user,
+        &accounts.token_program,
+    )?;
+    credit_collateral(
+        &mut accounts.user_data,
+        &mut accounts.deposit,
+        &accounts.position_index,
+        &mut accounts.engine,
+        &accounts.price,
+        received,
+    )?;
+
+    let new_borrowed = debt.checked_add(mint_amount).ok_or(ErrorCode::Overflow)?;
+    require_healthy_debt(
+        &accounts.deposit,
+        &accounts.price,
+        &accounts.engine,
+        new_borrowed,
+    )?;
+    require_mint_keeps_system_cr(
+        &accounts.engine,
+        convert_collateral_to_usd(received, &accounts.price)?,
+        mint_amount,
+    )?;
+    accounts.user_data.borrowed_amount = new_borrowed;
+
+    sync_position(
+        &accounts.position_index,
+        &mut accounts.engine,
+        &accounts.price,
+        &mut accounts.user_data,
+        accounts.deposit.token_amt,
+        new_borrowed,
+    )?;
+
+    emit!(LeverageChanged {
+        user: accounts.user.key(),
+        token: accounts.token_mint.key(),
+        dsc_amount: mint_amount,
+        collateral_amount: received,
+        opened: true,
+    });
+
+    Ok(())
+}
+
+/// Sells `collateral_amount` of the position's collateral for DSC and burns
+/// it against the debt. DSC beyond the debt stays with the user.
+pub fn deleverage<'info>(
+    ctx: Context<'_, '_, '_, 'info, Leverage<'info>>,
+    collateral_amount: u64,
+    min_dsc_out: u64,
+) -> Result<()> {
+    require!(collateral_amount > 0, ErrorCode::AmountLessThanZero);
+    require!(!ctx.accounts.engine.shutdown, ErrorCode::ShutdownActive);
+    require!(
+        ctx.accounts.deposit.token_amt >= collateral_amount,
+        ErrorCode::NotEnoughTokensInCollateral
+    );
+
+    let accounts = &mut *ctx.accounts;
+    require_position_holder(accounts)?;
+    send_from_vault(
+        &accounts.config,
+        &accounts.vault,
+        &accounts.user_token_account,
+        &accounts.token_mint,
+        &accounts.token_program,
+        collateral_amount,
+    )?;
+    accounts.deposit.token_amt -= collateral_amount;
+    accounts.user_data.token_balance = accounts
+        .user_data
+        .token_balance
+        .saturating_sub(collateral_amount);
+
+    let received = swap_via_adapter(
+        &accounts.swap_program,
+        ctx.remaining_accounts,
+        &mut accounts.user_dsc_account,
+        collateral_amount,
+        min_dsc_out,
+        None,
+    )?;
+
+    let repay = received.min(accounts.user_data.borrowed_amount);
+    if repay > 0 {
+        burn_debt(
+            &mut accounts.user_data,
+            &accounts.dsc_mint,
+            &accounts.user_dsc_account,
+            &accounts.user.to_account_info(),
+            &[],
+            &accounts.dsc_token_program,
+            accounts.token_mint.key(),
+            repay,
+        )?;
+    }
+
+    let debt = accounts.user_data.borrowed_amount;
+    if debt > 0 {
+        let hf = calculate_health_factor_after_withdrawal(
+            debt,
+            &accounts.price,
+            &accounts.engine,
+            accounts.deposit.token_amt,
+        )?;
+        require!(
+            hf >= accounts.engine.min_health_factor,
+            ErrorCode::LessHealthFactor
+        );
+    }
+
+    sync_position(
+        &accounts.position_index,
+        &mut accounts.engine,
+        &accounts.price,
+        &mut accounts.user_data,
+        accounts.deposit.token_amt,
+        debt,
+    )?;
+
+    emit!(LeverageChanged {
+        user: accounts.user.key(),
+        token: accounts.token_mint.key(),
+        dsc_amount: repay,
+        collateral_amount,
+        opened: false,
+    });
+
+    Ok(())
+}
+
+fn require_position_holder(accounts: &Leverage) -> Result<()> {
+    let controller = position_controller(&accounts.user_data, accounts.position_nft.as_deref())?;
+    require_keys_eq!(
+        controller,
+        accounts.user.key(),
+        ErrorCode::NotPositionHolder
+    );
+    Ok(())
+}
+
+/// Calls `swap(amount_in, min_amount_out)` on the configured swap program
+/// and returns what `destination` gained. Any program exposing an Anchor
+/// style `swap` with that signature can sit behind the adapter; its accounts
+/// come from the remaining accounts, in its own order. A PDA trading on its
+/// own behalf passes its key and seeds as `pda_signer`.
+pub fn swap_via_adapter<'info>(
+    swap_program: &UncheckedAccount<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    destination: &mut InterfaceAccount<'info, TokenAccount>,
+    amount_in: u64,
+    min_amount_out: u64,
+    pda_signer: Option<(Pubkey, &[&[u8]])>,
+) -> Result<u64> {
+    let before = destination.amount;
+
+    let mut data = hash(b"global:swap").to_bytes()[..8].to_vec();
+    data.extend_from_slice(&amount_in.to_le_bytes());
+    data.extend_from_slice(&min_amount_out.to_le_bytes());
+
+    let metas = remaining_accounts
+        .iter()
+        .map(|acc| AccountMeta {
+            pubkey: acc.key(),
+            is_signer: acc.is_signer || pda_signer.is_some_and(|(key, _)| key == acc.key()),
+            is_writable: acc.is_writable,
+        })
+        .collect();
+    let ix = Instruction {
+        program_id: swap_program.key(),
+        accounts: metas,
+        data,
+    };
+    let mut infos = remaining_accounts.to_vec();
+    infos.push(swap_program.to_account_info());
+    match pda_signer {
+        Some((_, seeds)) => invoke_signed(&ix, &infos, &[seeds])?,
+        None => invoke(&ix, &infos)?,
+    }
+
+    destination.reload()?;
+    let received = destination.amount.saturating_sub(before);
+    require!(
+        received > 0 && received >= min_amount_out,
+        ErrorCode::SlippageExceeded
+    );
+    Ok(received)
+}
+
+#[derive(Accounts)]
+pub struct Leverage<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"deposit",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = deposit.bump
+    )]
+    pub deposit: Account<'info, Deposit>,
+
+    pub position_nft: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump
+    )]
+    pub price: Account<'info, Price>,
+
+    #[account(
+        mut,
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = position_index.load()?.bump,
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = config,
+        associated_token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = user,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub user_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: must be the swap program set through governance; only invoked.
+    #[account(
+        executable,
+        address = engine.swap_program @ ErrorCode::InvalidSwapProgram,
+        constraint = engine.swap_program != Pubkey::default() @ ErrorCode::InvalidSwapProgram
+    )]
+    pub swap_program: UncheckedAccount<'info>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub dsc_token_program: Program<'info, Token2022>,
+}
@@ -1,47 +1,153 @@
 #![allow(clippy::result_large_err)]
 use anchor_lang::prelude::*;
 
+pub mod admin;
+pub mod combined;
 pub mod constants;
+pub mod delegation;
 pub mod deposit;
 pub mod engine;
 pub mod errors;
+pub mod flash;
+pub mod governance;
+pub mod insurance;
+pub mod leverage;
 pub mod lp;
+pub mod multisig;
 pub mod new_token;
+pub mod orders;
+pub mod position_nft;
 pub mod pricefeeds;
+pub mod psm;
+pub mod recovery;
+pub mod redemption;
+pub mod registry;
+pub mod repay;
+pub mod savings;
 pub mod shared;
+pub mod shutdown;
+pub mod soft_liquidation;
 pub mod structs;
+pub mod treasury;
+pub mod views;
 pub mod withdraw;
 
+pub use admin::*;
+pub use combined::*;
+pub use delegation::*;
 pub use deposit::*;
 pub use engine::*;
+pub use flash::*;
+pub use governance::*;
+pub use insurance::*;
+pub use leverage::*;
 pub use lp::*;
+pub use multisig::*;
 pub use new_token::*;
+pub use orders::*;
+pub use position_nft::*;
 pub use pricefeeds::*;
+pub use psm::*;
+pub use recovery::*;
+pub use redemption::*;
+pub use registry::*;
+pub use repay::*;
+pub use savings::*;
+pub use shutdown::*;
+pub use soft_liquidation::*;
+pub use treasury::*;
+pub use views::*;
 pub use withdraw::*;
 declare_id!("AM4tcZNBHBGaDeLEPgzuoEJbHbXqn2odYm9yXC93iUu");
 
 #[program]
 pub mod interest {
     use super::*;
-    use crate::structs::UserData;
     //all functions and their roles
 
     //Admin:
     pub fn start_engine(
         ctx: Context<InitializeEngine>,
+        market_id: u64,
         liquidation_threshold: u64,
         min_health_factor: u64,
         liquidation_bonus: u64,
         fee_percent: u64,
+        metadata: DscMetadataArgs,
     ) -> Result<()> {
         engine::initialize_engine(
             ctx,
+            market_id,
             liquidation_threshold,
             min_health_factor,
             liquidation_bonus,
             fee_percent,
+            metadata,
         )
     }
+    pub fn add_psm_asset(
+        ctx: Context<AddPsmAsset>,
+        fee_in_bps: u64,
+        fee_out_bps: u64,
+        cap: u64,
+    ) -> Result<()> {
+        psm::add_psm_asset(ctx, fee_in_bps, fee_out_bps, cap)
+    }
+    pub fn init_savings(ctx: Context<InitSavings>, rate_bps: u64) -> Result<()> {
+        savings::init_savings(ctx, rate_bps)
+    }
+    pub fn init_treasury(ctx: Context<InitTreasury>) -> Result<()> {
+        treasury::init_treasury(ctx)
+    }
+    pub fn sweep_treasury_fees(ctx: Context<SweepTreasuryFees>) -> Result<()> {
+        treasury::sweep_treasury_fees(ctx)
+    }
+    pub fn collect_protocol_fees(ctx: Context<CollectProtocolFees>) -> Result<()> {
+        treasury::collect_protocol_fees(ctx)
+    }
+    pub fn update_price(ctx: Context<UpdatePrice>, new_price: u64) -> Result<()> {
+        admin::update_price(ctx, new_price)
+    }
+    pub fn init_insurance_fund(ctx: Context<InitInsuranceFund>) -> Result<()> {
+        insurance::init_insurance_fund(ctx)
+    }
+    pub fn queue_action(ctx: Context<QueueAction>, action: GovernanceAction) -> Result<()> {
+        governance::queue_action(ctx, action)
+    }
+    pub fn execute_action(ctx: Context<ExecuteAction>) -> Result<()> {
+        governance::execute_action(ctx)
+    }
+    pub fn cancel_action(ctx: Context<CancelAction>) -> Result<()> {
+        governance::cancel_action(ctx)
+    }
+    pub fn pause(ctx: Context<Pause>) -> Result<()> {
+        admin::pause(ctx)
+    }
+    pub fn unpause(ctx: Context<Unpause>) -> Result<()> {
+        admin::unpause(ctx)
+    }
+    pub fn set_multisig(
+        ctx: Context<SetMultisig>,
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    ) -> Result<()> {
+        multisig::set_multisig(ctx, signers, threshold)
+    }
+    pub fn propose(ctx: Context<Propose>, action: MultisigAction) -> Result<()> {
+        multisig::propose(ctx, action)
+    }
+    pub fn approve(ctx: Context<Approve>) -> Result<()> {
+        multisig::approve(ctx)
+    }
+    pub fn execute_proposal(ctx: Context<ExecuteProposal>) -> Result<()> {
+        multisig::execute_proposal(ctx)
+    }
+    pub fn emergency_shutdown(ctx: Context<EmergencyShutdown>) -> Result<()> {
+        shutdown::emergency_shutdown(ctx)
+    }
+    pub fn delist_token(ctx: Context<DelistToken>) -> Result<()> {
+        registry::delist_token(ctx)
+    }
     pub fn start_token(ctx: Context<InitializeToken>, _price: u64) -> Result<()> {
         new_token::initialize_token(ctx, _price)
     }
@@ -49,28 +155,186 @@ pub mod interest {
         deposit::deposit_token(&mut ctx, amount)
     }
 
-    pub fn mint_dsc(mut ctx: Context<MintDSC>, amount: u64, new_price: u64) -> Result<()> {
-        engine::mint_dsc(&mut ctx, amount, new_price)
+    pub fn deposit_for(ctx: Context<DepositFor>, amount: u64) -> Result<()> {
+        deposit::deposit_for(ctx, amount)
+    }
+
+    pub fn set_delegate(
+        ctx: Context<SetDelegate>,
+        delegate: Pubkey,
+        permissions: u8,
+        mint_limit: u64,
+    ) -> Result<()> {
+        delegation::set_delegate(ctx, delegate, permissions, mint_limit)
+    }
+
+    pub fn revoke_delegate(ctx: Context<RevokeDelegate>) -> Result<()> {
+        delegation::revoke_delegate(ctx)
+    }
+
+    pub fn tokenize_position(ctx: Context<TokenizePosition>) -> Result<()> {
+        position_nft::tokenize_position(ctx)
+    }
+
+    pub fn place_order(
+        ctx: Context<PlaceOrder>,
+        action: u8,
+        trigger_hf: u64,
+        amount: u64,
+        min_out: u64,
+        tip: u64,
+    ) -> Result<()> {
+        orders::place_order(ctx, action, trigger_hf, amount, min_out, tip)
+    }
+
+    pub fn execute_order<'info>(
+        ctx: Context<'_, '_, '_, 'info, ExecuteOrder<'info>>,
+    ) -> Result<()> {
+        orders::execute_order(ctx)
+    }
+
+    pub fn cancel_order(ctx: Context<CancelOrder>) -> Result<()> {
+        orders::cancel_order(ctx)
+    }
+
+    pub fn mint_dsc(mut ctx: Context<MintDSC>, amount: u64) -> Result<()> {
+        engine::mint_dsc(&mut ctx, amount)
+    }
+
+    pub fn flash_mint<'info>(
+        ctx: Context<'_, '_, '_, 'info, FlashMintDSC<'info>>,
+        amount: u64,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        flash::flash_mint(ctx, amount, data)
+    }
+
+    pub fn withdraw_collateral(mut ctx: Context<WithdrawToken>, dsc_to_give: u64) -> Result<()> {
+        withdraw::redeem_collateral(&mut ctx, dsc_to_give)
     }
 
-    pub fn withdraw_collateral(
+    pub fn withdraw_collateral_amount(mut ctx: Context<WithdrawToken>, amount: u64) -> Result<()> {
+        withdraw::withdraw_collateral_amount(&mut ctx, amount)
+    }
+
+    pub fn deposit_and_mint(
+        ctx: Context<DepositAndMint>,
+        deposit_amount: u64,
+        mint_amount: u64,
+    ) -> Result<()> {
+        combined::deposit_and_mint(ctx, deposit_amount, mint_amount)
+    }
+
+    pub fn repay_and_withdraw(
         mut ctx: Context<WithdrawToken>,
-        dsc_to_give: u64,
-        new_price: u64,
+        repay_amount: u64,
+        withdraw_amount: u64,
     ) -> Result<()> {
-        withdraw::redeem_collateral(&mut ctx, dsc_to_give, new_price)
+        combined::repay_and_withdraw(&mut ctx, repay_amount, withdraw_amount)
     }
 
-    pub fn liquidate_user(
-        mut ctx: Context<Liquidate>,
-        debt_to_cover: u64,
-        new_price: u64,
+    pub fn open_leverage<'info>(
+        ctx: Context<'_, '_, '_, 'info, Leverage<'info>>,
+        target_ltv_bps: u64,
+        min_collateral_out: u64,
     ) -> Result<()> {
-        engine::liquidate(&mut ctx, debt_to_cover, new_price)
+        leverage::open_leverage(ctx, target_ltv_bps, min_collateral_out)
+    }
+
+    pub fn deleverage<'info>(
+        ctx: Context<'_, '_, '_, 'info, Leverage<'info>>,
+        collateral_amount: u64,
+        min_dsc_out: u64,
+    ) -> Result<()> {
+        leverage::deleverage(ctx, collateral_amount, min_dsc_out)
+    }
+
+    pub fn liquidate_user(mut ctx: Context<Liquidate>, debt_to_cover: u64) -> Result<()> {
+        engine::liquidate(&mut ctx, debt_to_cover)
+    }
+
+    pub fn soft_liquidate(mut ctx: Context<Liquidate>, debt_to_cover: u64) -> Result<()> {
+        soft_liquidation::soft_liquidate(&mut ctx, debt_to_cover)
+    }
+
+    pub fn update_system_totals<'info>(
+        ctx: Context<'_, '_, 'info, 'info, UpdateSystemTotals<'info>>,
+    ) -> Result<()> {
+        recovery::update_system_totals(ctx)
+    }
+
+    pub fn redeem_dsc<'info>(
+        ctx: Context<'_, '_, 'info, 'info, RedeemDSC<'info>>,
+        amount: u64,
+        max_fee_bps: u64,
+    ) -> Result<()> {
+        redemption::redeem_dsc(ctx, amount, max_fee_bps)
+    }
+
+    pub fn repay_dsc(ctx: Context<RepayDsc>, amount: u64) -> Result<()> {
+        repay::repay_dsc(ctx, amount)
+    }
+
+    pub fn refresh_health_factor(ctx: Context<RefreshHealthFactor>) -> Result<()> {
+        pricefeeds::refresh_health_factor(ctx)
+    }
+
+    pub fn position_view(ctx: Context<ViewPosition>) -> Result<PositionView> {
+        views::position_view(ctx)
     }
 
-    pub fn temp(ctx: Context<User>, hfbn: u64) -> Result<()> {
-        pricefeeds::temp_hf(ctx, hfbn)
+    pub fn simulate_health_factor(
+        ctx: Context<ViewPosition>,
+        collateral_delta: i64,
+        debt_delta: i64,
+    ) -> Result<u64> {
+        views::simulate_health_factor(ctx, collateral_delta, debt_delta)
+    }
+
+    pub fn psm_swap_in(ctx: Context<PsmSwap>, amount: u64) -> Result<()> {
+        psm::psm_swap_in(ctx, amount)
+    }
+    pub fn psm_swap_out(ctx: Context<PsmSwap>, amount: u64) -> Result<()> {
+        psm::psm_swap_out(ctx, amount)
+    }
+    pub fn sweep_psm_fees(ctx: Context<SweepPsmFees>) -> Result<()> {
+        psm::sweep_psm_fees(ctx)
+    }
+
+    pub fn fund_savings(ctx: Context<FundSavings>, amount: u64) -> Result<()> {
+        savings::fund_savings(ctx, amount)
+    }
+    pub fn sweep_savings_fees(ctx: Context<SweepSavingsFees>) -> Result<()> {
+        savings::sweep_savings_fees(ctx)
+    }
+    pub fn lock_dsc(ctx: Context<SavingsAction>, amount: u64) -> Result<()> {
+        savings::lock_dsc(ctx, amount)
+    }
+    pub fn unlock_dsc(ctx: Context<SavingsAction>, shares: u64) -> Result<()> {
+        savings::unlock_dsc(ctx, shares)
+    }
+
+    pub fn sweep_insurance_fees(ctx: Context<SweepInsuranceFees>) -> Result<()> {
+        insurance::sweep_insurance_fees(ctx)
+    }
+    pub fn cover_bad_debt(ctx: Context<CoverBadDebt>) -> Result<()> {
+        insurance::cover_bad_debt(ctx)
+    }
+
+    pub fn freeze_collateral(ctx: Context<FreezeCollateral>) -> Result<()> {
+        shutdown::freeze_collateral(ctx)
+    }
+    pub fn settle_position(ctx: Context<SettlePosition>) -> Result<()> {
+        shutdown::settle_position(ctx)
+    }
+    pub fn reclaim_collateral(ctx: Context<ReclaimCollateral>) -> Result<()> {
+        shutdown::reclaim_collateral(ctx)
+    }
+    pub fn pack_dsc(ctx: Context<PackDSC>, amount: u64) -> Result<()> {
+        shutdown::pack_dsc(ctx, amount)
+    }
+    pub fn cash_collateral(ctx: Context<CashCollateral>) -> Result<()> {
+        shutdown::cash_collateral(ctx)
     }
 
     pub fn give_liquidity(ctx: Context<GiveLiquidity>, amount: u64) -> Result<()> {
@@ -81,6 +81,12 @@ pub fn save_liq_deposit(ctx: &mut Context<GiveLiquidity>, token_amt: u64) -> Res
 
 #[derive(Accounts)]
 pub struct GiveLiquidity<'info> {
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
     #[account(mut)]
     pub user: Signer<'info>,
 
@@ -100,7 +106,7 @@ pub struct GiveLiquidity<'info> {
         init_if_needed,
         payer = user,
         space = 8 + LpData::INIT_SPACE,
-        seeds = [b"lp", user.key().as_ref(), token_mint.key().as_ref()],
+        seeds = [b"lp", engine.key().as_ref(), user.key().as_ref(), token_mint.key().as_ref()],
         bump
     )]
     pub lp_data: Account<'info, LpData>,
@@ -109,14 +115,19 @@ pub struct GiveLiquidity<'info> {
         init_if_needed,
         payer = user,
         space = 8 + LqDeposit::INIT_SPACE,
-        seeds = [b"liq_deposit", user.key().as_ref(), token_mint.key().as_ref()],
+        seeds = [
+            b"liq_deposit",
+            engine.key().as_ref(),
+            user.key().as_ref(),
+            token_mint.key().as_ref(),
+        ],
         bump
     )]
     pub liq_deposit: Account<'info, LqDeposit>,
 
     #[account(
         mut,
-        seeds = [b"config", token_mint.key().as_ref()],
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
         bump = config.bump
     )]
     pub config: Account<'info, Config>,
@@ -197,7 +208,12 @@ pub fn redeem_liquidity(mut ctx: Context<RedeemLiquidity>) -> Result<()> {
 
 pub fn send_tokens_to_user(ctx: &mut Context<RedeemLiquidity>, amt_total: u64) -> Result<()> {
     let binding = ctx.accounts.token_mint.key();
-    let seeds = &[b"config", binding.as_ref(), &[ctx.accounts.config.bump]];
+    let seeds = &[
+        b"config",
+        ctx.accounts.config.engine.as_ref(),
+        binding.as_ref(),
+        &[ctx.accounts.config.bump],
+    ];
     let signer_seeds = &[&seeds[..]];
 
     let decimals = ctx.accounts.token_mint.decimals;
@@ -226,12 +242,18 @@ pub fn send_tokens_to_user(ctx: &mut Context<RedeemLiquidity>, amt_total: u64) -
 
 #[derive(Accounts)]
 pub struct RedeemLiquidity<'info> {
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
     #[account(mut)]
     pub user: Signer<'info>,
 
     #[account(
         mut,
-        seeds = [b"lp", user.key().as_ref(),token_mint.key().as_ref()],
+        seeds = [b"lp", engine.key().as_ref(), user.key().as_ref(),token_mint.key().as_ref()],
         bump = lp_data.bump
     )]
     pub lp_data: Account<'info, LpData>,
@@ -243,7 +265,12 @@ pub struct RedeemLiquidity<'info> {
         close=user,
         has_one = user,
         has_one = token_mint,
-        seeds = [b"liq_deposit", user.key().as_ref(), token_mint.key().as_ref()],
+        seeds = [
+            b"liq_deposit",
+            engine.key().as_ref(),
+            user.key().as_ref(),
+            token_mint.key().as_ref(),
+        ],
         bump = liq_deposit.bump,
     )]
     pub liq_deposit: Account<'info, LqDeposit>,
@@ -251,7 +278,7 @@ pub struct RedeemLiquidity<'info> {
     #[account(
         mut,
         has_one = token_mint,
-        seeds = [b"config", token_mint.key().as_ref()],
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
         bump = config.bump
     )]
     pub config: Account<'info, Config>,
@@ -0,0 +1,288 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::structs::*;
+use crate::treasury::sweep_treasury;
+
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq, InitSpace)]
+pub enum MultisigAction {
+    ChangeAdmin {
+        new_admin: Pubkey,
+    },
+    Unpause,
+    SweepTreasury {
+        token_mint: Pubkey,
+    },
+    SetSigners {
+        #[max_len(MAX_MULTISIG_SIGNERS)]
+        signers: Vec<Pubkey>,
+        threshold: u8,
+    },
+}
+
+/// Installs the first signer set. Later changes go through a `SetSigners`
+/// proposal approved by the current set.
+pub fn set_multisig(ctx: Context<SetMultisig>, signers: Vec<Pubkey>, threshold: u8) -> Result<()> {
+    let engine = &mut ctx.accounts.engine;
+    require!(engine.multisig_threshold == 0, ErrorCode::MultisigRequired);
+    apply_signer_set(engine, &signers, threshold)
+}
+
+pub fn propose(ctx: Context<Propose>, action: MultisigAction) -> Result<()> {
+    let engine = &mut ctx.accounts.engine;
+    require!(engine.multisig_threshold > 0, ErrorCode::MultisigDisabled);
+    if let MultisigAction::SetSigners { signers, threshold } = &action {
+        validate_signer_set(signers, *threshold)?;
+    }
+
+    let slot = signer_slot(engine, &ctx.accounts.proposer.key())?;
+
+    let proposal = &mut ctx.accounts.proposal;
+    proposal.id = engine.proposal_count;
+    proposal.proposer = ctx.accounts.proposer.key();
+    proposal.action = action.clone();
+    proposal.approvals = 1 << slot;
+    proposal.nonce = engine.multisig_nonce;
+    proposal.bump = ctx.bumps.proposal;
+
+    engine.proposal_count = engine
+        .proposal_count
+        .checked_add(1)
+        .ok_or(ErrorCode::Overflow)?;
+
+    emit!(MultisigProposed {
+        id: proposal.id,
+        proposer: proposal.proposer,
+        action,
+    });
+
+    Ok(())
+}
+
+pub fn approve(ctx: Context<Approve>) -> Result<()> {
+    let engine = &ctx.accounts.engine;
+    let proposal = &mut ctx.accounts.proposal;
+    require!(
+        proposal.nonce == engine.multisig_nonce,
+        ErrorCode::StaleProposal
+    );
+
+    let bit = 1u8 << signer_slot(engine, &ctx.accounts.signer.key())?;
+    require!(proposal.approvals & bit == 0, ErrorCode::AlreadyApproved);
+    proposal.approvals |= bit;
+
+    emit!(MultisigApproved {
+        id: proposal.id,
+        signer: ctx.accounts.signer.key(),
+        approvals: proposal.approvals.count_ones() as u8,
+    });
+
+    Ok(())
+}
+
+/// Runs a proposal once it has enough approvals. Permissionless; a treasury
+/// sweep also needs that token's treasury, its vault and the fee collector's
+/// token account.
+pub fn execute_proposal(ctx: Context<ExecuteProposal>) -> Result<()> {
+    let proposal = &ctx.accounts.proposal;
+    let engine = &ctx.accounts.engine;
+    require!(
+        proposal.nonce == engine.multisig_nonce,
+        ErrorCode::StaleProposal
+    );
+    require!(
+        proposal.approvals.count_ones() >= engine.multisig_threshold as u32,
+        ErrorCode::ThresholdNotMet
+    );
+
+    match proposal.action.clone() {
+        MultisigAction::ChangeAdmin { new_admin } => {
+            ctx.accounts.roles.admin = new_admin;
+        }
+        MultisigAction::Unpause => {
+            ctx.accounts.engine.paused = false;
+            emit!(PauseChanged {
+                by: ctx.accounts.executor.key(),
+                paused: false,
+            });
+        }
+        MultisigAction::SweepTreasury { token_mint } => {
+            let accounts = &mut *ctx.accounts;
+            let (Some(treasury), Some(vault), Some(destination), Some(mint), Some(token_program)) = (
+                accounts.treasury.as_mut(),
+                accounts.vault.as_ref(),
+                accounts.treasury_token_account.as_ref(),
+                accounts.token_mint.as_ref(),
+                accounts.token_program.as_ref(),
+            ) else {
+                return Err(ErrorCode::InvalidMultisigAccounts.into());
+            };
+            require!(
+                treasury.token_mint == token_mint
+                    && mint.key() == token_mint
+                    && treasury.vault == vault.key()
+                    && destination.mint == token_mint
+                    && destination.owner == accounts.roles.fee_collector,
+                ErrorCode::InvalidMultisigAccounts
+            );
+            sweep_treasury(treasury, vault, destination, mint, token_program)?;
+        }
+        MultisigAction::SetSigners { signers, threshold } => {
+            apply_signer_set(&mut ctx.accounts.engine, &signers, threshold)?;
+        }
+    }
+
+    emit!(MultisigExecuted {
+        id: ctx.accounts.proposal.id,
+        executor: ctx.accounts.executor.key(),
+    });
+
+    Ok(())
+}
+
+/// An empty set with a zero threshold turns the multisig off; otherwise the
+/// threshold must be between 1 and the number of distinct signers.
+fn validate_signer_set(signers: &[Pubkey], threshold: u8) -> Result<()> {
+    require!(
+        signers.len() <= MAX_MULTISIG_SIGNERS,
+        ErrorCode::InvalidSignerSet
+    );
+    require!(
+        threshold as usize <= signers.len() && (threshold > 0 || signers.is_empty()),
+        ErrorCode::InvalidSignerSet
+    );
+    for (i, signer) in signers.iter().enumerate() {
+        require!(
+            *signer != Pubkey::default() && !signers[..i].contains(signer),
+            ErrorCode::InvalidSignerSet
+        );
+    }
+    Ok(())
+}
+
+fn apply_signer_set(engine: &mut Engine, signers: &[Pubkey], threshold: u8) -> Result<()> {
+    validate_signer_set(signers, threshold)?;
+
+    engine.multisig_signers = [Pubkey::default(); MAX_MULTISIG_SIGNERS];
+    engine.multisig_signers[..signers.len()].copy_from_slice(signers);
+    engine.multisig_signer_count = signers.len() as u8;
+    engine.multisig_threshold = threshold;
+    // Pending proposals were approved by the old set and no longer count.
+    engine.multisig_nonce = engine
+        .multisig_nonce
+        .checked_add(1)
+        .ok_or(ErrorCode::Overflow)?;
+    Ok(())
+}
+
+fn signer_slot(engine: &Engine, key: &Pubkey) -> Result<usize> {
+    engine.multisig_signers[..engine.multisig_signer_count as usize]
+        .iter()
+        .position(|s| s == key)
+        .ok_or(ErrorCode::NotMultisigSigner.into())
+}
+
+#[derive(Accounts)]
+pub struct SetMultisig<'info> {
+    #[account(
+        seeds = [b"roles", engine.key().as_ref()],
+        bump = roles.bump,
+        has_one = admin @ ErrorCode::UnauthorizedUser
+    )]
+    pub roles: Account<'info, Roles>,
+
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+}
+
+#[derive(Accounts)]
+pub struct Propose<'info> {
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(
+        init,
+        payer = proposer,
+        space = 8 + MultisigProposal::INIT_SPACE,
+        seeds = [b"proposal", engine.key().as_ref(), engine.proposal_count.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub proposal: Account<'info, MultisigProposal>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Approve<'info> {
+    pub signer: Signer<'info>,
+
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(
+        mut,
+        seeds = [b"proposal", engine.key().as_ref(), proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Account<'info, MultisigProposal>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteProposal<'info> {
+    pub executor: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mut, seeds = [b"roles", engine.key().as_ref()], bump = roles.bump)]
+    pub roles: Account<'info, Roles>,
+
+    #[account(
+        mut,
+        has_one = proposer,
+        close = proposer,
+        seeds = [b"proposal", engine.key().as_ref(), proposal.id.to_le_bytes().as_ref()],
+        bump = proposal.bump
+    )]
+    pub proposal: Account<'info, MultisigProposal>,
+
+    /// CHECK: only receives the rent of the closed proposal.
+    #[account(mut)]
+    pub proposer: UncheckedAccount<'info>,
+
+    pub token_mint: Option<InterfaceAccount<'info, Mint>>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury", engine.key().as_ref(), treasury.token_mint.as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Option<Account<'info, Treasury>>,
+
+    #[account(mut)]
+    pub vault: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(mut)]
+    pub treasury_token_account: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    pub token_program: Option<Interface<'info, TokenInterface>>,
+}
@@ -4,33 +4,69 @@ use anchor_spl::{
     token_interface::{Mint, TokenAccount, TokenInterface},
 };
 
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::registry::register_collateral;
 use crate::structs::*;
 
 pub fn initialize_token(ctx: Context<InitializeToken>, _price: u64) -> Result<()> {
     let config = &mut ctx.accounts.config;
     let price = &mut ctx.accounts.price;
 
+    config.engine = ctx.accounts.engine.key();
     config.token_mint = ctx.accounts.token_mint.key();
     config.total_collected = 0;
     config.total_liq = 0;
+    config.lp_fee_bps = DEFAULT_LP_FEE_BPS;
+    config.treasury_fee_bps = DEFAULT_TREASURY_FEE_BPS;
+    config.insurance_fee_bps = 0;
+    config.treasury_collected = 0;
+    config.insurance_collected = 0;
+    config.savings_fee_bps = 0;
+    config.savings_collected = 0;
+    config.status = COLLATERAL_ACTIVE;
+    config.oracle = Pubkey::default();
     config.vault = ctx.accounts.vault.key();
-    config.authority = ctx.accounts.admin.key();
     config.bump = ctx.bumps.config;
 
     price.token_mint = ctx.accounts.token_mint.key();
     price.price = _price;
     price.bump = ctx.bumps.price;
-    Ok(())
+    price.updated_at = Clock::get()?.unix_timestamp;
+
+    let mut position_index = ctx.accounts.position_index.load_init()?;
+    position_index.token_mint = ctx.accounts.token_mint.key();
+    position_index.count = 0;
+    position_index.bump = ctx.bumps.position_index;
+    drop(position_index);
+
+    register_collateral(&ctx.accounts.registry, ctx.accounts.token_mint.key())
 }
 
 #[derive(Accounts)]
 #[instruction(price: u64)]
 pub struct InitializeToken<'info> {
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(
+        seeds = [b"roles", engine.key().as_ref()],
+        bump = roles.bump,
+        has_one = admin @ ErrorCode::UnauthorizedUser
+    )]
+    pub roles: Account<'info, Roles>,
+
+    #[account(mut, seeds = [b"registry", engine.key().as_ref()], bump = registry.load()?.bump)]
+    pub registry: AccountLoader<'info, CollateralRegistry>,
+
     #[account(
         init,
         payer = admin,
         space = 8 + Config::INIT_SPACE,
-        seeds = [b"config", token_mint.key().as_ref()],
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
         bump
     )]
     pub config: Account<'info, Config>,
@@ -39,11 +75,20 @@ pub struct InitializeToken<'info> {
         init,
         payer=admin,
         space = 8 + Price::INIT_SPACE,
-        seeds = [b"price", token_mint.key().as_ref()],
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
         bump
     )]
     pub price: Account<'info, Price>,
 
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + std::mem::size_of::<PositionIndex>(),
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+
     #[account(mint::token_program = token_program)]
     pub token_mint: InterfaceAccount<'info, Mint>,
 
@@ -0,0 +1,564 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::leverage::swap_via_adapter;
+use crate::position_nft::position_controller;
+use crate::pricefeeds::calculate_health_factor;
+use crate::redemption::sync_position;
+use crate::repay::burn_debt;
+use crate::shared::send_from_vault;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_lang::system_program::{transfer, Transfer};
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_2022::Token2022,
+    token_interface::{
+        close_account, transfer_checked, CloseAccount, Mint, TokenAccount, TokenInterface,
+        TransferChecked,
+    },
+};
+
+/// Registers a stop-loss on the caller's position. Once its health factor
+/// falls below `trigger_hf`, any keeper may run `action` with `amount` and
+/// collect `tip` lamports. Repay orders escrow `amount` DSC up front.
+pub fn place_order(
+    ctx: Context<PlaceOrder>,
+    action: u8,
+    trigger_hf: u64,
+    amount: u64,
+    min_out: u64,
+    tip: u64,
+) -> Result<()> {
+    require!(
+        action == ORDER_REPAY || action == ORDER_SELL_COLLATERAL,
+        ErrorCode::InvalidOrder
+    );
+    require!(amount > 0, ErrorCode::AmountLessThanZero);
+    require!(
+        trigger_hf > ctx.accounts.engine.min_health_factor,
+        ErrorCode::InvalidOrder
+    );
+    require_keys_eq!(
+        position_controller(
+            &ctx.accounts.user_data,
+            ctx.accounts.position_nft.as_deref()
+        )?,
+        ctx.accounts.owner.key(),
+        ErrorCode::NotPositionHolder
+    );
+
+    if action == ORDER_REPAY {
+        transfer_checked(
+            CpiContext::new(
+                ctx.accounts.dsc_token_program.to_account_info(),
+                TransferChecked {
+                    from: ctx.accounts.owner_dsc_account.to_account_info(),
+                    to: ctx.accounts.escrow_dsc.to_account_info(),
+                    mint: ctx.accounts.dsc_mint.to_account_info(),
+                    authority: ctx.accounts.owner.to_account_info(),
+                },
+            ),
+            amount,
+            ctx.accounts.dsc_mint.decimals,
+        )?;
+    }
+
+    if tip > 0 {
+        transfer(
+            CpiContext::new(
+                ctx.accounts.system_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.owner.to_account_info(),
+                    to: ctx.accounts.order.to_account_info(),
+                },
+            ),
+            tip,
+        )?;
+    }
+
+    let order = &mut ctx.accounts.order;
+    order.owner = ctx.accounts.owner.key();
+    order.position = ctx.accounts.user_data.key();
+    order.token_mint = ctx.accounts.token_mint.key();
+    order.action = action;
+    order.trigger_hf = trigger_hf;
+    order.amount = amount;
+    order.min_out = min_out;
+    order.tip = tip;
+    order.active = true;
+    order.bump = ctx.bumps.order;
+
+    emit!(OrderPlaced {
+        owner: order.owner,
+        user: ctx.accounts.user_data.user,
+        token: order.token_mint,
+        action,
+        trigger_hf,
+        amount,
+    });
+
+    Ok(())
+}
+
+/// Runs a triggered order. Sell orders swap collateral for DSC through the
+/// swap adapter, with the swap program's accounts passed as remaining
+/// accounts and the order PDA as trader. DSC beyond the debt stays in escrow.
+/// Orders only shrink a position, so they run while the protocol is paused.
+pub fn execute_order<'info>(ctx: Context<'_, '_, '_, 'info, ExecuteOrder<'info>>) -> Result<()> {
+    require!(!ctx.accounts.engine.shutdown, ErrorCode::ShutdownActive);
+
+    let accounts = &mut *ctx.accounts;
+    require!(accounts.order.active, ErrorCode::OrderInactive);
+
+    // An order placed before the position changed hands no longer speaks for
+    // it; switch it off and leave the escrow for its owner to cancel.
+    let controller = position_controller(&accounts.user_data, accounts.position_nft.as_deref())?;
+    if accounts.order.owner != controller {
+        accounts.order.active = false;
+        emit!(OrderDeactivated {
+            owner: accounts.order.owner,
+            user: accounts.user_data.user,
+            token: accounts.token_mint.key(),
+        });
+        return Ok(());
+    }
+
+    let debt = accounts.user_data.borrowed_amount;
+    let hf_before = calculate_health_factor(
+        accounts.deposit.token_amt,
+        debt,
+        &accounts.price,
+        &accounts.engine,
+    )?;
+    require!(
+        debt > 0 && hf_before < accounts.order.trigger_hf,
+        ErrorCode::OrderNotTriggered
+    );
+
+    let engine_key = accounts.engine.key();
+    let position = accounts.order.position;
+    let bump = [accounts.order.bump];
+    let seeds: &[&[u8]] = &[b"order", engine_key.as_ref(), position.as_ref(), &bump];
+    let order_info = accounts.order.to_account_info();
+
+    let mut collateral_sold = 0;
+    let available_dsc = if accounts.order.action == ORDER_REPAY {
+        accounts.escrow_dsc.amount.min(accounts.order.amount)
+    } else {
+        collateral_sold = accounts.order.amount.min(accounts.deposit.token_amt);
+        require!(collateral_sold > 0, ErrorCode::NotEnoughTokensInCollateral);
+        send_from_vault(
+            &accounts.config,
+            &accounts.vault,
+            &accounts.escrow_collateral,
+            &accounts.token_mint,
+            &accounts.token_program,
+            collateral_sold,
+        )?;
+        accounts.deposit.token_amt -= collateral_sold;
+        accounts.user_data.token_balance = accounts
+            .user_data
+            .token_balance
+            .saturating_sub(collateral_sold);
+
+        let swap_program = accounts
+            .swap_program
+            .as_ref()
+            .ok_or(ErrorCode::InvalidSwapProgram)?;
+        swap_via_adapter(
+            swap_program,
+            ctx.remaining_accounts,
+            &mut accounts.escrow_dsc,
+            collateral_sold,
+            accounts.order.min_out,
+            Some((order_info.key(), seeds)),
+        )?
+    };
+
+    let dsc_repaid = available_dsc.min(debt);
+    require!(dsc_repaid > 0, ErrorCode::AmountLessThanZero);
+    burn_debt(
+        &mut accounts.user_data,
+        &accounts.dsc_mint,
+        &accounts.escrow_dsc,
+        &order_info,
+        &[seeds],
+        &accounts.dsc_token_program,
+        accounts.token_mint.key(),
+        dsc_repaid,
+    )?;
+
+    let remaining_debt = accounts.user_data.borrowed_amount;
+    let hf_after = calculate_health_factor(
+        accounts.deposit.token_amt,
+        remaining_debt,
+        &accounts.price,
+        &accounts.engine,
+    )?;
+    require!(hf_after > hf_before, ErrorCode::LessHealthFactor);
+
+    sync_position(
+        &accounts.position_index,
+        &mut accounts.engine,
+        &accounts.price,
+        &mut accounts.user_data,
+        accounts.deposit.token_amt,
+        remaining_debt,
+    )?;
+
+    let tip = accounts.order.tip;
+    accounts.order.active = false;
+    accounts.order.tip = 0;
+    **order_info.try_borrow_mut_lamports()? -= tip;
+    **accounts
+        .keeper
+        .to_account_info()
+        .try_borrow_mut_lamports()? += tip;
+
+    emit!(OrderExecuted {
+        keeper: accounts.keeper.key(),
+        user: accounts.user_data.user,
+        token: accounts.token_mint.key(),
+        action: accounts.order.action,
+        dsc_repaid,
+        collateral_sold,
+        tip,
+    });
+
+    Ok(())
+}
+
+/// Closes the order, handing back whatever is left in escrow along with the
+/// rent and an unpaid tip to the key that placed it. Either that key or the
+/// position's current controller may cancel, so a new holder can clear a
+/// stale order before placing their own.
+pub fn cancel_order(ctx: Context<CancelOrder>) -> Result<()> {
+    let accounts = &ctx.accounts;
+    let signer = accounts.authority.key();
+    if signer != accounts.order.owner {
+        require_keys_eq!(
+            position_controller(&accounts.user_data, accounts.position_nft.as_deref())?,
+            signer,
+            ErrorCode::NotPositionHolder
+        );
+    }
+
+    let engine_key = accounts.engine.key();
+    let position = accounts.order.position;
+    let bump = [accounts.order.bump];
+    let seeds: &[&[u8]] = &[b"order", engine_key.as_ref(), position.as_ref(), &bump];
+
+    refund_escrow(
+        &accounts.escrow_dsc,
+        &accounts.owner_dsc_account,
+        &accounts.dsc_mint,
+        &accounts.dsc_token_program.to_account_info(),
+        accounts,
+        seeds,
+    )?;
+    refund_escrow(
+        &accounts.escrow_collateral,
+        &accounts.owner_token_account,
+        &accounts.token_mint,
+        &accounts.token_program.to_account_info(),
+        accounts,
+        seeds,
+    )
+}
+
+fn refund_escrow<'info>(
+    escrow: &InterfaceAccount<'info, TokenAccount>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &AccountInfo<'info>,
+    accounts: &CancelOrder<'info>,
+    seeds: &[&[u8]],
+) -> Result<()> {
+    if escrow.amount > 0 {
+        transfer_checked(
+            CpiContext::new_with_signer(
+                token_program.clone(),
+                TransferChecked {
+                    from: escrow.to_account_info(),
+                    to: to.to_account_info(),
+                    mint: mint.to_account_info(),
+                    authority: accounts.order.to_account_info(),
+                },
+                &[seeds],
+            ),
+            escrow.amount,
+            mint.decimals,
+        )?;
+    }
+    close_account(CpiContext::new_with_signer(
+        token_program.clone(),
+        CloseAccount {
+            account: escrow.to_account_info(),
+            destination: accounts.owner.to_account_info(),
+            authority: accounts.order.to_account_info(),
+        },
+        &[seeds],
+    ))
+}
+
+#[derive(Accounts)]
+pub struct PlaceOrder<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [
+            b"user",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    pub position_nft: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        init,
+        payer = owner,
+        space = 8 + ProtectionOrder::INIT_SPACE,
+        seeds = [b"order", engine.key().as_ref(), user_data.key().as_ref()],
+        bump
+    )]
+    pub order: Account<'info, ProtectionOrder>,
+
+    #[account(
+        init,
+        payer = owner,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = order,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub escrow_dsc: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init,
+        payer = owner,
+        associated_token::mint = token_mint,
+        associated_token::authority = order,
+        associated_token::token_program = token_program
+    )]
+    pub escrow_collateral: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = dsc_mint,
+        token::authority = owner,
+        token::token_program = dsc_token_program
+    )]
+    pub owner_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub dsc_token_program: Program<'info, Token2022>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteOrder<'info> {
+    #[account(mut)]
+    pub keeper: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    pub position_nft: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"deposit",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = deposit.bump
+    )]
+    pub deposit: Account<'info, Deposit>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump
+    )]
+    pub price: Account<'info, Price>,
+
+    #[account(
+        mut,
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = position_index.load()?.bump,
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = config,
+        associated_token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        has_one = token_mint,
+        seeds = [b"order", engine.key().as_ref(), user_data.key().as_ref()],
+        bump = order.bump
+    )]
+    pub order: Account<'info, ProtectionOrder>,
+
+    #[account(
+        mut,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = order,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub escrow_dsc: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = order,
+        associated_token::token_program = token_program
+    )]
+    pub escrow_collateral: InterfaceAccount<'info, TokenAccount>,
+
+    /// CHECK: must be the swap program set through governance; only invoked,
+    /// and only needed for sell orders.
+    #[account(
+        executable,
+        address = engine.swap_program @ ErrorCode::InvalidSwapProgram,
+        constraint = engine.swap_program != Pubkey::default() @ ErrorCode::InvalidSwapProgram
+    )]
+    pub swap_program: Option<UncheckedAccount<'info>>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub dsc_token_program: Program<'info, Token2022>,
+}
+
+#[derive(Accounts)]
+pub struct CancelOrder<'info> {
+    /// The order's owner or the position's current controller.
+    pub authority: Signer<'info>,
+
+    /// CHECK: the key that placed the order; receives the refunds.
+    #[account(mut)]
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        has_one = owner,
+        has_one = token_mint,
+        close = owner,
+        seeds = [b"order", engine.key().as_ref(), user_data.key().as_ref()],
+        bump = order.bump
+    )]
+    pub order: Account<'info, ProtectionOrder>,
+
+    #[account(
+        seeds = [
+            b"user",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    pub position_nft: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        mut,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = order,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub escrow_dsc: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = order,
+        associated_token::token_program = token_program
+    )]
+    pub escrow_collateral: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = dsc_mint,
+        token::authority = owner,
+        token::token_program = dsc_token_program
+    )]
+    pub owner_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        token::mint = token_mint,
+        token::authority = owner,
+        token::token_program = token_program
+    )]
+    pub owner_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+    pub dsc_token_program: Program<'info, Token2022>,
+}
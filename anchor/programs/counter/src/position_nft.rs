@@ -0,0 +1,116 @@
+use crate::engine::mint_as_engine;
+use crate::errors::ErrorCode;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_2022::{spl_token_2022::instruction::AuthorityType, Token2022},
+    token_interface::{set_authority, Mint, SetAuthority, TokenAccount},
+};
+
+/// Mints a 1-of-1 NFT for the caller's position. From then on whoever holds
+/// the NFT controls the position; the PDAs keep their original seeds.
+pub fn tokenize_position(ctx: Context<TokenizePosition>) -> Result<()> {
+    let accounts = &ctx.accounts;
+    require!(
+        accounts.user_data.position_mint == Pubkey::default(),
+        ErrorCode::PositionAlreadyTokenized
+    );
+
+    mint_as_engine(
+        &accounts.engine,
+        &accounts.position_mint,
+        &accounts.owner_nft_account,
+        &accounts.nft_token_program,
+        1,
+    )?;
+
+    let market = accounts.engine.market_id.to_le_bytes();
+    let seeds: &[&[u8]] = &[b"engine", &market, &[accounts.engine.bump]];
+    set_authority(
+        CpiContext::new_with_signer(
+            accounts.nft_token_program.to_account_info(),
+            SetAuthority {
+                current_authority: accounts.engine.to_account_info(),
+                account_or_mint: accounts.position_mint.to_account_info(),
+            },
+            &[seeds],
+        ),
+        AuthorityType::MintTokens,
+        None,
+    )?;
+
+    let position_mint = accounts.position_mint.key();
+    ctx.accounts.user_data.position_mint = position_mint;
+
+    emit!(PositionTokenized {
+        user: ctx.accounts.owner.key(),
+        token: ctx.accounts.token_mint.key(),
+        position_mint,
+    });
+
+    Ok(())
+}
+
+/// The key that controls a position: the user who opened it, or the holder
+/// of its NFT once tokenized. `position_nft` is only needed in the latter case.
+pub fn position_controller(
+    user_data: &UserData,
+    position_nft: Option<&TokenAccount>,
+) -> Result<Pubkey> {
+    if user_data.position_mint == Pubkey::default() {
+        return Ok(user_data.user);
+    }
+    let nft = position_nft.ok_or(ErrorCode::NotPositionHolder)?;
+    require!(
+        nft.mint == user_data.position_mint && nft.amount == 1,
+        ErrorCode::NotPositionHolder
+    );
+    Ok(nft.owner)
+}
+
+#[derive(Accounts)]
+pub struct TokenizePosition<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [b"user", engine.key().as_ref(), owner.key().as_ref(), token_mint.key().as_ref()],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    #[account(
+        init,
+        payer = owner,
+        seeds = [b"position_mint", user_data.key().as_ref()],
+        bump,
+        mint::decimals = 0,
+        mint::authority = engine,
+        mint::token_program = nft_token_program
+    )]
+    pub position_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = owner,
+        associated_token::mint = position_mint,
+        associated_token::authority = owner,
+        associated_token::token_program = nft_token_program
+    )]
+    pub owner_nft_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub nft_token_program: Program<'info, Token2022>,
+    pub system_program: Program<'info, System>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
@@ -1,72 +1,31 @@
 use crate::errors::ErrorCode;
 use crate::structs::*;
 use anchor_lang::prelude::*;
-
-use anchor_lang::prelude::*;
-use anchor_spl::{
-    associated_token::AssociatedToken,
-    token_interface::{Mint, TokenAccount, TokenInterface},
-};
-
-pub fn temp_hf(ctx:Context<User>, hfbn: u64) -> Result<()> {
-    let user=&mut ctx.accounts.user_data;
-    user.hf = hfbn;
+use anchor_spl::token_interface::Mint;
+
+/// Recomputes a position's health factor from its deposit, debt and the
+/// stored price, and records the slot it was computed at. Permissionless.
+pub fn refresh_health_factor(ctx: Context<RefreshHealthFactor>) -> Result<()> {
+    let health_factor = calculate_health_factor(
+        ctx.accounts.deposit.token_amt,
+        ctx.accounts.user_data.borrowed_amount,
+        &ctx.accounts.price,
+        &ctx.accounts.engine,
+    )?;
+    let slot = Clock::get()?.slot;
+
+    let user_data = &mut ctx.accounts.user_data;
+    user_data.hf = health_factor;
+    user_data.hf_slot = slot;
+
+    emit!(HealthFactors {
+        user: user_data.user,
+        token: ctx.accounts.token_mint.key(),
+        health_factor,
+        slot,
+    });
     Ok(())
 }
-// pub fn temp(ctx: Context<User>, hfbn: u64) -> Result<()> {
-//     let user_data = &mut ctx.accounts.user_data;
-//     user_data.hf = hfbn;
-//     Ok(())
-// }
-
-// pub fn calculate_health_factor(ctx: Context<HealthFactor>, new_price: u64) -> Result<()> {
-//     let engine = &ctx.accounts.engine;
-//     let user_data = &mut ctx.accounts.user_data;
-
-//     let price = &mut ctx.accounts.price;
-//     price.price = new_price;
-
-//     let total_debt = user_data.borrowed_amount;
-
-//     if total_debt == 0 {
-//         user_data.hf = u64::MAX;
-//         return Ok(());
-//     }
-
-//     let collateral_tokens = user_data.token_balance;
-//     let collateral_value = convert_collateral_to_usd_scaled(collateral_tokens, price)?;
-
-//     let threshold_value = collateral_value
-//         .checked_mul(engine.liquidation_threshold)
-//         .ok_or(ErrorCode::MathOverflow)?
-//         .checked_div(100)
-//         .ok_or(ErrorCode::DivisionError)?;
-
-//     // Remove invalid comparison - we should check if debt exceeds threshold, not tokens vs threshold
-//     require!(
-//         total_debt.checked_mul(10000).unwrap() <= threshold_value,
-//         ErrorCode::OverCollateralLimit
-//     );
-
-//     let health_factorn = (threshold_value as u128)
-//         .checked_mul(1_000_000) // Scale up for fixed-point output (consistent 1e6 scaling)
-//         .ok_or(ErrorCode::MathOverflow)?
-//         .checked_div(10000000000 as u128)
-//         .ok_or(ErrorCode::DivisionError)?
-//         .checked_div(total_debt as u128)
-//         .ok_or(ErrorCode::DivisionError)?;
-
-//     if health_factorn > u64::MAX as u128 {
-//         return Err(ErrorCode::MathOverflow.into());
-//     }
-
-//     emit!(HealthFactors {
-//         health_factor: health_factorn as u64,
-//     });
-//     user_data.hf = health_factorn as u64;
-//     msg!("hf,{}", health_factorn as u64);
-//     Ok(())
-// }
 
 pub fn calculate_health_factor_with_debt(
     deposit: &Account<Deposit>,
@@ -114,12 +73,23 @@ pub fn calculate_health_factor_after_withdrawal(
     price: &Price,
     engine: &Account<Engine>,
     remaining_amount: u64,
+) -> Result<u64> {
+    calculate_health_factor(remaining_amount, total_debt, price, engine)
+}
+
+/// Health factor of `collateral_amount` backing `total_debt`, scaled by 1e6.
+/// Unlike `calculate_health_factor_with_debt` this does not fail below 1.
+pub fn calculate_health_factor(
+    collateral_amount: u64,
+    total_debt: u64,
+    price: &Price,
+    engine: &Engine,
 ) -> Result<u64> {
     if total_debt == 0 {
         return Ok(u64::MAX);
     }
 
-    let collateral_value = convert_collateral_to_usd_scaled(remaining_amount, price)?;
+    let collateral_value = convert_collateral_to_usd_scaled(collateral_amount, price)?;
 
     let threshold_value = collateral_value
         .checked_mul(engine.liquidation_threshold)
@@ -224,46 +194,42 @@ pub fn convert_dsc_to_collateral_scaled(dsc_amount: u64, price: &Price) -> Resul
 }
 
 #[derive(Accounts)]
-pub struct User<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
-
+pub struct RefreshHealthFactor<'info> {
     #[account(
-        mut,
-        seeds = [b"user", user.key().as_ref(),token_mint.key().as_ref()],
-        bump = user_data.bump
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
     )]
-    pub user_data: Account<'info, UserData>,
-    pub token_mint: InterfaceAccount<'info, Mint>,
-}
+    pub engine: Account<'info, Engine>,
 
-#[derive(Accounts)]
-pub struct HealthFactor<'info> {
-    #[account(mut)]
-    pub user: Signer<'info>,
+    pub token_mint: InterfaceAccount<'info, Mint>,
 
     #[account(
         mut,
-        seeds = [b"user", user.key().as_ref(),token_mint.key().as_ref()],
+        seeds = [
+            b"user",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
         bump = user_data.bump
     )]
     pub user_data: Account<'info, UserData>,
 
     #[account(
-        seeds = [b"engine"],
-        bump = engine.bump
+        seeds = [
+            b"deposit",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = deposit.bump
     )]
-    pub engine: Account<'info, Engine>,
-    pub token_mint: InterfaceAccount<'info, Mint>,
+    pub deposit: Account<'info, Deposit>,
 
     #[account(
-        mut,
         has_one = token_mint,
-        seeds = [b"price",token_mint.key().as_ref()],
-        bump = price.bump,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump
     )]
     pub price: Account<'info, Price>,
-
-    pub system_program: Program<'info, System>,
-    pub token_program: Interface<'info, TokenInterface>,
 }
@@ -0,0 +1,375 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::shared::*;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_2022::Token2022,
+    token_interface::{
+        burn, mint_to, transfer_checked, Burn, Mint, MintTo, TokenAccount, TokenInterface,
+        TransferChecked,
+    },
+};
+
+pub fn add_psm_asset(
+    ctx: Context<AddPsmAsset>,
+    fee_in_bps: u64,
+    fee_out_bps: u64,
+    cap: u64,
+) -> Result<()> {
+    require!(
+        fee_in_bps <= BPS_DENOMINATOR && fee_out_bps <= BPS_DENOMINATOR,
+        ErrorCode::InvalidBps
+    );
+
+    let psm_asset = &mut ctx.accounts.psm_asset;
+    psm_asset.stable_mint = ctx.accounts.stable_mint.key();
+    psm_asset.vault = ctx.accounts.vault.key();
+    psm_asset.fee_in_bps = fee_in_bps;
+    psm_asset.fee_out_bps = fee_out_bps;
+    psm_asset.cap = cap;
+    psm_asset.dsc_minted = 0;
+    psm_asset.fees_collected = 0;
+    psm_asset.bump = ctx.bumps.psm_asset;
+    Ok(())
+}
+
+/// Deposits `amount` of the stablecoin into the PSM vault and mints the
+/// equivalent DSC, less the swap-in fee.
+pub fn psm_swap_in(ctx: Context<PsmSwap>, amount: u64) -> Result<()> {
+    require!(amount > 0, ErrorCode::AmountLessThanZero);
+    require!(!ctx.accounts.engine.paused, ErrorCode::ProtocolPaused);
+    require!(!ctx.accounts.engine.shutdown, ErrorCode::ShutdownActive);
+
+    let dsc_gross = convert_decimals(
+        amount,
+        ctx.accounts.stable_mint.decimals,
+        ctx.accounts.dsc_mint.decimals,
+    )?;
+    let fee = bps_of(dsc_gross, ctx.accounts.psm_asset.fee_in_bps)?;
+    let dsc_out = dsc_gross.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?;
+    require!(dsc_out > 0, ErrorCode::AmountLessThanZero);
+
+    let new_minted = ctx
+        .accounts
+        .psm_asset
+        .dsc_minted
+        .checked_add(dsc_out)
+        .ok_or(ErrorCode::Overflow)?;
+    require!(
+        new_minted <= ctx.accounts.psm_asset.cap,
+        ErrorCode::PsmCapExceeded
+    );
+
+    transfer_tokens(
+        &ctx.accounts.user_stable_account,
+        &ctx.accounts.vault,
+        &amount,
+        &ctx.accounts.stable_mint,
+        &ctx.accounts.user,
+        &ctx.accounts.token_program,
+    )?;
+
+    let market = ctx.accounts.engine.market_id.to_le_bytes();
+    let seeds: &[&[u8]] = &[b"engine", &market, &[ctx.accounts.engine.bump]];
+    let signer = &[seeds];
+
+    let cpi_ctx = CpiContext::new_with_signer(
+        ctx.accounts.dsc_token_program.to_account_info(),
+        MintTo {
+            mint: ctx.accounts.dsc_mint.to_account_info(),
+            to: ctx.accounts.user_dsc_account.to_account_info(),
+            authority: ctx.accounts.engine.to_account_info(),
+        },
+        signer,
+    );
+    mint_to(cpi_ctx, dsc_out)?;
+
+    // Whatever the minted DSC does not claim back is fee.
+    let backing = convert_decimals(
+        dsc_out,
+        ctx.accounts.dsc_mint.decimals,
+        ctx.accounts.stable_mint.decimals,
+    )?;
+    let psm_asset = &mut ctx.accounts.psm_asset;
+    psm_asset.dsc_minted = new_minted;
+    psm_asset.fees_collected = psm_asset
+        .fees_collected
+        .checked_add(amount.saturating_sub(backing))
+        .ok_or(ErrorCode::Overflow)?;
+    let engine = &mut ctx.accounts.engine;
+    engine.psm_debt = engine
+        .psm_debt
+        .checked_add(dsc_out)
+        .ok_or(ErrorCode::Overflow)?;
+
+    emit!(PsmSwapped {
+        user: ctx.accounts.user.key(),
+        stable_mint: ctx.accounts.stable_mint.key(),
+        stable_amount: amount,
+        dsc_amount: dsc_out,
+        fee,
+        swap_in: true,
+    });
+
+    Ok(())
+}
+
+/// Burns `amount` DSC and releases the equivalent stablecoin from the PSM
+/// vault, less the swap-out fee. Stays open after shutdown, since PSM-minted
+/// DSC is left out of the settlement and is redeemed here instead.
+pub fn psm_swap_out(ctx: Context<PsmSwap>, amount: u64) -> Result<()> {
+    require!(amount > 0, ErrorCode::AmountLessThanZero);
+    require!(
+        amount <= ctx.accounts.psm_asset.dsc_minted,
+        ErrorCode::PsmInsufficientReserves
+    );
+
+    let fee = bps_of(amount, ctx.accounts.psm_asset.fee_out_bps)?;
+    let stable_out = convert_decimals(
+        amount.checked_sub(fee).ok_or(ErrorCode::MathOverflow)?,
+        ctx.accounts.dsc_mint.decimals,
+        ctx.accounts.stable_mint.decimals,
+    )?;
+    require!(stable_out > 0, ErrorCode::AmountLessThanZero);
+    require!(
+        ctx.accounts
+            .vault
+            .amount
+            .saturating_sub(ctx.accounts.psm_asset.fees_collected)
+            >= stable_out,
+        ErrorCode::PsmInsufficientReserves
+    );
+    let backing = convert_decimals(
+        amount,
+        ctx.accounts.dsc_mint.decimals,
+        ctx.accounts.stable_mint.decimals,
+    )?;
+
+    let burn_ctx = CpiContext::new(
+        ctx.accounts.dsc_token_program.to_account_info(),
+        Burn {
+            mint: ctx.accounts.dsc_mint.to_account_info(),
+            from: ctx.accounts.user_dsc_account.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        },
+    );
+    burn(burn_ctx, amount)?;
+
+    let market = ctx.accounts.engine.market_id.to_le_bytes();
+    let seeds: &[&[u8]] = &[b"engine", &market, &[ctx.accounts.engine.bump]];
+    let signer = &[seeds];
+
+    let transfer_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.user_stable_account.to_account_info(),
+            mint: ctx.accounts.stable_mint.to_account_info(),
+            authority: ctx.accounts.engine.to_account_info(),
+        },
+        signer,
+    );
+    transfer_checked(transfer_ctx, stable_out, ctx.accounts.stable_mint.decimals)?;
+
+    let psm_asset = &mut ctx.accounts.psm_asset;
+    psm_asset.dsc_minted -= amount;
+    psm_asset.fees_collected = psm_asset
+        .fees_collected
+        .checked_add(backing.saturating_sub(stable_out))
+        .ok_or(ErrorCode::Overflow)?;
+    let engine = &mut ctx.accounts.engine;
+    engine.psm_debt = engine.psm_debt.saturating_sub(amount);
+
+    emit!(PsmSwapped {
+        user: ctx.accounts.user.key(),
+        stable_mint: ctx.accounts.stable_mint.key(),
+        stable_amount: stable_out,
+        dsc_amount: amount,
+        fee,
+        swap_in: false,
+    });
+
+    Ok(())
+}
+
+/// Moves a PSM asset's collected swap fees from its vault into the
+/// stablecoin's treasury. Permissionless.
+pub fn sweep_psm_fees(ctx: Context<SweepPsmFees>) -> Result<()> {
+    let amount = ctx.accounts.psm_asset.fees_collected;
+    require!(amount > 0, ErrorCode::NothingToCollect);
+
+    let market = ctx.accounts.engine.market_id.to_le_bytes();
+    let seeds: &[&[u8]] = &[b"engine", &market, &[ctx.accounts.engine.bump]];
+    let signer = &[seeds];
+    let transfer_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.treasury_vault.to_account_info(),
+            mint: ctx.accounts.stable_mint.to_account_info(),
+            authority: ctx.accounts.engine.to_account_info(),
+        },
+        signer,
+    );
+    transfer_checked(transfer_ctx, amount, ctx.accounts.stable_mint.decimals)?;
+
+    ctx.accounts.psm_asset.fees_collected = 0;
+    let treasury = &mut ctx.accounts.treasury;
+    treasury.balance = treasury
+        .balance
+        .checked_add(amount)
+        .ok_or(ErrorCode::Overflow)?;
+    Ok(())
+}
+
+/// Rescales a token amount between mints with different decimals.
+pub fn convert_decimals(amount: u64, from_decimals: u8, to_decimals: u8) -> Result<u64> {
+    let converted = if to_decimals >= from_decimals {
+        (amount as u128)
+            .checked_mul(10u128.pow((to_decimals - from_decimals) as u32))
+            .ok_or(ErrorCode::MathOverflow)?
+    } else {
+        (amount as u128) / 10u128.pow((from_decimals - to_decimals) as u32)
+    };
+
+    if converted > u64::MAX as u128 {
+        return Err(ErrorCode::MathOverflow.into());
+    }
+
+    Ok(converted as u64)
+}
+
+#[derive(Accounts)]
+pub struct AddPsmAsset<'info> {
+    #[account(
+        seeds = [b"roles", engine.key().as_ref()],
+        bump = roles.bump,
+        has_one = admin @ ErrorCode::UnauthorizedUser
+    )]
+    pub roles: Account<'info, Roles>,
+
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(mint::token_program = token_program)]
+    pub stable_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + PsmAsset::INIT_SPACE,
+        seeds = [b"psm", engine.key().as_ref(), stable_mint.key().as_ref()],
+        bump
+    )]
+    pub psm_asset: Account<'info, PsmAsset>,
+
+    #[account(
+        init,
+        payer = admin,
+        associated_token::mint = stable_mint,
+        associated_token::authority = engine,
+        associated_token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct PsmSwap<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mut, address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mint::token_program = token_program)]
+    pub stable_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        has_one = stable_mint,
+        has_one = vault,
+        seeds = [b"psm", engine.key().as_ref(), stable_mint.key().as_ref()],
+        bump = psm_asset.bump
+    )]
+    pub psm_asset: Account<'info, PsmAsset>,
+
+    #[account(mut)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = stable_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program
+    )]
+    pub user_stable_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = user,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub user_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub dsc_token_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct SweepPsmFees<'info> {
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub stable_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        has_one = stable_mint,
+        has_one = vault,
+        seeds = [b"psm", engine.key().as_ref(), stable_mint.key().as_ref()],
+        bump = psm_asset.bump
+    )]
+    pub psm_asset: Account<'info, PsmAsset>,
+
+    #[account(mut)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"treasury", engine.key().as_ref(), stable_mint.key().as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(
+        mut,
+        associated_token::mint = stable_mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program
+    )]
+    pub treasury_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
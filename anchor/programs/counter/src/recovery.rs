@@ -0,0 +1,181 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::pricefeeds::convert_collateral_to_usd;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+
+/// Recomputes the system collateral ratio from every listed token's position
+/// index and stored price, and switches recovery mode on or off. Remaining
+/// accounts are `(position_index, price)` pairs in registry order, starting
+/// at `engine.totals_cursor`.
+///
+/// A full registry does not fit in one transaction, so a pass may span
+/// several calls: each sums the pairs it is given and the call that reaches
+/// the end of the registry writes the totals and resets the cursor.
+///
+/// Only collateral of indebted positions counts: debt-free deposits can leave
+/// at any time and back nothing.
+pub fn update_system_totals<'info>(
+    ctx: Context<'_, '_, 'info, 'info, UpdateSystemTotals<'info>>,
+) -> Result<()> {
+    let engine_key = ctx.accounts.engine.key();
+    let registry = ctx.accounts.registry.load()?;
+    let count = registry.count as usize;
+    let start = ctx.accounts.engine.totals_cursor as usize;
+    let pairs = ctx.remaining_accounts.len() / 2;
+    require!(
+        ctx.remaining_accounts.len().is_multiple_of(2)
+            && start + pairs <= count
+            && (pairs > 0 || count == 0),
+        ErrorCode::InvalidSystemTotalsAccounts
+    );
+
+    let (mut collateral_value, mut debt) = if start == 0 {
+        (0u128, 0u128)
+    } else {
+        (
+            ctx.accounts.engine.pending_collateral_value as u128,
+            ctx.accounts.engine.pending_debt as u128,
+        )
+    };
+    for (entry, pair) in registry.entries[start..start + pairs]
+        .iter()
+        .zip(ctx.remaining_accounts.chunks(2))
+    {
+        let mint = entry.mint;
+
+        let index_loader = AccountLoader::<PositionIndex>::try_from(&pair[0])?;
+        let index = index_loader.load()?;
+        require_pda(
+            &pair[0],
+            &[
+                b"position_index",
+                engine_key.as_ref(),
+                mint.as_ref(),
+                &[index.bump],
+            ],
+        )?;
+
+        let price = Account::<Price>::try_from(&pair[1])?;
+        require_pda(
+            &pair[1],
+            &[b"price", engine_key.as_ref(), mint.as_ref(), &[price.bump]],
+        )?;
+
+        collateral_value += convert_collateral_to_usd(index.total_collateral, &price)? as u128;
+        debt += index.total_debt as u128;
+    }
+
+    let collateral_value = u64::try_from(collateral_value).map_err(|_| ErrorCode::MathOverflow)?;
+    let debt = u64::try_from(debt).map_err(|_| ErrorCode::MathOverflow)?;
+    let engine = &mut ctx.accounts.engine;
+    if start + pairs < count {
+        engine.totals_cursor = (start + pairs) as u16;
+        engine.pending_collateral_value = collateral_value;
+        engine.pending_debt = debt;
+        return Ok(());
+    }
+
+    engine.totals_cursor = 0;
+    engine.pending_collateral_value = 0;
+    engine.pending_debt = 0;
+    engine.system_collateral_value = collateral_value;
+    engine.system_debt = debt;
+    engine.recovery_mode = is_recovery_mode(engine);
+
+    emit!(SystemTotalsUpdated {
+        collateral_value: engine.system_collateral_value,
+        debt: engine.system_debt,
+        recovery_mode: engine.recovery_mode,
+    });
+
+    Ok(())
+}
+
+/// Moves the system totals by one position's change from `before` to
+/// `after`, each its `(collateral, debt)` as last synced into the index, and
+/// re-evaluates recovery mode. Keeps the totals current between
+/// `update_system_totals` runs, which also pick up price moves.
+pub fn record_position_change(
+    engine: &mut Engine,
+    price: &Price,
+    before: (u64, u64),
+    after: (u64, u64),
+) -> Result<()> {
+    let backing = |(collateral, debt): (u64, u64)| -> Result<u64> {
+        if debt == 0 {
+            return Ok(0);
+        }
+        convert_collateral_to_usd(collateral, price)
+    };
+    engine.system_collateral_value = engine
+        .system_collateral_value
+        .saturating_sub(backing(before)?)
+        .saturating_add(backing(after)?);
+    engine.system_debt = engine
+        .system_debt
+        .saturating_sub(before.1)
+        .saturating_add(after.1);
+    engine.recovery_mode = is_recovery_mode(engine);
+    Ok(())
+}
+
+/// Whether the stored system totals sit below the critical ratio.
+pub fn is_recovery_mode(engine: &Engine) -> bool {
+    engine.critical_collateral_ratio > 0
+        && engine.system_debt > 0
+        && (engine.system_collateral_value as u128) * (BPS_DENOMINATOR as u128)
+            < engine.system_debt as u128 * engine.critical_collateral_ratio as u128
+}
+
+/// In recovery mode, new debt must come with at least as much collateral
+/// value per DSC as the system already has, so it cannot drag the ratio down.
+pub fn require_mint_keeps_system_cr(
+    engine: &Engine,
+    added_collateral_value: u64,
+    added_debt: u64,
+) -> Result<()> {
+    if !engine.recovery_mode || added_debt == 0 {
+        return Ok(());
+    }
+    require!(
+        added_collateral_value as u128 * engine.system_debt as u128
+            >= engine.system_collateral_value as u128 * added_debt as u128,
+        ErrorCode::RecoveryModeMint
+    );
+    Ok(())
+}
+
+/// Whether a position is collateralized worse than the system as a whole
+/// while recovery mode is on, which makes it liquidatable.
+pub fn below_system_cr(engine: &Engine, collateral_value: u64, debt: u64) -> bool {
+    engine.recovery_mode
+        && debt > 0
+        && (collateral_value as u128) * (engine.system_debt as u128)
+            < (engine.system_collateral_value as u128) * (debt as u128)
+}
+
+fn require_pda(account: &AccountInfo, seeds: &[&[u8]]) -> Result<()> {
+    let expected = Pubkey::create_program_address(seeds, &crate::ID)
+        .map_err(|_| ErrorCode::InvalidSystemTotalsAccounts)?;
+    require_keys_eq!(
+        expected,
+        account.key(),
+        ErrorCode::InvalidSystemTotalsAccounts
+    );
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct UpdateSystemTotals<'info> {
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(seeds = [b"registry", engine.key().as_ref()], bump = registry.load()?.bump)]
+    pub registry: AccountLoader<'info, CollateralRegistry>,
+}
@@ -0,0 +1,384 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::pricefeeds::*;
+use crate::recovery::record_position_change;
+use crate::structs::*;
+use crate::treasury::accrue_fee;
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_2022::Token2022,
+    token_interface::{
+        burn, transfer_checked, Burn, Mint, TokenAccount, TokenInterface, TransferChecked,
+    },
+};
+use std::cmp::Ordering;
+
+/// Burns up to `amount` DSC against the lowest-ratio positions of one
+/// collateral token and pays the redeemer their collateral at oracle value,
+/// less the redemption fee.
+///
+/// `remaining_accounts` must hold `(user_data, deposit)` pairs for the
+/// positions in index order, skipping any that are under 100% collateralized.
+pub fn redeem_dsc<'info>(
+    ctx: Context<'_, '_, 'info, 'info, RedeemDSC<'info>>,
+    amount: u64,
+    max_fee_bps: u64,
+) -> Result<()> {
+    require!(amount > 0, ErrorCode::AmountLessThanZero);
+    require!(!ctx.accounts.engine.paused, ErrorCode::ProtocolPaused);
+    require!(!ctx.accounts.engine.shutdown, ErrorCode::ShutdownActive);
+
+    let price = &ctx.accounts.price;
+    let token_mint = ctx.accounts.token_mint.key();
+    let engine_key = ctx.accounts.engine.key();
+    let mut remaining = amount;
+    let mut collateral_drawn: u64 = 0;
+    let mut accounts = ctx.remaining_accounts.chunks(2);
+
+    {
+        let mut index = ctx.accounts.position_index.load_mut()?;
+        let mut pos = 0usize;
+
+        while remaining > 0 && pos < index.count as usize {
+            let entry = index.entries[pos];
+
+            // Underwater positions belong to liquidators, not redeemers.
+            if convert_collateral_to_usd(entry.collateral, price)? < entry.debt {
+                pos += 1;
+                continue;
+            }
+
+            let pair = accounts
+                .next()
+                .ok_or(ErrorCode::InvalidRedemptionAccounts)?;
+            require!(pair.len() == 2, ErrorCode::InvalidRedemptionAccounts);
+
+            let mut user_data = Account::<UserData>::try_from(&pair[0])?;
+            let mut deposit = Account::<Deposit>::try_from(&pair[1])?;
+            require_keys_eq!(
+                user_data.user,
+                entry.user,
+                ErrorCode::InvalidRedemptionAccounts
+            );
+            require_keys_eq!(
+                deposit.user,
+                entry.user,
+                ErrorCode::InvalidRedemptionAccounts
+            );
+
+            let user_key = Pubkey::create_program_address(
+                &[
+                    b"user",
+                    engine_key.as_ref(),
+                    entry.user.as_ref(),
+                    token_mint.as_ref(),
+                    &[user_data.bump],
+                ],
+                &crate::ID,
+            )
+            .map_err(|_| ErrorCode::InvalidRedemptionAccounts)?;
+            let deposit_key = Pubkey::create_program_address(
+                &[
+                    b"deposit",
+                    engine_key.as_ref(),
+                    entry.user.as_ref(),
+                    token_mint.as_ref(),
+                    &[deposit.bump],
+                ],
+                &crate::ID,
+            )
+            .map_err(|_| ErrorCode::InvalidRedemptionAccounts)?;
+            require_keys_eq!(
+                user_data.key(),
+                user_key,
+                ErrorCode::InvalidRedemptionAccounts
+            );
+            require_keys_eq!(
+                deposit.key(),
+                deposit_key,
+                ErrorCode::InvalidRedemptionAccounts
+            );
+
+            let debt_redeemed = remaining.min(user_data.borrowed_amount);
+            let collateral = convert_dsc_to_collateral(debt_redeemed, price)?;
+            require!(
+                deposit.token_amt >= collateral,
+                ErrorCode::NotEnoughTokensInCollateral
+            );
+
+            deposit.token_amt -= collateral;
+            user_data.token_balance = user_data.token_balance.saturating_sub(collateral);
+            user_data.borrowed_amount -= debt_redeemed;
+
+            let (collateral_left, debt_left) = (deposit.token_amt, user_data.borrowed_amount);
+            update_index(
+                &mut index,
+                &mut ctx.accounts.engine,
+                price,
+                &mut user_data,
+                collateral_left,
+                debt_left,
+            )?;
+
+            user_data.exit(&crate::ID)?;
+            deposit.exit(&crate::ID)?;
+
+            remaining -= debt_redeemed;
+            collateral_drawn = collateral_drawn
+                .checked_add(collateral)
+                .ok_or(ErrorCode::Overflow)?;
+        }
+    }
+
+    let redeemed = amount - remaining;
+    require!(redeemed > 0, ErrorCode::NotEnoughDebt);
+
+    let now = Clock::get()?.unix_timestamp;
+    let engine = &mut ctx.accounts.engine;
+    let base_rate = decayed_base_rate(engine, now)?
+        .checked_add(
+            (redeemed as u128)
+                .checked_mul(RATE_PRECISION as u128)
+                .ok_or(ErrorCode::MathOverflow)?
+                .checked_div(ctx.accounts.dsc_mint.supply.max(1) as u128)
+                .ok_or(ErrorCode::DivisionError)?
+                .checked_div(REDEMPTION_BETA as u128)
+                .ok_or(ErrorCode::DivisionError)? as u64,
+        )
+        .ok_or(ErrorCode::Overflow)?
+        .min(RATE_PRECISION);
+    engine.redemption_base_rate = base_rate;
+    engine.last_redemption_ts = now;
+
+    let fee_rate = base_rate
+        .saturating_add(REDEMPTION_FEE_FLOOR)
+        .min(RATE_PRECISION);
+    require!(
+        (fee_rate as u128) * (BPS_DENOMINATOR as u128)
+            <= (max_fee_bps as u128) * (RATE_PRECISION as u128),
+        ErrorCode::RedemptionFeeTooHigh
+    );
+
+    let fee = ((collateral_drawn as u128) * (fee_rate as u128) / (RATE_PRECISION as u128)) as u64;
+    let payout = collateral_drawn - fee;
+
+    let burn_ctx = CpiContext::new(
+        ctx.accounts.dsc_token_program.to_account_info(),
+        Burn {
+            mint: ctx.accounts.dsc_mint.to_account_info(),
+            from: ctx.accounts.redeemer_dsc_account.to_account_info(),
+            authority: ctx.accounts.redeemer.to_account_info(),
+        },
+    );
+    burn(burn_ctx, redeemed)?;
+
+    let seeds = &[
+        b"config",
+        ctx.accounts.config.engine.as_ref(),
+        token_mint.as_ref(),
+        &[ctx.accounts.config.bump],
+    ];
+    let signer_seeds = &[&seeds[..]];
+
+    let transfer_ctx = CpiContext::new_with_signer(
+        ctx.accounts.token_program.to_account_info(),
+        TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.redeemer_token_account.to_account_info(),
+            mint: ctx.accounts.token_mint.to_account_info(),
+            authority: ctx.accounts.config.to_account_info(),
+        },
+        signer_seeds,
+    );
+    transfer_checked(transfer_ctx, payout, ctx.accounts.token_mint.decimals)?;
+
+    // The fee stays in the vault and is split like the withdrawal fee.
+    accrue_fee(&mut ctx.accounts.config, fee)?;
+
+    emit!(DscRedeemed {
+        redeemer: ctx.accounts.redeemer.key(),
+        token: token_mint,
+        dsc_amount: redeemed,
+        collateral_amount: payout,
+        fee,
+    });
+
+    Ok(())
+}
+
+/// Decays the stored redemption base rate by the minutes since the last
+/// redemption.
+pub fn decayed_base_rate(engine: &Engine, now: i64) -> Result<u64> {
+    let minutes = (now.saturating_sub(engine.last_redemption_ts).max(0) / 60) as u64;
+    // After a year the rate has decayed to nothing.
+    let minutes = minutes.min(525_600);
+
+    let mut factor = RATE_PRECISION as u128;
+    let mut base = MINUTE_DECAY_FACTOR as u128;
+    let mut exp = minutes;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            factor = factor * base / RATE_PRECISION as u128;
+        }
+        base = base * base / RATE_PRECISION as u128;
+        exp >>= 1;
+    }
+
+    Ok(((engine.redemption_base_rate as u128) * factor / RATE_PRECISION as u128) as u64)
+}
+
+/// Brings the index entry and totals for `user_data` in line with its
+/// current collateral and debt, and moves the engine's system totals by the
+/// same change. Positions without debt are dropped from the index.
+pub fn sync_position(
+    position_index: &AccountLoader<PositionIndex>,
+    engine: &mut Engine,
+    price: &Price,
+    user_data: &mut UserData,
+    collateral: u64,
+    debt: u64,
+) -> Result<()> {
+    let mut index = position_index.load_mut()?;
+    update_index(&mut index, engine, price, user_data, collateral, debt)
+}
+
+fn update_index(
+    index: &mut PositionIndex,
+    engine: &mut Engine,
+    price: &Price,
+    user_data: &mut UserData,
+    collateral: u64,
+    debt: u64,
+) -> Result<()> {
+    let collateral = if debt > 0 { collateral } else { 0 };
+    let before = (user_data.synced_collateral, user_data.synced_debt);
+    index.total_collateral = (index.total_collateral - user_data.synced_collateral)
+        .checked_add(collateral)
+        .ok_or(ErrorCode::Overflow)?;
+    index.total_debt = (index.total_debt - user_data.synced_debt)
+        .checked_add(debt)
+        .ok_or(ErrorCode::Overflow)?;
+    user_data.synced_collateral = collateral;
+    user_data.synced_debt = debt;
+
+    remove_position(index, user_data.user);
+    if debt > 0 {
+        insert_position(index, user_data.user, collateral, debt);
+    }
+    record_position_change(engine, price, before, (collateral, debt))
+}
+
+fn remove_position(index: &mut PositionIndex, user: Pubkey) {
+    let count = index.count as usize;
+    if let Some(pos) = index.entries[..count].iter().position(|e| e.user == user) {
+        index.entries.copy_within(pos + 1..count, pos);
+        index.count -= 1;
+    }
+}
+
+/// Inserts in ratio order. A full index drops its safest entry to make room
+/// for a riskier one, and otherwise leaves the new position unindexed; it is
+/// picked up again the next time it is synced.
+fn insert_position(index: &mut PositionIndex, user: Pubkey, collateral: u64, debt: u64) {
+    let mut count = index.count as usize;
+    let entry = IndexedPosition {
+        user,
+        collateral,
+        debt,
+    };
+    let pos = index.entries[..count]
+        .iter()
+        .position(|e| compare_positions(&entry, e) == Ordering::Less)
+        .unwrap_or(count);
+    if pos == MAX_INDEXED_POSITIONS {
+        return;
+    }
+    if count == MAX_INDEXED_POSITIONS {
+        count -= 1;
+    }
+
+    index.entries.copy_within(pos..count, pos + 1);
+    index.entries[pos] = entry;
+    index.count = count as u32 + 1;
+}
+
+/// Orders positions by collateral/debt ratio, riskiest first. Both sides are
+/// in the same collateral token, so the order does not depend on its price.
+fn compare_positions(a: &IndexedPosition, b: &IndexedPosition) -> Ordering {
+    let lhs = (a.collateral as u128) * (b.debt as u128);
+    let rhs = (b.collateral as u128) * (a.debt as u128);
+    lhs.cmp(&rhs).then_with(|| a.user.cmp(&b.user))
+}
+
+#[derive(Accounts)]
+pub struct RedeemDSC<'info> {
+    #[account(mut)]
+    pub redeemer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        has_one = token_mint,
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump,
+    )]
+    pub price: Account<'info, Price>,
+
+    #[account(
+        mut,
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = position_index.load()?.bump,
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = config,
+        associated_token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = redeemer,
+        associated_token::mint = token_mint,
+        associated_token::authority = redeemer,
+        associated_token::token_program = token_program
+    )]
+    pub redeemer_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(mut, address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = redeemer,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub redeemer_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub dsc_token_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
@@ -0,0 +1,87 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+
+/// Delists a token right away. Deposits and new mints against it stop;
+/// withdrawals, repayments and liquidations keep working.
+pub fn delist_token(ctx: Context<DelistToken>) -> Result<()> {
+    set_collateral_status(
+        &mut ctx.accounts.config,
+        &ctx.accounts.registry,
+        COLLATERAL_DELISTED,
+    )
+}
+
+pub fn register_collateral(
+    registry: &AccountLoader<CollateralRegistry>,
+    token_mint: Pubkey,
+) -> Result<()> {
+    let mut registry = registry.load_mut()?;
+    let count = registry.count as usize;
+    require!(count < MAX_COLLATERALS, ErrorCode::RegistryFull);
+
+    registry.entries[count] = RegisteredCollateral {
+        mint: token_mint,
+        status: COLLATERAL_ACTIVE,
+    };
+    registry.count += 1;
+    Ok(())
+}
+
+/// Updates a token's status on its config and its registry entry. Delisting
+/// is final.
+pub fn set_collateral_status(
+    config: &mut Config,
+    registry: &AccountLoader<CollateralRegistry>,
+    status: u8,
+) -> Result<()> {
+    require!(
+        status <= COLLATERAL_DELISTED && config.status != COLLATERAL_DELISTED,
+        ErrorCode::InvalidCollateralStatus
+    );
+
+    let mut registry = registry.load_mut()?;
+    let count = registry.count as usize;
+    let entry = registry.entries[..count]
+        .iter_mut()
+        .find(|e| e.mint == config.token_mint)
+        .ok_or(ErrorCode::InvalidCollateralStatus)?;
+    entry.status = status;
+    config.status = status;
+
+    emit!(CollateralStatusChanged {
+        token: config.token_mint,
+        status,
+    });
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct DelistToken<'info> {
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(
+        seeds = [b"roles", engine.key().as_ref()],
+        bump = roles.bump,
+        has_one = admin @ ErrorCode::UnauthorizedUser
+    )]
+    pub roles: Account<'info, Roles>,
+
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"config", engine.key().as_ref(), config.token_mint.as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(mut, seeds = [b"registry", engine.key().as_ref()], bump = registry.load()?.bump)]
+    pub registry: AccountLoader<'info, CollateralRegistry>,
+}
@@ -0,0 +1,143 @@
+use crate::errors::ErrorCode;
+use crate::redemption::sync_position;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    token_2022::Token2022,
+    token_interface::{burn, Burn, Mint, TokenAccount},
+};
+
+/// Burns the payer's DSC against a position's debt. Collateral stays put, so
+/// anyone can repay for anyone.
+pub fn repay_dsc(ctx: Context<RepayDsc>, amount: u64) -> Result<()> {
+    require!(!ctx.accounts.engine.shutdown, ErrorCode::ShutdownActive);
+
+    burn_debt(
+        &mut ctx.accounts.user_data,
+        &ctx.accounts.dsc_mint,
+        &ctx.accounts.payer_dsc_account,
+        &ctx.accounts.payer.to_account_info(),
+        &[],
+        &ctx.accounts.dsc_token_program,
+        ctx.accounts.token_mint.key(),
+        amount,
+    )?;
+
+    let debt = ctx.accounts.user_data.borrowed_amount;
+    sync_position(
+        &ctx.accounts.position_index,
+        &mut ctx.accounts.engine,
+        &ctx.accounts.price,
+        &mut ctx.accounts.user_data,
+        ctx.accounts.deposit.token_amt,
+        debt,
+    )
+}
+
+/// Burns `amount` of `from` against the position's debt. `signer_seeds` is
+/// only needed when `payer` is a PDA.
+#[allow(clippy::too_many_arguments)]
+pub fn burn_debt<'info>(
+    user_data: &mut UserData,
+    dsc_mint: &InterfaceAccount<'info, Mint>,
+    from: &InterfaceAccount<'info, TokenAccount>,
+    payer: &AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    dsc_token_program: &Program<'info, Token2022>,
+    token_mint: Pubkey,
+    amount: u64,
+) -> Result<()> {
+    require!(amount > 0, ErrorCode::AmountLessThanZero);
+    require!(
+        amount <= user_data.borrowed_amount,
+        ErrorCode::RepayExceedsDebt
+    );
+
+    let burn_ctx = CpiContext::new_with_signer(
+        dsc_token_program.to_account_info(),
+        Burn {
+            mint: dsc_mint.to_account_info(),
+            from: from.to_account_info(),
+            authority: payer.clone(),
+        },
+        signer_seeds,
+    );
+    burn(burn_ctx, amount)?;
+
+    user_data.borrowed_amount -= amount;
+
+    emit!(DscRepaid {
+        payer: payer.key(),
+        user: user_data.user,
+        token: token_mint,
+        amount,
+        remaining_debt: user_data.borrowed_amount,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct RepayDsc<'info> {
+    pub payer: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(mut, address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    #[account(
+        seeds = [
+            b"deposit",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = deposit.bump
+    )]
+    pub deposit: Account<'info, Deposit>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump,
+    )]
+    pub price: Account<'info, Price>,
+
+    #[account(
+        mut,
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = position_index.load()?.bump,
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+
+    #[account(
+        mut,
+        token::mint = dsc_mint,
+        token::authority = payer,
+        token::token_program = dsc_token_program
+    )]
+    pub payer_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub dsc_token_program: Program<'info, Token2022>,
+}
@@ -0,0 +1,408 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::pricefeeds::convert_collateral_to_usd;
+use crate::shared::*;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_2022::Token2022,
+    token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+pub fn init_savings(ctx: Context<InitSavings>, rate_bps: u64) -> Result<()> {
+    let savings = &mut ctx.accounts.savings;
+    savings.vault = ctx.accounts.vault.key();
+    savings.chi = CHI_PRECISION;
+    savings.rate_bps = rate_bps;
+    savings.rho = Clock::get()?.unix_timestamp;
+    savings.total_shares = 0;
+    savings.buffer = 0;
+    savings.bump = ctx.bumps.savings;
+    Ok(())
+}
+
+/// Adds DSC to the buffer that pays the savings rate.
+pub fn fund_savings(ctx: Context<FundSavings>, amount: u64) -> Result<()> {
+    require!(amount > 0, ErrorCode::AmountLessThanZero);
+
+    transfer_tokens(
+        &ctx.accounts.funder_dsc_account,
+        &ctx.accounts.vault,
+        &amount,
+        &ctx.accounts.dsc_mint,
+        &ctx.accounts.funder,
+        &ctx.accounts.dsc_token_program,
+    )?;
+
+    let savings = &mut ctx.accounts.savings;
+    savings.buffer = savings
+        .buffer
+        .checked_add(amount)
+        .ok_or(ErrorCode::Overflow)?;
+    Ok(())
+}
+
+/// Sells the savings share of a token's collected fees at the stored price.
+/// The buyer pays DSC into the savings vault, topping up the buffer, and
+/// takes the collateral. Permissionless.
+pub fn sweep_savings_fees(ctx: Context<SweepSavingsFees>) -> Result<()> {
+    let amount = ctx.accounts.config.savings_collected;
+    require!(amount > 0, ErrorCode::NothingToCollect);
+    let dsc_amount = convert_collateral_to_usd(amount, &ctx.accounts.price)?;
+    require!(dsc_amount > 0, ErrorCode::NothingToCollect);
+
+    // Accrue at the old buffer first so the top-up only pays from now on.
+    drip(&mut ctx.accounts.savings, Clock::get()?.unix_timestamp)?;
+
+    let accounts = &ctx.accounts;
+    transfer_checked(
+        CpiContext::new(
+            accounts.dsc_token_program.to_account_info(),
+            TransferChecked {
+                from: accounts.buyer_dsc_account.to_account_info(),
+                to: accounts.savings_vault.to_account_info(),
+                mint: accounts.dsc_mint.to_account_info(),
+                authority: accounts.buyer.to_account_info(),
+            },
+        ),
+        dsc_amount,
+        accounts.dsc_mint.decimals,
+    )?;
+    send_from_vault(
+        &accounts.config,
+        &accounts.vault,
+        &accounts.buyer_token_account,
+        &accounts.token_mint,
+        &accounts.token_program,
+        amount,
+    )?;
+
+    ctx.accounts.config.savings_collected = 0;
+    let savings = &mut ctx.accounts.savings;
+    savings.buffer = savings
+        .buffer
+        .checked_add(dsc_amount)
+        .ok_or(ErrorCode::Overflow)?;
+
+    emit!(SavingsFeesSwept {
+        buyer: ctx.accounts.buyer.key(),
+        token: ctx.accounts.token_mint.key(),
+        collateral: amount,
+        dsc_amount,
+    });
+
+    Ok(())
+}
+
+pub fn lock_dsc(ctx: Context<SavingsAction>, amount: u64) -> Result<()> {
+    require!(amount > 0, ErrorCode::AmountLessThanZero);
+
+    let savings = &mut ctx.accounts.savings;
+    drip(savings, Clock::get()?.unix_timestamp)?;
+
+    let shares = (amount as u128)
+        .checked_mul(CHI_PRECISION)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(savings.chi)
+        .ok_or(ErrorCode::DivisionError)? as u64;
+    require!(shares > 0, ErrorCode::AmountLessThanZero);
+
+    transfer_tokens(
+        &ctx.accounts.user_dsc_account,
+        &ctx.accounts.vault,
+        &amount,
+        &ctx.accounts.dsc_mint,
+        &ctx.accounts.user,
+        &ctx.accounts.dsc_token_program,
+    )?;
+
+    let savings = &mut ctx.accounts.savings;
+    savings.total_shares = savings
+        .total_shares
+        .checked_add(shares)
+        .ok_or(ErrorCode::Overflow)?;
+
+    let position = &mut ctx.accounts.position;
+    if position.user == Pubkey::default() {
+        position.user = ctx.accounts.user.key();
+        position.bump = ctx.bumps.position;
+    }
+    position.shares = position
+        .shares
+        .checked_add(shares)
+        .ok_or(ErrorCode::Overflow)?;
+
+    emit!(SavingsLocked {
+        user: ctx.accounts.user.key(),
+        amount,
+        shares,
+    });
+
+    Ok(())
+}
+
+pub fn unlock_dsc(ctx: Context<SavingsAction>, shares: u64) -> Result<()> {
+    require!(shares > 0, ErrorCode::AmountLessThanZero);
+    require!(
+        ctx.accounts.position.shares >= shares,
+        ErrorCode::InsufficientShares
+    );
+
+    let savings = &mut ctx.accounts.savings;
+    drip(savings, Clock::get()?.unix_timestamp)?;
+
+    let amount = (shares as u128)
+        .checked_mul(savings.chi)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(CHI_PRECISION)
+        .ok_or(ErrorCode::DivisionError)? as u64;
+
+    let engine_key = ctx.accounts.engine.key();
+    let seeds: &[&[u8]] = &[b"savings", engine_key.as_ref(), &[savings.bump]];
+    let signer = &[seeds];
+
+    let transfer_ctx = CpiContext::new_with_signer(
+        ctx.accounts.dsc_token_program.to_account_info(),
+        TransferChecked {
+            from: ctx.accounts.vault.to_account_info(),
+            to: ctx.accounts.user_dsc_account.to_account_info(),
+            mint: ctx.accounts.dsc_mint.to_account_info(),
+            authority: ctx.accounts.savings.to_account_info(),
+        },
+        signer,
+    );
+    transfer_checked(transfer_ctx, amount, ctx.accounts.dsc_mint.decimals)?;
+
+    ctx.accounts.savings.total_shares -= shares;
+    ctx.accounts.position.shares -= shares;
+
+    emit!(SavingsUnlocked {
+        user: ctx.accounts.user.key(),
+        amount,
+        shares,
+    });
+
+    Ok(())
+}
+
+/// Accrues the savings rate into `chi` since the last drip. Growth is capped
+/// by the funded buffer so the vault always covers every share.
+pub fn drip(savings: &mut DscSavings, now: i64) -> Result<()> {
+    let elapsed = now.saturating_sub(savings.rho);
+    if elapsed <= 0 {
+        return Ok(());
+    }
+    savings.rho = now;
+    if savings.total_shares == 0 || savings.rate_bps == 0 {
+        return Ok(());
+    }
+
+    let mut growth = savings
+        .chi
+        .checked_mul(savings.rate_bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_mul(elapsed as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / (BPS_DENOMINATOR as u128 * SECONDS_PER_YEAR as u128);
+
+    let shares = savings.total_shares as u128;
+    let mut owed = shares.checked_mul(growth).ok_or(ErrorCode::MathOverflow)? / CHI_PRECISION;
+    if owed > savings.buffer as u128 {
+        growth = (savings.buffer as u128) * CHI_PRECISION / shares;
+        owed = shares * growth / CHI_PRECISION;
+    }
+
+    savings.chi += growth;
+    savings.buffer -= owed as u64;
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct InitSavings<'info> {
+    #[account(
+        seeds = [b"roles", engine.key().as_ref()],
+        bump = roles.bump,
+        has_one = admin @ ErrorCode::UnauthorizedUser
+    )]
+    pub roles: Account<'info, Roles>,
+
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + DscSavings::INIT_SPACE,
+        seeds = [b"savings", engine.key().as_ref()],
+        bump
+    )]
+    pub savings: Account<'info, DscSavings>,
+
+    #[account(
+        init,
+        payer = admin,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = savings,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub dsc_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct FundSavings<'info> {
+    pub funder: Signer<'info>,
+
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        has_one = vault,
+        seeds = [b"savings", engine.key().as_ref()],
+        bump = savings.bump
+    )]
+    pub savings: Account<'info, DscSavings>,
+
+    #[account(mut)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = funder,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub funder_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub dsc_token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SavingsAction<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        has_one = vault,
+        seeds = [b"savings", engine.key().as_ref()],
+        bump = savings.bump
+    )]
+    pub savings: Account<'info, DscSavings>,
+
+    #[account(mut)]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + SavingsPosition::INIT_SPACE,
+        seeds = [b"savings_position", engine.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub position: Account<'info, SavingsPosition>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = user,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub user_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub dsc_token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct SweepSavingsFees<'info> {
+    #[account(mut)]
+    pub buyer: Signer<'info>,
+
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        has_one = token_mint,
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump
+    )]
+    pub price: Account<'info, Price>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = config,
+        associated_token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"savings", engine.key().as_ref()],
+        bump = savings.bump
+    )]
+    pub savings: Account<'info, DscSavings>,
+
+    #[account(mut, address = savings.vault)]
+    pub savings_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = buyer,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub buyer_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        associated_token::mint = token_mint,
+        associated_token::authority = buyer,
+        associated_token::token_program = token_program
+    )]
+    pub buyer_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub dsc_token_program: Program<'info, Token2022>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
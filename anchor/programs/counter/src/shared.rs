@@ -1,3 +1,6 @@
+use crate::constants::BPS_DENOMINATOR;
+use crate::errors::ErrorCode;
+use crate::structs::Config;
 use anchor_lang::prelude::*;
 use anchor_spl::token_interface::{
     transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked,
@@ -21,3 +24,48 @@ pub fn transfer_tokens<'info>(
 
     transfer_checked(cpi_context, (*amount) as u64, mint.decimals)
 }
+
+pub fn send_from_vault<'info>(
+    config: &Account<'info, Config>,
+    vault: &InterfaceAccount<'info, TokenAccount>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<()> {
+    let seeds = &[
+        b"config",
+        config.engine.as_ref(),
+        config.token_mint.as_ref(),
+        &[config.bump],
+    ];
+    let signer_seeds = &[&seeds[..]];
+
+    let transfer_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        TransferChecked {
+            from: vault.to_account_info(),
+            to: to.to_account_info(),
+            mint: mint.to_account_info(),
+            authority: config.to_account_info(),
+        },
+        signer_seeds,
+    );
+    transfer_checked(transfer_ctx, amount, mint.decimals)
+}
+
+pub fn percent_of(amount: u64, percent: u64) -> Result<u64> {
+    let value = (amount as u128)
+        .checked_mul(percent as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / 100;
+    u64::try_from(value).map_err(|_| ErrorCode::MathOverflow.into())
+}
+
+pub fn bps_of(amount: u64, bps: u64) -> Result<u64> {
+    let value = (amount as u128)
+        .checked_mul(bps as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        / BPS_DENOMINATOR as u128;
+    Ok(value as u64)
+}
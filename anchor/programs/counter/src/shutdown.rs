@@ -0,0 +1,480 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::position_nft::position_controller;
+use crate::pricefeeds::*;
+use crate::redemption::sync_position;
+use crate::shared::send_from_vault;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_2022::Token2022,
+    token_interface::{burn, Burn, Mint, TokenAccount, TokenInterface},
+};
+
+/// Shuts the protocol down for good. Every instruction that writes a price is
+/// blocked from here on, so stored prices stay frozen at their current value.
+pub fn emergency_shutdown(ctx: Context<EmergencyShutdown>) -> Result<()> {
+    let engine = &mut ctx.accounts.engine;
+    require!(!engine.shutdown, ErrorCode::ShutdownActive);
+
+    engine.shutdown = true;
+    engine.shutdown_ts = Clock::get()?.unix_timestamp;
+    engine.shutdown_debt = ctx.accounts.dsc_mint.supply.saturating_sub(engine.psm_debt);
+
+    emit!(ShutdownTriggered {
+        by: ctx.accounts.admin.key(),
+        dsc_supply: engine.shutdown_debt,
+        timestamp: engine.shutdown_ts,
+    });
+
+    Ok(())
+}
+
+pub fn freeze_collateral(ctx: Context<FreezeCollateral>) -> Result<()> {
+    require!(ctx.accounts.engine.shutdown, ErrorCode::NotShutdown);
+
+    let settlement = &mut ctx.accounts.settlement;
+    settlement.token_mint = ctx.accounts.token_mint.key();
+    settlement.price = ctx.accounts.price.price;
+    settlement.pool = 0;
+    settlement.cashed = 0;
+    settlement.bump = ctx.bumps.settlement;
+    Ok(())
+}
+
+/// Takes the collateral backing a position's debt, at the frozen price, into
+/// the settlement pool and clears the debt. Permissionless, and may run after
+/// cash-outs have started; later settlements go to DSC not yet cashed.
+pub fn settle_position(ctx: Context<SettlePosition>) -> Result<()> {
+    let debt = ctx.accounts.user_data.borrowed_amount;
+    require!(debt > 0, ErrorCode::NotEnoughDebt);
+
+    let owed = convert_dsc_to_collateral(debt, &ctx.accounts.price)?;
+    let taken = owed.min(ctx.accounts.deposit.token_amt);
+
+    let settlement = &mut ctx.accounts.settlement;
+    settlement.pool = settlement
+        .pool
+        .checked_add(taken)
+        .ok_or(ErrorCode::Overflow)?;
+
+    let deposit = &mut ctx.accounts.deposit;
+    deposit.token_amt -= taken;
+
+    let user_data = &mut ctx.accounts.user_data;
+    user_data.borrowed_amount = 0;
+    user_data.token_balance = user_data.token_balance.saturating_sub(taken);
+
+    sync_position(
+        &ctx.accounts.position_index,
+        &mut ctx.accounts.engine,
+        &ctx.accounts.price,
+        user_data,
+        ctx.accounts.deposit.token_amt,
+        0,
+    )?;
+
+    emit!(PositionSettled {
+        user: ctx.accounts.user_data.user,
+        token: ctx.accounts.token_mint.key(),
+        debt,
+        collateral: taken,
+    });
+
+    Ok(())
+}
+
+/// Returns a settled position's leftover collateral to whoever controls it.
+pub fn reclaim_collateral(ctx: Context<ReclaimCollateral>) -> Result<()> {
+    require!(ctx.accounts.engine.shutdown, ErrorCode::NotShutdown);
+    let controller = position_controller(
+        &ctx.accounts.user_data,
+        ctx.accounts.position_nft.as_deref(),
+    )?;
+    require_keys_eq!(
+        ctx.accounts.user.key(),
+        controller,
+        ErrorCode::NotPositionHolder
+    );
+    require!(
+        ctx.accounts.user_data.borrowed_amount == 0,
+        ErrorCode::PositionNotSettled
+    );
+
+    let amount = ctx.accounts.deposit.token_amt;
+    require!(amount > 0, ErrorCode::NotEnoughTokensInCollateral);
+
+    send_from_vault(
+        &ctx.accounts.config,
+        &ctx.accounts.vault,
+        &ctx.accounts.user_token_account,
+        &ctx.accounts.token_mint,
+        &ctx.accounts.token_program,
+        amount,
+    )?;
+
+    ctx.accounts.deposit.token_amt = 0;
+    let user_data = &mut ctx.accounts.user_data;
+    user_data.token_balance = user_data.token_balance.saturating_sub(amount);
+
+    emit!(TokenRedeemed {
+        user: ctx.accounts.user.key(),
+        token: ctx.accounts.token_mint.key(),
+        amount,
+    });
+
+    Ok(())
+}
+
+/// Burns DSC into the caller's settlement bag. Packed DSC is then cashed out
+/// token by token with `cash_collateral`.
+pub fn pack_dsc(ctx: Context<PackDSC>, amount: u64) -> Result<()> {
+    require!(ctx.accounts.engine.shutdown, ErrorCode::NotShutdown);
+    require!(amount > 0, ErrorCode::AmountLessThanZero);
+
+    let burn_ctx = CpiContext::new(
+        ctx.accounts.dsc_token_program.to_account_info(),
+        Burn {
+            mint: ctx.accounts.dsc_mint.to_account_info(),
+            from: ctx.accounts.user_dsc_account.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        },
+    );
+    burn(burn_ctx, amount)?;
+
+    let bag = &mut ctx.accounts.bag;
+    if bag.user == Pubkey::default() {
+        bag.user = ctx.accounts.user.key();
+        bag.bump = ctx.bumps.bag;
+    }
+    bag.packed = bag.packed.checked_add(amount).ok_or(ErrorCode::Overflow)?;
+
+    Ok(())
+}
+
+/// Pays out this token's pro-rata share of the settlement pool for DSC
+/// packed since the caller's last cash-out of the token. The share is taken
+/// from the live pool over the DSC still uncashed against it, so collateral
+/// settled after earlier cash-outs is not stranded.
+pub fn cash_collateral(ctx: Context<CashCollateral>) -> Result<()> {
+    let engine = &ctx.accounts.engine;
+    require!(engine.shutdown, ErrorCode::NotShutdown);
+    require!(
+        Clock::get()?.unix_timestamp >= engine.shutdown_ts + SHUTDOWN_SETTLEMENT_WAIT,
+        ErrorCode::SettlementWaitActive
+    );
+
+    let settlement = &mut ctx.accounts.settlement;
+
+    let claim = &mut ctx.accounts.claim;
+    if claim.user == Pubkey::default() {
+        claim.user = ctx.accounts.user.key();
+        claim.token_mint = ctx.accounts.token_mint.key();
+        claim.bump = ctx.bumps.claim;
+    }
+
+    let unclaimed = ctx.accounts.bag.packed - claim.cashed;
+    require!(unclaimed > 0, ErrorCode::NothingToCash);
+
+    let outstanding = engine
+        .shutdown_debt
+        .saturating_sub(settlement.cashed)
+        .max(unclaimed);
+    let payout = (unclaimed as u128)
+        .checked_mul(settlement.pool as u128)
+        .ok_or(ErrorCode::MathOverflow)?
+        .checked_div(outstanding as u128)
+        .ok_or(ErrorCode::DivisionError)? as u64;
+
+    claim.cashed = ctx.accounts.bag.packed;
+    settlement.cashed = settlement.cashed.saturating_add(unclaimed);
+    settlement.pool -= payout;
+
+    if payout > 0 {
+        send_from_vault(
+            &ctx.accounts.config,
+            &ctx.accounts.vault,
+            &ctx.accounts.user_token_account,
+            &ctx.accounts.token_mint,
+            &ctx.accounts.token_program,
+            payout,
+        )?;
+    }
+
+    emit!(SettlementCashed {
+        user: ctx.accounts.user.key(),
+        token: ctx.accounts.token_mint.key(),
+        dsc_amount: unclaimed,
+        collateral: payout,
+    });
+
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct EmergencyShutdown<'info> {
+    #[account(
+        seeds = [b"roles", engine.key().as_ref()],
+        bump = roles.bump,
+        has_one = admin @ ErrorCode::UnauthorizedUser
+    )]
+    pub roles: Account<'info, Roles>,
+
+    pub admin: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+}
+
+#[derive(Accounts)]
+pub struct FreezeCollateral<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump
+    )]
+    pub price: Account<'info, Price>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = 8 + Settlement::INIT_SPACE,
+        seeds = [b"settlement", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub settlement: Account<'info, Settlement>,
+
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SettlePosition<'info> {
+    #[account(
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        has_one = token_mint,
+        seeds = [b"settlement", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = settlement.bump
+    )]
+    pub settlement: Account<'info, Settlement>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump
+    )]
+    pub price: Account<'info, Price>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"user",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    #[account(
+        mut,
+        seeds = [
+            b"deposit",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = deposit.bump
+    )]
+    pub deposit: Account<'info, Deposit>,
+
+    #[account(
+        mut,
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = position_index.load()?.bump,
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+}
+
+#[derive(Accounts)]
+pub struct ReclaimCollateral<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    /// CHECK: the key the position was opened under; only used as a seed.
+    pub owner: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"user", engine.key().as_ref(), owner.key().as_ref(), token_mint.key().as_ref()],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    #[account(
+        mut,
+        has_one = token_mint,
+        constraint = deposit.user == owner.key() @ ErrorCode::UnauthorizedUser,
+        seeds = [b"deposit", engine.key().as_ref(), owner.key().as_ref(), token_mint.key().as_ref()],
+        bump = deposit.bump
+    )]
+    pub deposit: Account<'info, Deposit>,
+
+    pub position_nft: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = config,
+        associated_token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = token_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct PackDSC<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mut, address = engine.dsc_mint)]
+    pub dsc_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        associated_token::mint = dsc_mint,
+        associated_token::authority = user,
+        associated_token::token_program = dsc_token_program
+    )]
+    pub user_dsc_account: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + SettlementBag::INIT_SPACE,
+        seeds = [b"bag", engine.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub bag: Account<'info, SettlementBag>,
+
+    pub system_program: Program<'info, System>,
+    pub dsc_token_program: Program<'info, Token2022>,
+}
+
+#[derive(Accounts)]
+pub struct CashCollateral<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        has_one = token_mint,
+        seeds = [b"settlement", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = settlement.bump
+    )]
+    pub settlement: Account<'info, Settlement>,
+
+    #[account(
+        has_one = user,
+        seeds = [b"bag", engine.key().as_ref(), user.key().as_ref()],
+        bump = bag.bump
+    )]
+    pub bag: Account<'info, SettlementBag>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + CashClaim::INIT_SPACE,
+        seeds = [b"cash", engine.key().as_ref(), user.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub claim: Account<'info, CashClaim>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = config,
+        associated_token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = user,
+        associated_token::mint = token_mint,
+        associated_token::authority = user,
+        associated_token::token_program = token_program
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
@@ -0,0 +1,118 @@
+use crate::constants::*;
+use crate::engine::Liquidate;
+use crate::errors::ErrorCode;
+use crate::pricefeeds::*;
+use crate::redemption::sync_position;
+use crate::repay::burn_debt;
+use crate::shared::{bps_of, send_from_vault};
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+
+/// Repays a slice of a position sitting just above the liquidation line, in
+/// exchange for its collateral at `soft_liquidation_discount_bps` below the
+/// stored price. The slice may grow the deeper the position is in the band,
+/// up to `soft_liquidation_max_bps` of the debt at its bottom edge. Like full
+/// liquidation it stays open while the protocol is paused.
+pub fn soft_liquidate(ctx: &mut Context<Liquidate>, debt_to_cover: u64) -> Result<()> {
+    require!(!ctx.accounts.engine.shutdown, ErrorCode::ShutdownActive);
+    require!(
+        ctx.accounts.engine.soft_liquidation_band > 0,
+        ErrorCode::SoftLiquidationDisabled
+    );
+    require!(
+        ctx.accounts.liquidator.key() != ctx.accounts.user_data.user,
+        ErrorCode::CannotLiquidateSelf
+    );
+
+    let accounts = &mut ctx.accounts;
+    let engine = &accounts.engine;
+    let debt = accounts.user_data.borrowed_amount;
+    let hf = calculate_health_factor(accounts.deposit.token_amt, debt, &accounts.price, engine)?;
+    let band_top = engine
+        .min_health_factor
+        .saturating_add(engine.soft_liquidation_band);
+    require!(
+        debt > 0 && hf >= engine.min_health_factor && hf < band_top,
+        ErrorCode::NotInSoftLiquidationBand
+    );
+
+    let cap = soft_liquidation_cap(engine, debt, hf)?;
+    require!(
+        debt_to_cover > 0 && debt_to_cover <= cap,
+        ErrorCode::SoftLiquidationCapExceeded
+    );
+
+    let base = convert_dsc_to_collateral(debt_to_cover, &accounts.price)?;
+    let seized = base
+        .checked_add(bps_of(base, engine.soft_liquidation_discount_bps)?)
+        .ok_or(ErrorCode::Overflow)?;
+    require!(
+        accounts.deposit.token_amt >= seized,
+        ErrorCode::NotEnoughTokensInCollateral
+    );
+
+    burn_debt(
+        &mut accounts.user_data,
+        &accounts.dsc_mint,
+        &accounts.liquidator_dsc_account,
+        &accounts.liquidator.to_account_info(),
+        &[],
+        &accounts.dsc_token_program,
+        accounts.token_mint.key(),
+        debt_to_cover,
+    )?;
+
+    send_from_vault(
+        &accounts.config,
+        &accounts.vault,
+        &accounts.liquidator_token_account,
+        &accounts.token_mint,
+        &accounts.token_program,
+        seized,
+    )?;
+    accounts.deposit.token_amt -= seized;
+    accounts.user_data.token_balance = accounts.user_data.token_balance.saturating_sub(seized);
+
+    let remaining_debt = accounts.user_data.borrowed_amount;
+    let hf_after = calculate_health_factor(
+        accounts.deposit.token_amt,
+        remaining_debt,
+        &accounts.price,
+        &accounts.engine,
+    )?;
+    require!(hf_after >= hf, ErrorCode::LessHealthFactor);
+
+    sync_position(
+        &accounts.position_index,
+        &mut accounts.engine,
+        &accounts.price,
+        &mut accounts.user_data,
+        accounts.deposit.token_amt,
+        remaining_debt,
+    )?;
+
+    emit!(SoftLiquidated {
+        liquidator: accounts.liquidator.key(),
+        user: accounts.user_data.user,
+        token: accounts.token_mint.key(),
+        debt_repaid: debt_to_cover,
+        collateral_seized: seized,
+    });
+
+    Ok(())
+}
+
+/// Most DSC one call may repay at health factor `hf`: zero at the top of the
+/// band, rising linearly to `soft_liquidation_max_bps` of `debt` at
+/// `min_health_factor`.
+pub fn soft_liquidation_cap(engine: &Engine, debt: u64, hf: u64) -> Result<u64> {
+    let band = engine.soft_liquidation_band as u128;
+    let depth = (engine.min_health_factor as u128 + band).saturating_sub(hf as u128);
+    let cap = (debt as u128)
+        .checked_mul(engine.soft_liquidation_max_bps as u128)
+        .and_then(|v| v.checked_mul(depth.min(band)))
+        .ok_or(ErrorCode::MathOverflow)?
+        / (BPS_DENOMINATOR as u128 * band);
+    Ok(cap as u64)
+}
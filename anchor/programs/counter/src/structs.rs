@@ -1,14 +1,73 @@
+use crate::constants::{MAX_COLLATERALS, MAX_INDEXED_POSITIONS, MAX_MULTISIG_SIGNERS};
+use crate::governance::GovernanceAction;
+use crate::multisig::MultisigAction;
 use anchor_lang::prelude::*;
 
 #[account]
 #[derive(InitSpace)]
 pub struct Engine {
-    pub authority: Pubkey,
+    pub market_id: u64,
     pub dsc_mint: Pubkey,
     pub liquidation_threshold: u64,
     pub min_health_factor: u64,
     pub liquidation_bonus: u64,
     pub fee_percent: u64,
+    pub flash_mint_cap: u64,
+    pub flash_mint_fee_bps: u64,
+    pub redemption_base_rate: u64,
+    pub last_redemption_ts: i64,
+    pub liquidation_insurance_bps: u64,
+    pub swap_program: Pubkey,
+    /// Width of the health factor band above `min_health_factor` where soft
+    /// liquidation is allowed. Zero turns it off.
+    pub soft_liquidation_band: u64,
+    pub soft_liquidation_discount_bps: u64,
+    pub soft_liquidation_max_bps: u64,
+    /// System collateral ratio, in bps, below which the engine enters
+    /// recovery mode. Zero turns recovery mode off.
+    pub critical_collateral_ratio: u64,
+    /// Value, in DSC, of collateral backing debt, and that debt. Moved by
+    /// every position sync and recomputed by `update_system_totals`.
+    pub system_collateral_value: u64,
+    pub system_debt: u64,
+    pub recovery_mode: bool,
+    /// Next registry entry `update_system_totals` expects, and what it has
+    /// summed so far in the current pass.
+    pub totals_cursor: u16,
+    pub pending_collateral_value: u64,
+    pub pending_debt: u64,
+    pub timelock_delay: i64,
+    pub action_count: u64,
+    /// While set, nothing that adds risk goes through: deposits, mints,
+    /// flash mints, PSM swap-ins, redemptions and opening leverage. Repaying,
+    /// withdrawing, liquidations, soft liquidations, protection orders and
+    /// deleveraging stay open so positions can still be made safer.
+    pub paused: bool,
+    pub multisig_signers: [Pubkey; MAX_MULTISIG_SIGNERS],
+    pub multisig_signer_count: u8,
+    pub multisig_threshold: u8,
+    pub multisig_nonce: u64,
+    pub proposal_count: u64,
+    pub shutdown: bool,
+    pub shutdown_ts: i64,
+    /// DSC backed by collateral at shutdown; PSM-minted DSC is left out as
+    /// it stays redeemable against the PSM reserves.
+    pub shutdown_debt: u64,
+    /// DSC minted through the PSM and not yet swapped back, across assets.
+    pub psm_debt: u64,
+    pub bump: u8,
+}
+
+/// Holders of the protocol's privileged roles. Each admin instruction checks
+/// the one role it needs.
+#[account]
+#[derive(InitSpace)]
+pub struct Roles {
+    pub admin: Pubkey,
+    pub risk_manager: Pubkey,
+    pub oracle_updater: Pubkey,
+    pub guardian: Pubkey,
+    pub fee_collector: Pubkey,
     pub bump: u8,
 }
 
@@ -35,11 +94,22 @@ pub struct Deposit {
 #[account]
 #[derive(InitSpace)]
 pub struct Config {
+    pub engine: Pubkey,
     pub token_mint: Pubkey,
     pub total_liq: u64,
     pub total_collected: u64,
+    pub lp_fee_bps: u64,
+    pub treasury_fee_bps: u64,
+    pub insurance_fee_bps: u64,
+    pub treasury_collected: u64,
+    pub insurance_collected: u64,
+    /// Share of fees, in bps, that tops up the DSC savings buffer.
+    pub savings_fee_bps: u64,
+    /// Collateral set aside for savings, waiting to be sold for DSC.
+    pub savings_collected: u64,
+    pub status: u8,
+    pub oracle: Pubkey,
     pub vault: Pubkey,
-    pub authority: Pubkey,
     pub bump: u8,
 }
 
@@ -49,6 +119,8 @@ pub struct Price {
     pub token_mint: Pubkey,
     pub price: u64,
     pub bump: u8,
+    /// Unix time of the last oracle write.
+    pub updated_at: i64,
 }
 
 #[account]
@@ -58,8 +130,14 @@ pub struct UserData {
     pub borrowed_amount: u64,
     pub primary_token: Pubkey,
     pub hf: u64,
+    pub hf_slot: u64,
     pub token_balance: u64,
     pub bump: u8,
+    /// Set once the position is tokenized; its holder then controls it.
+    pub position_mint: Pubkey,
+    /// What this position last contributed to its `PositionIndex` totals.
+    pub synced_collateral: u64,
+    pub synced_debt: u64,
 }
 
 #[account]
@@ -71,6 +149,192 @@ pub struct LpData {
     pub bump: u8,
 }
 
+#[account]
+#[derive(InitSpace)]
+pub struct PsmAsset {
+    pub stable_mint: Pubkey,
+    pub vault: Pubkey,
+    pub fee_in_bps: u64,
+    pub fee_out_bps: u64,
+    pub cap: u64,
+    pub dsc_minted: u64,
+    /// Swap fees, in the stablecoin, held in the vault on top of the
+    /// reserves backing `dsc_minted`.
+    pub fees_collected: u64,
+    pub bump: u8,
+}
+
+/// The riskiest indebted positions of one collateral token, kept sorted by
+/// ascending collateral/debt ratio so the riskiest position is always first.
+/// Once full, safer positions stay out of `entries` but still count towards
+/// the totals.
+#[account(zero_copy)]
+pub struct PositionIndex {
+    pub token_mint: Pubkey,
+    pub count: u32,
+    pub bump: u8,
+    pub _padding: [u8; 3],
+    /// Sums over every indebted position, kept in step by `sync_position`.
+    pub total_collateral: u64,
+    pub total_debt: u64,
+    pub entries: [IndexedPosition; MAX_INDEXED_POSITIONS],
+}
+
+#[zero_copy]
+pub struct IndexedPosition {
+    pub user: Pubkey,
+    pub collateral: u64,
+    pub debt: u64,
+}
+
+/// Every collateral mint listed through `initialize_token`, in listing order,
+/// with its `COLLATERAL_*` status.
+#[account(zero_copy)]
+pub struct CollateralRegistry {
+    pub count: u32,
+    pub bump: u8,
+    pub _padding: [u8; 3],
+    pub entries: [RegisteredCollateral; MAX_COLLATERALS],
+}
+
+#[zero_copy]
+pub struct RegisteredCollateral {
+    pub mint: Pubkey,
+    pub status: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct DscSavings {
+    pub vault: Pubkey,
+    pub chi: u128,
+    pub rate_bps: u64,
+    pub rho: i64,
+    pub total_shares: u64,
+    pub buffer: u64,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct SavingsPosition {
+    pub user: Pubkey,
+    pub shares: u64,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct InsuranceFund {
+    pub engine: Pubkey,
+    pub token_mint: Pubkey,
+    pub vault: Pubkey,
+    pub balance: u64,
+    pub bump: u8,
+}
+
+/// Holds the treasury's share of a token's fees outside the collateral vault
+/// until the fee collector takes it.
+#[account]
+#[derive(InitSpace)]
+pub struct Treasury {
+    pub engine: Pubkey,
+    pub token_mint: Pubkey,
+    pub vault: Pubkey,
+    pub balance: u64,
+    pub bump: u8,
+}
+
+/// A governance change queued by the admin. Anyone can execute it once
+/// `eta` has passed; the guardian can cancel it before then.
+#[account]
+#[derive(InitSpace)]
+pub struct PendingAction {
+    pub id: u64,
+    pub proposer: Pubkey,
+    pub action: GovernanceAction,
+    pub eta: i64,
+    pub bump: u8,
+}
+
+/// A critical action awaiting approval from the engine's signer set.
+/// `approvals` is a bitmask over `Engine::multisig_signers`.
+#[account]
+#[derive(InitSpace)]
+pub struct MultisigProposal {
+    pub id: u64,
+    pub proposer: Pubkey,
+    pub action: MultisigAction,
+    pub approvals: u8,
+    pub nonce: u64,
+    pub bump: u8,
+}
+
+/// Collateral of one token taken from settled positions after shutdown,
+/// paid out pro rata to packed DSC.
+#[account]
+#[derive(InitSpace)]
+pub struct Settlement {
+    pub token_mint: Pubkey,
+    pub price: u64,
+    pub pool: u64,
+    /// Packed DSC already cashed out against this token.
+    pub cashed: u64,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct SettlementBag {
+    pub user: Pubkey,
+    pub packed: u64,
+    pub bump: u8,
+}
+
+#[account]
+#[derive(InitSpace)]
+pub struct CashClaim {
+    pub user: Pubkey,
+    pub token_mint: Pubkey,
+    pub cashed: u64,
+    pub bump: u8,
+}
+
+/// An operator allowed to act on one position within `permissions`. DSC it
+/// mints and collateral it withdraws always go to the owner.
+#[account]
+#[derive(InitSpace)]
+pub struct Delegation {
+    pub owner: Pubkey,
+    pub position: Pubkey,
+    pub token_mint: Pubkey,
+    pub delegate: Pubkey,
+    pub permissions: u8,
+    pub mint_limit: u64,
+    pub minted: u64,
+    pub bump: u8,
+}
+
+/// A keeper-executable stop-loss on one position. It owns escrow token
+/// accounts for DSC and collateral and holds `tip` lamports for the keeper.
+#[account]
+#[derive(InitSpace)]
+pub struct ProtectionOrder {
+    pub owner: Pubkey,
+    /// The position's `UserData` account.
+    pub position: Pubkey,
+    pub token_mint: Pubkey,
+    pub action: u8,
+    pub trigger_hf: u64,
+    /// DSC to repay or collateral to sell, depending on `action`.
+    pub amount: u64,
+    /// Minimum DSC out of the swap for sell orders.
+    pub min_out: u64,
+    pub tip: u64,
+    pub active: bool,
+    pub bump: u8,
+}
+
 // Events
 #[event]
 pub struct TokenDeposited {
@@ -86,6 +350,84 @@ pub struct LiquidityProvided {
     pub amount: u64,
 }
 
+#[event]
+pub struct DelegateSet {
+    pub owner: Pubkey,
+    pub position: Pubkey,
+    pub token: Pubkey,
+    pub delegate: Pubkey,
+    pub permissions: u8,
+    pub mint_limit: u64,
+}
+
+#[event]
+pub struct PositionTokenized {
+    pub user: Pubkey,
+    pub token: Pubkey,
+    pub position_mint: Pubkey,
+}
+
+#[event]
+pub struct OrderPlaced {
+    pub owner: Pubkey,
+    pub user: Pubkey,
+    pub token: Pubkey,
+    pub action: u8,
+    pub trigger_hf: u64,
+    pub amount: u64,
+}
+
+#[event]
+pub struct OrderExecuted {
+    pub keeper: Pubkey,
+    pub user: Pubkey,
+    pub token: Pubkey,
+    pub action: u8,
+    pub dsc_repaid: u64,
+    pub collateral_sold: u64,
+    pub tip: u64,
+}
+
+#[event]
+pub struct OrderDeactivated {
+    pub owner: Pubkey,
+    pub user: Pubkey,
+    pub token: Pubkey,
+}
+
+#[event]
+pub struct SoftLiquidated {
+    pub liquidator: Pubkey,
+    pub user: Pubkey,
+    pub token: Pubkey,
+    pub debt_repaid: u64,
+    pub collateral_seized: u64,
+}
+
+#[event]
+pub struct SystemTotalsUpdated {
+    pub collateral_value: u64,
+    pub debt: u64,
+    pub recovery_mode: bool,
+}
+
+#[event]
+pub struct DepositedOnBehalf {
+    pub payer: Pubkey,
+    pub beneficiary: Pubkey,
+    pub token: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct DscRepaid {
+    pub payer: Pubkey,
+    pub user: Pubkey,
+    pub token: Pubkey,
+    pub amount: u64,
+    pub remaining_debt: u64,
+}
+
 #[event]
 pub struct TokenRedeemed {
     pub user: Pubkey,
@@ -110,5 +452,169 @@ pub struct LiquidityRedeemed {
 
 #[event]
 pub struct HealthFactors {
+    pub user: Pubkey,
+    pub token: Pubkey,
     pub health_factor: u64,
+    pub slot: u64,
+}
+
+#[event]
+pub struct FlashMinted {
+    pub receiver: Pubkey,
+    pub callback_program: Pubkey,
+    pub amount: u64,
+    pub fee: u64,
+}
+
+#[event]
+pub struct PsmSwapped {
+    pub user: Pubkey,
+    pub stable_mint: Pubkey,
+    pub stable_amount: u64,
+    pub dsc_amount: u64,
+    pub fee: u64,
+    pub swap_in: bool,
+}
+
+#[event]
+pub struct DscRedeemed {
+    pub redeemer: Pubkey,
+    pub token: Pubkey,
+    pub dsc_amount: u64,
+    pub collateral_amount: u64,
+    pub fee: u64,
+}
+
+#[event]
+pub struct SavingsFeesSwept {
+    pub buyer: Pubkey,
+    pub token: Pubkey,
+    pub collateral: u64,
+    pub dsc_amount: u64,
+}
+
+#[event]
+pub struct SavingsLocked {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+}
+
+#[event]
+pub struct SavingsUnlocked {
+    pub user: Pubkey,
+    pub amount: u64,
+    pub shares: u64,
+}
+
+#[event]
+pub struct ProtocolFeesCollected {
+    pub token: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+}
+
+#[event]
+pub struct InsuranceFundDeposited {
+    pub token: Pubkey,
+    pub amount: u64,
+    pub balance: u64,
+}
+
+#[event]
+pub struct LeverageChanged {
+    pub user: Pubkey,
+    pub token: Pubkey,
+    pub dsc_amount: u64,
+    pub collateral_amount: u64,
+    pub opened: bool,
+}
+
+#[event]
+pub struct InsuranceFundWithdrawn {
+    pub token: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub balance: u64,
+    pub bad_debt: bool,
+}
+
+#[event]
+pub struct ActionQueued {
+    pub id: u64,
+    pub action: GovernanceAction,
+    pub eta: i64,
+}
+
+#[event]
+pub struct ActionExecuted {
+    pub id: u64,
+    pub executor: Pubkey,
+}
+
+#[event]
+pub struct ActionCancelled {
+    pub id: u64,
+    pub guardian: Pubkey,
+}
+
+#[event]
+pub struct PriceUpdated {
+    pub token: Pubkey,
+    pub price: u64,
+}
+
+#[event]
+pub struct PauseChanged {
+    pub by: Pubkey,
+    pub paused: bool,
+}
+
+#[event]
+pub struct MultisigProposed {
+    pub id: u64,
+    pub proposer: Pubkey,
+    pub action: MultisigAction,
+}
+
+#[event]
+pub struct MultisigApproved {
+    pub id: u64,
+    pub signer: Pubkey,
+    pub approvals: u8,
+}
+
+#[event]
+pub struct MultisigExecuted {
+    pub id: u64,
+    pub executor: Pubkey,
+}
+
+#[event]
+pub struct ShutdownTriggered {
+    pub by: Pubkey,
+    pub dsc_supply: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PositionSettled {
+    pub user: Pubkey,
+    pub token: Pubkey,
+    pub debt: u64,
+    pub collateral: u64,
+}
+
+#[event]
+pub struct SettlementCashed {
+    pub user: Pubkey,
+    pub token: Pubkey,
+    pub dsc_amount: u64,
+    pub collateral: u64,
+}
+
+#[event]
+pub struct CollateralStatusChanged {
+    pub token: Pubkey,
+    pub status: u8,
 }
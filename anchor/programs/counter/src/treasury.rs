@@ -0,0 +1,269 @@
+use crate::constants::*;
+use crate::errors::ErrorCode;
+use crate::shared::send_from_vault;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{transfer_checked, Mint, TokenAccount, TokenInterface, TransferChecked},
+};
+
+pub fn init_treasury(ctx: Context<InitTreasury>) -> Result<()> {
+    let treasury = &mut ctx.accounts.treasury;
+    treasury.engine = ctx.accounts.engine.key();
+    treasury.token_mint = ctx.accounts.token_mint.key();
+    treasury.vault = ctx.accounts.treasury_vault.key();
+    treasury.balance = 0;
+    treasury.bump = ctx.bumps.treasury;
+    Ok(())
+}
+
+/// Moves the treasury share of collected fees out of the collateral vault
+/// into the token's treasury. Permissionless.
+pub fn sweep_treasury_fees(ctx: Context<SweepTreasuryFees>) -> Result<()> {
+    let amount = ctx.accounts.config.treasury_collected;
+    require!(amount > 0, ErrorCode::NothingToCollect);
+
+    send_from_vault(
+        &ctx.accounts.config,
+        &ctx.accounts.vault,
+        &ctx.accounts.treasury_vault,
+        &ctx.accounts.token_mint,
+        &ctx.accounts.token_program,
+        amount,
+    )?;
+
+    ctx.accounts.config.treasury_collected = 0;
+    let treasury = &mut ctx.accounts.treasury;
+    treasury.balance = treasury
+        .balance
+        .checked_add(amount)
+        .ok_or(ErrorCode::Overflow)?;
+    Ok(())
+}
+
+/// Sends the token's treasury balance to the fee collector's token account
+/// for this mint. Once a multisig is configured the payout has to go through
+/// a proposal instead.
+pub fn collect_protocol_fees(ctx: Context<CollectProtocolFees>) -> Result<()> {
+    require!(
+        ctx.accounts.engine.multisig_threshold == 0,
+        ErrorCode::MultisigRequired
+    );
+
+    sweep_treasury(
+        &mut ctx.accounts.treasury,
+        &ctx.accounts.treasury_vault,
+        &ctx.accounts.treasury_token_account,
+        &ctx.accounts.token_mint,
+        &ctx.accounts.token_program,
+    )
+}
+
+pub fn sweep_treasury<'info>(
+    treasury: &mut Account<'info, Treasury>,
+    treasury_vault: &InterfaceAccount<'info, TokenAccount>,
+    destination: &InterfaceAccount<'info, TokenAccount>,
+    token_mint: &InterfaceAccount<'info, Mint>,
+    token_program: &Interface<'info, TokenInterface>,
+) -> Result<()> {
+    let amount = treasury.balance;
+    require!(amount > 0, ErrorCode::NothingToCollect);
+
+    let seeds = &[
+        b"treasury",
+        treasury.engine.as_ref(),
+        treasury.token_mint.as_ref(),
+        &[treasury.bump],
+    ];
+    let signer_seeds = &[&seeds[..]];
+
+    let transfer_ctx = CpiContext::new_with_signer(
+        token_program.to_account_info(),
+        TransferChecked {
+            from: treasury_vault.to_account_info(),
+            to: destination.to_account_info(),
+            mint: token_mint.to_account_info(),
+            authority: treasury.to_account_info(),
+        },
+        signer_seeds,
+    );
+    transfer_checked(transfer_ctx, amount, token_mint.decimals)?;
+
+    treasury.balance = 0;
+
+    emit!(ProtocolFeesCollected {
+        token: token_mint.key(),
+        destination: destination.key(),
+        amount,
+    });
+
+    Ok(())
+}
+
+/// Splits a collateral fee that stayed in the vault between LPs, the
+/// treasury, the insurance reserve and DSC savings. Rounding dust goes to LPs.
+pub fn accrue_fee(config: &mut Config, fee: u64) -> Result<()> {
+    let treasury = (fee as u128 * config.treasury_fee_bps as u128 / BPS_DENOMINATOR as u128) as u64;
+    let insurance =
+        (fee as u128 * config.insurance_fee_bps as u128 / BPS_DENOMINATOR as u128) as u64;
+    let savings = (fee as u128 * config.savings_fee_bps as u128 / BPS_DENOMINATOR as u128) as u64;
+    let lp = fee - treasury - insurance - savings;
+
+    config.total_collected = config
+        .total_collected
+        .checked_add(lp)
+        .ok_or(ErrorCode::Overflow)?;
+    config.treasury_collected = config
+        .treasury_collected
+        .checked_add(treasury)
+        .ok_or(ErrorCode::Overflow)?;
+    config.insurance_collected = config
+        .insurance_collected
+        .checked_add(insurance)
+        .ok_or(ErrorCode::Overflow)?;
+    config.savings_collected = config
+        .savings_collected
+        .checked_add(savings)
+        .ok_or(ErrorCode::Overflow)?;
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct InitTreasury<'info> {
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(
+        seeds = [b"roles", engine.key().as_ref()],
+        bump = roles.bump,
+        has_one = admin @ ErrorCode::UnauthorizedUser
+    )]
+    pub roles: Account<'info, Roles>,
+
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        init,
+        payer = admin,
+        space = 8 + Treasury::INIT_SPACE,
+        seeds = [b"treasury", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(
+        init,
+        payer = admin,
+        associated_token::mint = token_mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program
+    )]
+    pub treasury_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+pub struct SweepTreasuryFees<'info> {
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        has_one = token_mint,
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = config.bump
+    )]
+    pub config: Account<'info, Config>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = config,
+        associated_token::token_program = token_program
+    )]
+    pub vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        has_one = token_mint,
+        seeds = [b"treasury", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program
+    )]
+    pub treasury_vault: InterfaceAccount<'info, TokenAccount>,
+
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct CollectProtocolFees<'info> {
+    #[account(seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()], bump = engine.bump)]
+    pub engine: Account<'info, Engine>,
+
+    #[account(
+        seeds = [b"roles", engine.key().as_ref()],
+        bump = roles.bump,
+        has_one = fee_collector @ ErrorCode::UnauthorizedUser
+    )]
+    pub roles: Account<'info, Roles>,
+
+    #[account(mut)]
+    pub fee_collector: Signer<'info>,
+
+    #[account(mint::token_program = token_program)]
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        mut,
+        has_one = token_mint,
+        seeds = [b"treasury", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = treasury.bump
+    )]
+    pub treasury: Account<'info, Treasury>,
+
+    #[account(
+        mut,
+        associated_token::mint = token_mint,
+        associated_token::authority = treasury,
+        associated_token::token_program = token_program
+    )]
+    pub treasury_vault: InterfaceAccount<'info, TokenAccount>,
+
+    #[account(
+        init_if_needed,
+        payer = fee_collector,
+        associated_token::mint = token_mint,
+        associated_token::authority = fee_collector,
+        associated_token::token_program = token_program
+    )]
+    pub treasury_token_account: InterfaceAccount<'info, TokenAccount>,
+
+    pub system_program: Program<'info, System>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
@@ -0,0 +1,155 @@
+use crate::errors::ErrorCode;
+use crate::pricefeeds::*;
+use crate::structs::*;
+
+use anchor_lang::prelude::*;
+use anchor_spl::token_interface::Mint;
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct PositionView {
+    pub health_factor: u64,
+    /// Extra DSC that can be minted before the health factor drops below the
+    /// engine minimum.
+    pub max_mintable: u64,
+    /// Collateral that can leave the position while keeping the minimum.
+    pub max_withdrawable: u64,
+    /// Lowest price at which the position is not liquidatable. Zero without
+    /// debt, `u64::MAX` when no price saves it.
+    pub liquidation_price: u64,
+}
+
+/// Answers "what can this position still do" at the stored price. Meant for
+/// `simulateTransaction`; nothing is written.
+pub fn position_view(ctx: Context<ViewPosition>) -> Result<PositionView> {
+    let engine = &ctx.accounts.engine;
+    let price = &ctx.accounts.price;
+    let collateral = ctx.accounts.deposit.token_amt;
+    let debt = ctx.accounts.user_data.borrowed_amount;
+    let healthy = |collateral: u64, debt: u64, price: &Price| -> Result<bool> {
+        Ok(calculate_health_factor(collateral, debt, price, engine)? >= engine.min_health_factor)
+    };
+
+    let healthy_now = healthy(collateral, debt, price)?;
+
+    let max_mintable = if healthy_now {
+        last_passing(u64::MAX - debt, |extra| {
+            healthy(collateral, debt + extra, price)
+        })?
+    } else {
+        0
+    };
+
+    let max_withdrawable = if healthy_now {
+        last_passing(collateral, |amount| {
+            healthy(collateral - amount, debt, price)
+        })?
+    } else {
+        0
+    };
+
+    let liquidation_price = if debt == 0 {
+        0
+    } else {
+        // Bound the search so the collateral valuation cannot overflow.
+        let max_price = u64::MAX / collateral.max(1) / engine.liquidation_threshold.max(1);
+        let at = |p: u64| Price {
+            token_mint: price.token_mint,
+            price: p,
+            bump: price.bump,
+            updated_at: price.updated_at,
+        };
+        if healthy(collateral, debt, &at(max_price))? {
+            max_price
+                - last_passing(max_price, |drop| {
+                    healthy(collateral, debt, &at(max_price - drop))
+                })?
+        } else {
+            u64::MAX
+        }
+    };
+
+    Ok(PositionView {
+        health_factor: calculate_health_factor(collateral, debt, price, engine)?,
+        max_mintable,
+        max_withdrawable,
+        liquidation_price,
+    })
+}
+
+/// Health factor the position would have after adding `collateral_delta`
+/// tokens and `debt_delta` DSC (either may be negative).
+pub fn simulate_health_factor(
+    ctx: Context<ViewPosition>,
+    collateral_delta: i64,
+    debt_delta: i64,
+) -> Result<u64> {
+    let collateral = ctx
+        .accounts
+        .deposit
+        .token_amt
+        .checked_add_signed(collateral_delta)
+        .ok_or(ErrorCode::MathOverflow)?;
+    let debt = ctx
+        .accounts
+        .user_data
+        .borrowed_amount
+        .checked_add_signed(debt_delta)
+        .ok_or(ErrorCode::MathOverflow)?;
+
+    calculate_health_factor(collateral, debt, &ctx.accounts.price, &ctx.accounts.engine)
+}
+
+/// Largest `x` in `0..=max` for which `pass` holds, given that `pass(0)` holds
+/// and `pass` only flips from true to false as `x` grows.
+fn last_passing(max: u64, pass: impl Fn(u64) -> Result<bool>) -> Result<u64> {
+    let (mut lo, mut hi) = (0u64, max);
+    while lo < hi {
+        let mid = lo + (hi - lo).div_ceil(2);
+        if pass(mid)? {
+            lo = mid;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    Ok(lo)
+}
+
+#[derive(Accounts)]
+pub struct ViewPosition<'info> {
+    #[account(
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
+        bump = engine.bump
+    )]
+    pub engine: Account<'info, Engine>,
+
+    pub token_mint: InterfaceAccount<'info, Mint>,
+
+    #[account(
+        seeds = [
+            b"user",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = user_data.bump
+    )]
+    pub user_data: Account<'info, UserData>,
+
+    #[account(
+        seeds = [
+            b"deposit",
+            engine.key().as_ref(),
+            user_data.user.as_ref(),
+            token_mint.key().as_ref(),
+        ],
+        bump = deposit.bump
+    )]
+    pub deposit: Account<'info, Deposit>,
+
+    #[account(
+        has_one = token_mint,
+        seeds = [b"price", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = price.bump
+    )]
+    pub price: Account<'info, Price>,
+}
@@ -1,48 +1,64 @@
+use crate::constants::*;
+use crate::delegation::authorize;
 use crate::errors::ErrorCode;
+use crate::position_nft::position_controller;
 use crate::pricefeeds::*;
+use crate::redemption::sync_position;
 use crate::structs::*;
+use crate::treasury::accrue_fee;
 
 use anchor_lang::prelude::*;
 use anchor_spl::{
     associated_token::AssociatedToken,
+    token_2022::Token2022,
     token_interface::{burn, transfer_checked, Burn, Mint, TokenAccount, TokenInterface},
 };
 
-pub fn redeem_collateral(
-    mut ctx: &mut Context<WithdrawToken>,
-    dsc_to_give: u64,
-    new_price: u64,
-) -> Result<()> {
+pub fn redeem_collateral(mut ctx: &mut Context<WithdrawToken>, dsc_to_give: u64) -> Result<()> {
     // msg!("1");
     require_keys_eq!(
         ctx.accounts.deposit.user.key(),
-        ctx.accounts.user.key(),
+        ctx.accounts.owner.key(),
         ErrorCode::UnauthorizedUser
     );
+    let permissions = if dsc_to_give == 0 {
+        DELEGATE_WITHDRAW
+    } else {
+        DELEGATE_REPAY | DELEGATE_WITHDRAW
+    };
+    authorize_withdrawal(ctx, permissions)?;
     require!(
         ctx.accounts.deposit.token_amt > 0,
         ErrorCode::NotEnoughTokensInCollateral
     );
-
-    let price = &mut ctx.accounts.price;
-    price.price = new_price;
+    require!(!ctx.accounts.engine.shutdown, ErrorCode::ShutdownActive);
 
     let user_data = &mut ctx.accounts.user_data;
     require!(
         user_data.user.key() != Pubkey::default(),
         ErrorCode::UnauthorizedUser
     );
-    let amt = user_data.token_balance;
 
     if dsc_to_give == 0 {
         require!(user_data.borrowed_amount == 0, ErrorCode::MustRepayDscFirst);
 
+        // The deposit record is what every other path draws on, so it is
+        // what gets paid out and cleared here.
+        let amt = ctx.accounts.deposit.token_amt;
         send_tokens_to_user(&mut ctx, amt)?;
-        // ctx.accounts.deposit.token_amt = 0;
+        ctx.accounts.deposit.token_amt = 0;
         ctx.accounts.user_data.token_balance = 0;
+        sync_position(
+            &ctx.accounts.position_index,
+            &mut ctx.accounts.engine,
+            &ctx.accounts.price,
+            &mut ctx.accounts.user_data,
+            0,
+            0,
+        )?;
 
         emit!(TokenRedeemed {
-            user: ctx.accounts.user.key(),
+            user: ctx.accounts.owner.key(),
             token: ctx.accounts.token_mint.key(),
             amount: amt,
         });
@@ -51,7 +67,7 @@ pub fn redeem_collateral(
     }
 
     let burn_ctx = CpiContext::new(
-        ctx.accounts.token_program.to_account_info(),
+        ctx.accounts.dsc_token_program.to_account_info(),
         Burn {
             mint: ctx.accounts.dsc_mint.to_account_info(),
             from: ctx.accounts.user_dsc_account.to_account_info(),
@@ -71,20 +87,21 @@ pub fn redeem_collateral(
         .unwrap();
     user_data.borrowed_amount = new_borrow;
 
+    let price = &ctx.accounts.price;
     let dsc_collateral_equiv = convert_dsc_to_collateral(dsc_burn_scaled, price)?;
     msg!("feeperc ,{}", ctx.accounts.engine.fee_percent);
     let fee = calculate_collateral_fee(dsc_burn_scaled, price, ctx.accounts.engine.fee_percent)?;
-    let liq_amt = fee * (3) / 4;
     msg!("dscequiv {}", dsc_collateral_equiv);
     msg!("fee ,{}", fee);
     let withdrawable_amt = dsc_collateral_equiv.checked_sub(fee).unwrap();
 
     require!(
-        ctx.accounts.deposit.token_amt >= withdrawable_amt as u64,
+        ctx.accounts.deposit.token_amt >= dsc_collateral_equiv,
         ErrorCode::NotEnoughTokensInCollateral
     );
 
-    let new_collateral = ctx.accounts.deposit.token_amt - (withdrawable_amt);
+    // The fee leaves the position too; it stays in the vault as protocol revenue.
+    let new_collateral = ctx.accounts.deposit.token_amt - dsc_collateral_equiv;
     let user_new = user_data.token_balance - dsc_collateral_equiv;
 
     if user_data.borrowed_amount > 0 {
@@ -104,15 +121,20 @@ pub fn redeem_collateral(
     send_tokens_to_user(&mut ctx, withdrawable_amt)?;
     ctx.accounts.deposit.token_amt = new_collateral;
     ctx.accounts.user_data.token_balance = user_new;
-    ctx.accounts.config.total_collected = ctx
-        .accounts
-        .config
-        .total_collected
-        .checked_add(liq_amt)
-        .unwrap();
+    accrue_fee(&mut ctx.accounts.config, fee)?;
+
+    let debt = ctx.accounts.user_data.borrowed_amount;
+    sync_position(
+        &ctx.accounts.position_index,
+        &mut ctx.accounts.engine,
+        &ctx.accounts.price,
+        &mut ctx.accounts.user_data,
+        new_collateral,
+        debt,
+    )?;
 
     emit!(TokenRedeemed {
-        user: ctx.accounts.user.key(),
+        user: ctx.accounts.owner.key(),
         token: ctx.accounts.token_mint.key(),
         amount: withdrawable_amt,
     });
@@ -120,15 +142,90 @@ pub fn redeem_collateral(
     Ok(())
 }
 
+/// Releases `amount` of collateral at the stored price, as long as the
+/// remaining position stays at or above the minimum health factor.
+pub fn withdraw_collateral_amount(ctx: &mut Context<WithdrawToken>, amount: u64) -> Result<()> {
+    require!(amount > 0, ErrorCode::AmountLessThanZero);
+    require!(!ctx.accounts.engine.shutdown, ErrorCode::ShutdownActive);
+    require!(
+        ctx.accounts.deposit.token_amt >= amount,
+        ErrorCode::NotEnoughTokensInCollateral
+    );
+    authorize_withdrawal(ctx, DELEGATE_WITHDRAW)?;
+
+    let new_collateral = ctx.accounts.deposit.token_amt - amount;
+    let debt = ctx.accounts.user_data.borrowed_amount;
+    if debt > 0 {
+        let hf = calculate_health_factor_after_withdrawal(
+            debt,
+            &ctx.accounts.price,
+            &ctx.accounts.engine,
+            new_collateral,
+        )?;
+        require!(
+            hf >= ctx.accounts.engine.min_health_factor,
+            ErrorCode::LessHealthFactor
+        );
+    }
+
+    send_tokens_to_user(ctx, amount)?;
+    ctx.accounts.deposit.token_amt = new_collateral;
+    ctx.accounts.user_data.token_balance =
+        ctx.accounts.user_data.token_balance.saturating_sub(amount);
+
+    sync_position(
+        &ctx.accounts.position_index,
+        &mut ctx.accounts.engine,
+        &ctx.accounts.price,
+        &mut ctx.accounts.user_data,
+        new_collateral,
+        debt,
+    )?;
+
+    emit!(TokenRedeemed {
+        user: ctx.accounts.owner.key(),
+        token: ctx.accounts.token_mint.key(),
+        amount,
+    });
+
+    Ok(())
+}
+
+/// Checks the signer may act on the position with `permissions` and that
+/// collateral is headed to whoever controls it.
+pub fn authorize_withdrawal(ctx: &Context<WithdrawToken>, permissions: u8) -> Result<()> {
+    let controller = position_controller(
+        &ctx.accounts.user_data,
+        ctx.accounts.position_nft.as_deref(),
+    )?;
+    authorize(
+        ctx.accounts.user.key(),
+        controller,
+        ctx.accounts.delegation.as_deref(),
+        permissions,
+    )?;
+    require_keys_eq!(
+        ctx.accounts.user_token_account.owner,
+        controller,
+        ErrorCode::NotPositionHolder
+    );
+    Ok(())
+}
+
 pub fn send_tokens_to_user(ctx: &mut Context<WithdrawToken>, amt_total: u64) -> Result<()> {
     let binding = ctx.accounts.token_mint.key();
-    let seeds = &[b"config", binding.as_ref(), &[ctx.accounts.config.bump]];
+    let seeds = &[
+        b"config",
+        ctx.accounts.config.engine.as_ref(),
+        binding.as_ref(),
+        &[ctx.accounts.config.bump],
+    ];
     let signer_seeds = &[&seeds[..]];
 
     let decimals = ctx.accounts.token_mint.decimals;
 
     msg!(
-        "Withdrawing {} tokens (decimals: {}) to user {}",
+        "Withdrawing {} tokens (decimals: {}) to owner {}",
         amt_total,
         decimals,
         ctx.accounts.user_token_account.key()
@@ -154,15 +251,19 @@ pub struct WithdrawToken<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
 
+    /// CHECK: the key the position was opened under; only used as a seed.
+    pub owner: UncheckedAccount<'info>,
+
     #[account(
         mut,
-        seeds = [b"user", user.key().as_ref(),token_mint.key().as_ref()],
+        seeds = [b"user", engine.key().as_ref(), owner.key().as_ref(),token_mint.key().as_ref()],
         bump = user_data.bump
     )]
     pub user_data: Account<'info, UserData>,
 
     #[account(
-        seeds = [b"engine"],
+        mut,
+        seeds = [b"engine", engine.market_id.to_le_bytes().as_ref()],
         bump = engine.bump
     )]
     pub engine: Account<'info, Engine>,
@@ -174,17 +275,24 @@ pub struct WithdrawToken<'info> {
 
     #[account(
         mut,
-        has_one = user,
         has_one = token_mint,
-        seeds = [b"deposit", user.key().as_ref(), token_mint.key().as_ref()],
+        constraint = deposit.user == owner.key() @ ErrorCode::UnauthorizedUser,
+        seeds = [b"deposit", engine.key().as_ref(), owner.key().as_ref(), token_mint.key().as_ref()],
         bump = deposit.bump,
     )]
     pub deposit: Account<'info, Deposit>,
 
     #[account(
-        mut,
+        seeds = [b"delegation", engine.key().as_ref(), user_data.key().as_ref()],
+        bump = delegation.bump
+    )]
+    pub delegation: Option<Account<'info, Delegation>>,
+
+    pub position_nft: Option<InterfaceAccount<'info, TokenAccount>>,
+
+    #[account(
         has_one = token_mint,
-        seeds = [b"price",token_mint.key().as_ref()],
+        seeds = [b"price", engine.key().as_ref(),token_mint.key().as_ref()],
         bump = price.bump,
     )]
     pub price: Account<'info, Price>,
@@ -192,11 +300,18 @@ pub struct WithdrawToken<'info> {
     #[account(
         mut,
         has_one = token_mint,
-        seeds = [b"config", token_mint.key().as_ref()],
+        seeds = [b"config", engine.key().as_ref(), token_mint.key().as_ref()],
         bump = config.bump
     )]
     pub config: Account<'info, Config>,
 
+    #[account(
+        mut,
+        seeds = [b"position_index", engine.key().as_ref(), token_mint.key().as_ref()],
+        bump = position_index.load()?.bump,
+    )]
+    pub position_index: AccountLoader<'info, PositionIndex>,
+
     #[account(
         mut,
         associated_token::mint = token_mint,
@@ -205,11 +320,11 @@ pub struct WithdrawToken<'info> {
     )]
     pub vault: InterfaceAccount<'info, TokenAccount>,
 
+    /// Collateral goes to whoever controls the position, checked in the handler.
     #[account(
         mut,
-        associated_token::mint = token_mint,
-        associated_token::authority = user,
-        associated_token::token_program = token_program
+        token::mint = token_mint,
+        token::token_program = token_program
     )]
     pub user_token_account: InterfaceAccount<'info, TokenAccount>,
 
@@ -218,11 +333,12 @@ pub struct WithdrawToken<'info> {
         payer=user,
         associated_token::mint=dsc_mint,
         associated_token::authority=user,
-        associated_token::token_program=token_program
+        associated_token::token_program=dsc_token_program
     )]
     pub user_dsc_account: InterfaceAccount<'info, TokenAccount>,
 
     pub system_program: Program<'info, System>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub token_program: Interface<'info, TokenInterface>,
+    pub dsc_token_program: Program<'info, Token2022>,
 }
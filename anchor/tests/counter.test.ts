@@ -3,33 +3,45 @@ import {
   ProgramTestContext,
   startAnchor,
   BanksClient,
+  Clock,
 } from 'solana-bankrun';
 import { BankrunProvider } from 'anchor-bankrun';
 import {
+  ComputeBudgetProgram,
   PublicKey,
   SystemProgram,
   Keypair,
   Transaction,
+  TransactionInstruction,
 } from '@solana/web3.js';
 import { beforeEach, it, describe } from 'node:test';
+import assert from 'node:assert';
 import {
   getAssociatedTokenAddress,
+  getAssociatedTokenAddressSync,
   TOKEN_PROGRAM_ID,
+  TOKEN_2022_PROGRAM_ID,
   ASSOCIATED_TOKEN_PROGRAM_ID,
   createAssociatedTokenAccountInstruction,
+  createTransferCheckedInstruction,
+  AccountLayout,
 } from '@solana/spl-token';
 import { BN, Program } from '@coral-xyz/anchor';
 import { Buffer } from 'buffer';
 import { Interest } from '../target/types/interest';
-import { createMint, mintTo } from 'spl-token-bankrun';
+import { Amm } from '../target/types/amm';
+import { createAssociatedTokenAccount, createMint, mintTo } from 'spl-token-bankrun';
 
 const IDL = require('../target/idl/interest.json');
+const AMM_IDL = require('../target/idl/amm.json');
 const programId = new PublicKey('J4bfWKCuz2J1gzbwhosrhRV5Q1bQATjvAmnzP7SMYptY');
+const ammProgramId = new PublicKey('9acwHFFeTw3BbtHxiokKBCpi8bWos2pQYcZuLf4uRYbr');
 
 describe('Interest Program', () => {
   let context;
   let provider;
   let program: Program<Interest>;
+  let amm: Program<Amm>;
   let banksClient: BanksClient;
 
   let user: Keypair;
@@ -38,9 +50,13 @@ describe('Interest Program', () => {
   let configPDA: PublicKey;
   let collateralPDA: PublicKey;
   let pricePDA: PublicKey;
+  let positionIndexPDA: PublicKey;
   let userPDA: PublicKey;
   let depositPDA: PublicKey;
   let enginePDA: PublicKey;
+  const marketId = new BN(0);
+  let rolesPDA: PublicKey;
+  let registryPDA: PublicKey;
   let mint: PublicKey;
   let dscMint: PublicKey;
   let userDSCAccount: PublicKey;
@@ -49,8 +65,12 @@ describe('Interest Program', () => {
     await program.methods
       .startToken(new BN(100_000_000))
       .accountsStrict({
+        engine: enginePDA,
+        roles: rolesPDA,
+        registry: registryPDA,
         config: configPDA,
         price: pricePDA,
+        positionIndex: positionIndexPDA,
         tokenMint: mint,
         vault: vaultATA,
         admin: user.publicKey,
@@ -69,13 +89,19 @@ describe('Interest Program', () => {
     const feePercent = new BN(8);
 
     await program.methods
-      .startEngine(liqThresh, healthFac, liqBonus, feePercent)
+      .startEngine(marketId, liqThresh, healthFac, liqBonus, feePercent, {
+        name: 'Credix Stablecoin',
+        symbol: 'DSC',
+        uri: 'https://ninad00.github.io/CredixSolana/dsc.json',
+      })
       .accountsStrict({
         engine: enginePDA,
+        roles: rolesPDA,
+        registry: registryPDA,
         authority: user.publicKey,
         dscMint: dscMint,
         systemProgram: SystemProgram.programId,
-        tokenProgram: TOKEN_PROGRAM_ID,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
       })
       .signers([user])
       .rpc();
@@ -86,7 +112,8 @@ describe('Interest Program', () => {
       user.publicKey,
       userDSCAccount,
       user.publicKey,
-      dscMint
+      dscMint,
+      TOKEN_2022_PROGRAM_ID
     );
 
     const tx = new Transaction().add(ataIx);
@@ -96,16 +123,187 @@ describe('Interest Program', () => {
     await banksClient.processTransaction(tx);
   }
 
-  async function depositCollateral() {
+  function fundedKeypair(): Keypair {
+    const kp = Keypair.generate();
+    context.setAccount(kp.publicKey, {
+      lamports: 1_000_000_000,
+      data: Buffer.alloc(0),
+      owner: SystemProgram.programId,
+      executable: false,
+    });
+    return kp;
+  }
+
+  // Queues `action`, waits out the timelock and executes it. Accounts the
+  // action needs (config, registry, psmAsset, savings) go in `accounts`.
+  async function governance(action, accounts: Record<string, PublicKey> = {}) {
+    const engine = await program.account.engine.fetch(enginePDA);
+    const [pendingActionPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('pending_action'), enginePDA.toBuffer(), engine.actionCount.toArrayLike(Buffer, 'le', 8)],
+      program.programId
+    );
+    await program.methods
+      .queueAction(action)
+      .accountsStrict({
+        roles: rolesPDA,
+        engine: enginePDA,
+        proposer: user.publicKey,
+        pendingAction: pendingActionPDA,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const clock = await banksClient.getClock();
+    context.setClock(
+      new Clock(
+        clock.slot,
+        clock.epochStartTimestamp,
+        clock.epoch,
+        clock.leaderScheduleEpoch,
+        clock.unixTimestamp + BigInt(engine.timelockDelay.toString())
+      )
+    );
+
+    await program.methods
+      .executeAction()
+      .accountsStrict({
+        executor: user.publicKey,
+        engine: enginePDA,
+        roles: rolesPDA,
+        pendingAction: pendingActionPDA,
+        proposer: user.publicKey,
+        config: null,
+        registry: null,
+        psmAsset: null,
+        savings: null,
+        insuranceFund: null,
+        insuranceVault: null,
+        destination: null,
+        tokenMint: null,
+        tokenProgram: null,
+        ...accounts,
+      })
+      .signers([user])
+      .rpc();
+  }
+
+  async function tokenBalance(address: PublicKey): Promise<bigint> {
+    const account = await banksClient.getAccount(address);
+    return account === null ? 0n : AccountLayout.decode(Buffer.from(account.data)).amount;
+  }
+
+  async function mintDsc(amount: BN) {
+    await program.methods
+      .mintDsc(amount)
+      .accountsStrict({
+        engine: enginePDA,
+        userData: userPDA,
+        tokenMint: mint,
+        user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
+        dscMint: dscMint,
+        deposit: depositPDA,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        userDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+  }
+
+  async function setPrice(price: BN) {
+    await program.methods
+      .updatePrice(price)
+      .accountsStrict({
+        roles: rolesPDA,
+        oracleUpdater: user.publicKey,
+        engine: enginePDA,
+        price: pricePDA,
+        config: configPDA,
+      })
+      .signers([user])
+      .rpc();
+  }
+
+  async function initInsuranceFund() {
+    const [insuranceFund] = PublicKey.findProgramAddressSync(
+      [Buffer.from('insurance'), enginePDA.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+    const insuranceVault = getAssociatedTokenAddressSync(mint, insuranceFund, true, TOKEN_PROGRAM_ID);
+    await program.methods
+      .initInsuranceFund()
+      .accountsStrict({
+        engine: enginePDA,
+        roles: rolesPDA,
+        admin: user.publicKey,
+        tokenMint: mint,
+        insuranceFund,
+        insuranceVault,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+    return { insuranceFund, insuranceVault };
+  }
+
+  // A fresh keeper holding `amount` of the user's DSC.
+  async function keeperWithDsc(amount: number) {
+    const DSC_DECIMALS = 6;
+    const keeper = fundedKeypair();
+    const keeperDsc = getAssociatedTokenAddressSync(dscMint, keeper.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    const tx = new Transaction().add(
+      createAssociatedTokenAccountInstruction(
+        user.publicKey,
+        keeperDsc,
+        keeper.publicKey,
+        dscMint,
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createTransferCheckedInstruction(
+        userDSCAccount,
+        dscMint,
+        keeperDsc,
+        user.publicKey,
+        amount,
+        DSC_DECIMALS,
+        [],
+        TOKEN_2022_PROGRAM_ID
+      )
+    );
+    tx.feePayer = user.publicKey;
+    tx.recentBlockhash = context.lastBlockhash;
+    tx.sign(user);
+    await banksClient.processTransaction(tx);
+    return { keeper, keeperDsc, keeperCollateral: getAssociatedTokenAddressSync(mint, keeper.publicKey) };
+  }
+
+  async function depositCollateral(amount = new BN(100_000_000)) {
     await program.methods
-      .depositCollateral(new BN(100_000_000))
+      .depositCollateral(amount)
       .accountsStrict({
         user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
+        engine: enginePDA,
         tokenMint: mint,
         userTokenAccount: userATA,
         userData: userPDA,
         deposit: depositPDA,
         config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
         vault: vaultATA,
         tokenProgram: TOKEN_PROGRAM_ID,
         systemProgram: SystemProgram.programId,
@@ -120,7 +318,10 @@ describe('Interest Program', () => {
 
     context = await startAnchor(
       '',
-      [{ name: 'interest', programId }],
+      [
+        { name: 'interest', programId },
+        { name: 'amm', programId: ammProgramId },
+      ],
       [
         {
           address: user.publicKey,
@@ -137,34 +338,53 @@ describe('Interest Program', () => {
     provider = new BankrunProvider(context);
     anchor.setProvider(provider);
     program = new Program<Interest>(IDL as Interest, provider);
+    amm = new Program<Amm>(AMM_IDL as Amm, provider);
     banksClient = context.banksClient;
     mint = await createMint(banksClient, user, user.publicKey, null, 6);
 
     [enginePDA] = PublicKey.findProgramAddressSync(
-      [Buffer.from('engine')],
+      [Buffer.from('engine'), marketId.toArrayLike(Buffer, 'le', 8)],
+      program.programId
+    );
+
+    [rolesPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('roles'), enginePDA.toBuffer()],
+      program.programId
+    );
+
+    [registryPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('registry'), enginePDA.toBuffer()],
       program.programId
     );
 
-    dscMint = await createMint(banksClient, user, enginePDA, null, 6);
+    [dscMint] = PublicKey.findProgramAddressSync(
+      [Buffer.from('dsc_mint'), enginePDA.toBuffer()],
+      program.programId
+    );
     await startEngine();
 
     [configPDA] = PublicKey.findProgramAddressSync(
-      [Buffer.from('config'), mint.toBuffer()],
+      [Buffer.from('config'), enginePDA.toBuffer(), mint.toBuffer()],
       program.programId
     );
 
     [userPDA] = PublicKey.findProgramAddressSync(
-      [Buffer.from('user'), user.publicKey.toBuffer(), mint.toBuffer()],
+      [Buffer.from('user'), enginePDA.toBuffer(), user.publicKey.toBuffer(), mint.toBuffer()],
       program.programId
     );
 
     [depositPDA] = PublicKey.findProgramAddressSync(
-      [Buffer.from('deposit'), user.publicKey.toBuffer(), mint.toBuffer()],
+      [Buffer.from('deposit'), enginePDA.toBuffer(), user.publicKey.toBuffer(), mint.toBuffer()],
       program.programId
     );
 
     [pricePDA] = PublicKey.findProgramAddressSync(
-      [Buffer.from('price'), mint.toBuffer()],
+      [Buffer.from('price'), enginePDA.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+
+    [positionIndexPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('position_index'), enginePDA.toBuffer(), mint.toBuffer()],
       program.programId
     );
 
@@ -193,7 +413,7 @@ describe('Interest Program', () => {
       dscMint,
       user.publicKey,
       false,
-      TOKEN_PROGRAM_ID,
+      TOKEN_2022_PROGRAM_ID,
       ASSOCIATED_TOKEN_PROGRAM_ID
     );
 
@@ -223,12 +443,13 @@ describe('Interest Program', () => {
   it('initializes token config', async () => {
     await initializeConfig();
     const config = await program.account.config.fetch(configPDA);
-    console.log('Config created:', config);
+    assert.ok(config.tokenMint.equals(mint));
   });
 
   it('starts engine (already done in beforeEach)', async () => {
     const engine = await program.account.engine.fetch(enginePDA);
-    console.log('Engine verified:', engine);
+    assert.ok(engine.dscMint.equals(dscMint));
+    assert.strictEqual(engine.liquidationThreshold.toNumber(), 50);
   });
 
   it('deposits collateral', async () => {
@@ -236,7 +457,8 @@ describe('Interest Program', () => {
     await depositCollateral();
 
     const deposit = await program.account.deposit.fetch(depositPDA);
-    console.log('Collateral deposited:', deposit);
+    assert.strictEqual(deposit.tokenAmt.toNumber(), 100_000_000);
+    assert.strictEqual(await tokenBalance(vaultATA), 100_000_000n);
   });
 
   it('checks health factor before minting DSC', async () => {
@@ -244,30 +466,32 @@ describe('Interest Program', () => {
     await depositCollateral();
     await createUserDSCAccount();
 
-    try {
-      await program.methods
-        .mintDsc(new BN(1_000), new BN(100_000_000))
-        .accountsStrict({
-          engine: enginePDA,
-          userData: userPDA,
-          tokenMint: mint,
-          user: user.publicKey,
-          dscMint: dscMint,
-          deposit: depositPDA,
-          config: configPDA,
-          price: pricePDA,
-          userDscAccount: userDSCAccount,
-          systemProgram: SystemProgram.programId,
-          tokenProgram: TOKEN_PROGRAM_ID,
-          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
-        })
-        .signers([user])
-        .rpc();
+    await program.methods
+      .mintDsc(new BN(1_000))
+      .accountsStrict({
+        engine: enginePDA,
+        userData: userPDA,
+        tokenMint: mint,
+        user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
+        dscMint: dscMint,
+        deposit: depositPDA,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        userDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
 
-      console.log('✓ Small DSC mint successful');
-    } catch (error) {
-      console.log('✗ Small DSC mint failed:', error.message);
-    }
+    const userData = await program.account.userData.fetch(userPDA);
+    assert.strictEqual(userData.borrowedAmount.toNumber(), 1_000);
   });
 
   it('mints DSC', async () => {
@@ -276,87 +500,290 @@ describe('Interest Program', () => {
     await createUserDSCAccount();
 
     await program.methods
-      .mintDsc(new BN(10_000), new BN(100_000_000))
+      .mintDsc(new BN(10_000))
       .accountsStrict({
         engine: enginePDA,
         userData: userPDA,
         tokenMint: mint,
         user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
         dscMint: dscMint,
         deposit: depositPDA,
         config: configPDA,
         price: pricePDA,
+        positionIndex: positionIndexPDA,
         userDscAccount: userDSCAccount,
         systemProgram: SystemProgram.programId,
         tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       })
       .signers([user])
       .rpc();
 
-    console.log('DSC minted successfully.');
-    const userDscBalance = await banksClient.getAccount(userDSCAccount);
-    console.log('User DSC account balance:', userDscBalance);
+    assert.strictEqual(await tokenBalance(userDSCAccount), 10_000n);
   });
 
-  it('full workflow: config -> engine -> deposit -> mint DSC', async () => {
+  it('refreshes the stored health factor from deposit, debt and price', async () => {
     await initializeConfig();
     await depositCollateral();
     await createUserDSCAccount();
 
     await program.methods
-      .mintDsc(new BN(10_000), new BN(100_000_000))
+      .mintDsc(new BN(10_000))
       .accountsStrict({
         engine: enginePDA,
         userData: userPDA,
         tokenMint: mint,
         user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
         dscMint: dscMint,
         deposit: depositPDA,
         config: configPDA,
         price: pricePDA,
+        positionIndex: positionIndexPDA,
         userDscAccount: userDSCAccount,
         systemProgram: SystemProgram.programId,
         tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       })
       .signers([user])
       .rpc();
+
+    await program.methods
+      .refreshHealthFactor()
+      .accountsStrict({
+        engine: enginePDA,
+        tokenMint: mint,
+        userData: userPDA,
+        deposit: depositPDA,
+        price: pricePDA,
+      })
+      .rpc();
+
+    const userData = await program.account.userData.fetch(userPDA);
+    const slot = await banksClient.getSlot();
+    assert.notStrictEqual(userData.hf.toString(), '18446744073709551615');
+    assert.strictEqual(userData.hfSlot.toString(), slot.toString());
   });
 
-  it('withdraws collateral', async () => {
+  it('answers what-if questions through view instructions', async () => {
+    await initializeConfig();
+    await depositCollateral();
+
+    const accounts = {
+      engine: enginePDA,
+      tokenMint: mint,
+      userData: userPDA,
+      deposit: depositPDA,
+      price: pricePDA,
+    };
+    const view = await program.methods.positionView().accountsStrict(accounts).view();
+    const afterMint = await program.methods
+      .simulateHealthFactor(new BN(0), view.maxMintable)
+      .accountsStrict(accounts)
+      .view();
+    const engine = await program.account.engine.fetch(enginePDA);
+
+    assert.ok(view.liquidationPrice.isZero());
+    assert.strictEqual(view.maxWithdrawable.toNumber(), 100_000_000);
+    // Max mintable keeps the position at the minimum health factor.
+    assert.ok(afterMint.gte(engine.minHealthFactor));
+  });
+
+  it('repays DSC without moving collateral', async () => {
     await initializeConfig();
     await depositCollateral();
     await createUserDSCAccount();
 
     await program.methods
-      .mintDsc(new BN(5_000000), new BN(100_000_000))
+      .mintDsc(new BN(10_000))
       .accountsStrict({
         engine: enginePDA,
         userData: userPDA,
         tokenMint: mint,
         user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
         dscMint: dscMint,
         deposit: depositPDA,
         config: configPDA,
         price: pricePDA,
+        positionIndex: positionIndexPDA,
         userDscAccount: userDSCAccount,
         systemProgram: SystemProgram.programId,
         tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       })
       .signers([user])
       .rpc();
 
-    const initialDeposit = await program.account.deposit.fetch(depositPDA);
+    await program.methods
+      .repayDsc(new BN(4_000))
+      .accountsStrict({
+        payer: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        dscMint: dscMint,
+        userData: userPDA,
+        deposit: depositPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        payerDscAccount: userDSCAccount,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const userData = await program.account.userData.fetch(userPDA);
+    const deposit = await program.account.deposit.fetch(depositPDA);
+    assert.strictEqual(userData.borrowedAmount.toNumber(), 6_000);
+    assert.strictEqual(deposit.tokenAmt.toNumber(), 100_000_000);
+  });
+
+  it('withdraws collateral by amount while the position stays healthy', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    const withdrawAccounts = {
+      user: user.publicKey,
+      owner: user.publicKey,
+      delegation: null,
+      positionNft: null,
+      userData: userPDA,
+      engine: enginePDA,
+      tokenMint: mint,
+      dscMint: dscMint,
+      deposit: depositPDA,
+      price: pricePDA,
+      config: configPDA,
+      positionIndex: positionIndexPDA,
+      vault: vaultATA,
+      userTokenAccount: userATA,
+      userDscAccount: userDSCAccount,
+      systemProgram: SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+    };
+
+    await program.methods
+      .withdrawCollateralAmount(new BN(40_000_000))
+      .accountsStrict(withdrawAccounts)
+      .signers([user])
+      .rpc();
+
+    const deposit = await program.account.deposit.fetch(depositPDA);
+    assert.strictEqual(deposit.tokenAmt.toNumber(), 60_000_000);
+
+    await assert.rejects(
+      program.methods
+        .withdrawCollateralAmount(new BN(60_000_001))
+        .accountsStrict(withdrawAccounts)
+        .signers([user])
+        .rpc(),
+      /NotEnoughTokensInCollateral/
+    );
+  });
+
+  it('does not pay out collateral already withdrawn in full', async () => {
+    await initializeConfig();
+    await depositCollateral();
+
+    const withdrawAccounts = {
+      user: user.publicKey,
+      owner: user.publicKey,
+      delegation: null,
+      positionNft: null,
+      userData: userPDA,
+      engine: enginePDA,
+      tokenMint: mint,
+      dscMint: dscMint,
+      deposit: depositPDA,
+      price: pricePDA,
+      config: configPDA,
+      positionIndex: positionIndexPDA,
+      vault: vaultATA,
+      userTokenAccount: userATA,
+      userDscAccount: userDSCAccount,
+      systemProgram: SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+    };
+
+    const before = await tokenBalance(userATA);
+    await program.methods
+      .withdrawCollateral(new BN(0))
+      .accountsStrict(withdrawAccounts)
+      .signers([user])
+      .rpc();
+
+    assert.strictEqual((await tokenBalance(userATA)) - before, 100_000_000n);
+    assert.ok((await program.account.deposit.fetch(depositPDA)).tokenAmt.isZero());
+
+    await assert.rejects(
+      program.methods
+        .withdrawCollateralAmount(new BN(100_000_000))
+        .accountsStrict(withdrawAccounts)
+        .signers([user])
+        .rpc(),
+      /NotEnoughTokensInCollateral/
+    );
+    assert.strictEqual(await tokenBalance(vaultATA), 0n);
+  });
+
+  it('deposits and mints, then repays and withdraws, in single calls', async () => {
+    await initializeConfig();
+    await createUserDSCAccount();
+
+    await program.methods
+      .depositAndMint(new BN(100_000_000), new BN(10_000))
+      .accountsStrict({
+        user: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        dscMint: dscMint,
+        userTokenAccount: userATA,
+        userData: userPDA,
+        deposit: depositPDA,
+        positionNft: null,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        vault: vaultATA,
+        userDscAccount: userDSCAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
 
-    const withdrawAmount = new BN(1_000_000);
-    const currentPrice = new BN(100_000_000);
+    let userData = await program.account.userData.fetch(userPDA);
+    assert.strictEqual(userData.borrowedAmount.toNumber(), 10_000);
+    // The deposited collateral counts towards the system totals along with
+    // the new debt.
+    let engine = await program.account.engine.fetch(enginePDA);
+    assert.strictEqual(engine.systemCollateralValue.toNumber(), 1_000_000_000_000);
+    assert.strictEqual(engine.systemDebt.toNumber(), 10_000);
 
     await program.methods
-      .withdrawCollateral(withdrawAmount, currentPrice)
+      .repayAndWithdraw(new BN(10_000), new BN(100_000_000))
       .accountsStrict({
         user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
         userData: userPDA,
         engine: enginePDA,
         tokenMint: mint,
@@ -364,23 +791,2713 @@ describe('Interest Program', () => {
         deposit: depositPDA,
         price: pricePDA,
         config: configPDA,
+        positionIndex: positionIndexPDA,
         vault: vaultATA,
         userTokenAccount: userATA,
         userDscAccount: userDSCAccount,
         systemProgram: SystemProgram.programId,
         associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
         tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
       })
       .signers([user])
       .rpc();
 
-    const finalDeposit = await program.account.deposit.fetch(depositPDA);
-    const expectedFinalAmount = initialDeposit.tokenAmt.sub(withdrawAmount);
+    userData = await program.account.userData.fetch(userPDA);
+    const deposit = await program.account.deposit.fetch(depositPDA);
+    assert.ok(userData.borrowedAmount.isZero());
+    assert.ok(deposit.tokenAmt.isZero());
+    engine = await program.account.engine.fetch(enginePDA);
+    assert.ok(engine.systemCollateralValue.isZero());
+    assert.ok(engine.systemDebt.isZero());
+  });
+
+  it('opens and unwinds leverage through the swap adapter', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
 
-    if (finalDeposit.tokenAmt.eq(expectedFinalAmount)) {
-      console.log('✓ Collateral withdrawal successful');
-    } else {
-      console.log('✗ Collateral withdrawal amount mismatch');
-    }
+    // Borrow DSC to seed a pool priced at the oracle rate.
+    await program.methods
+      .mintDsc(new BN(100_000_000_000))
+      .accountsStrict({
+        engine: enginePDA,
+        userData: userPDA,
+        tokenMint: mint,
+        user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
+        dscMint: dscMint,
+        deposit: depositPDA,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        userDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const [poolPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('pool'), mint.toBuffer(), dscMint.toBuffer()],
+      ammProgramId
+    );
+    const poolCollateral = await getAssociatedTokenAddress(mint, poolPDA, true, TOKEN_PROGRAM_ID);
+    const poolDsc = await getAssociatedTokenAddress(dscMint, poolPDA, true, TOKEN_2022_PROGRAM_ID);
+
+    await amm.methods
+      .initializePool(new BN(30))
+      .accountsStrict({
+        payer: user.publicKey,
+        mintA: mint,
+        mintB: dscMint,
+        pool: poolPDA,
+        vaultA: poolCollateral,
+        vaultB: poolDsc,
+        tokenProgramA: TOKEN_PROGRAM_ID,
+        tokenProgramB: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+    await amm.methods
+      .addLiquidity(new BN(10_000_000), new BN(100_000_000_000))
+      .accountsStrict({
+        provider: user.publicKey,
+        pool: poolPDA,
+        mintA: mint,
+        mintB: dscMint,
+        providerA: userATA,
+        providerB: userDSCAccount,
+        vaultA: poolCollateral,
+        vaultB: poolDsc,
+        tokenProgramA: TOKEN_PROGRAM_ID,
+        tokenProgramB: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    // Point the engine at the pool program through governance.
+    await governance({ setSwapProgram: { program: ammProgramId } });
+
+    const leverageAccounts = {
+      user: user.publicKey,
+      engine: enginePDA,
+      tokenMint: mint,
+      dscMint: dscMint,
+      userData: userPDA,
+      deposit: depositPDA,
+      positionNft: null,
+      config: configPDA,
+      price: pricePDA,
+      positionIndex: positionIndexPDA,
+      vault: vaultATA,
+      userTokenAccount: userATA,
+      userDscAccount: userDSCAccount,
+      swapProgram: ammProgramId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+    };
+    const swapAccounts = (sellDsc: boolean) => {
+      const dscSide = [dscMint, userDSCAccount, poolDsc, TOKEN_2022_PROGRAM_ID];
+      const collateralSide = [mint, userATA, poolCollateral, TOKEN_PROGRAM_ID];
+      const [tokenIn, tokenOut] = sellDsc ? [dscSide, collateralSide] : [collateralSide, dscSide];
+      return [
+        { pubkey: user.publicKey, isSigner: true, isWritable: true },
+        { pubkey: poolPDA, isSigner: false, isWritable: false },
+        { pubkey: tokenIn[0], isSigner: false, isWritable: false },
+        { pubkey: tokenOut[0], isSigner: false, isWritable: false },
+        { pubkey: tokenIn[1], isSigner: false, isWritable: true },
+        { pubkey: tokenOut[1], isSigner: false, isWritable: true },
+        { pubkey: tokenIn[2], isSigner: false, isWritable: true },
+        { pubkey: tokenOut[2], isSigner: false, isWritable: true },
+        { pubkey: tokenIn[3], isSigner: false, isWritable: false },
+        { pubkey: tokenOut[3], isSigner: false, isWritable: false },
+      ];
+    };
+
+    await program.methods
+      .openLeverage(new BN(3_000), new BN(1))
+      .accountsStrict(leverageAccounts)
+      .remainingAccounts(swapAccounts(true))
+      .signers([user])
+      .rpc();
+
+    let userData = await program.account.userData.fetch(userPDA);
+    let deposit = await program.account.deposit.fetch(depositPDA);
+    const leveragedDebt = userData.borrowedAmount;
+    assert.ok(leveragedDebt.gt(new BN(100_000_000_000)));
+    assert.ok(deposit.tokenAmt.gt(new BN(100_000_000)));
+    // The swapped-in collateral is part of the system totals too; at a price
+    // of 1e8 each token is worth 1e4 DSC units.
+    let engine = await program.account.engine.fetch(enginePDA);
+    assert.strictEqual(engine.systemCollateralValue.toString(), deposit.tokenAmt.muln(10_000).toString());
+    assert.strictEqual(engine.systemDebt.toString(), leveragedDebt.toString());
+
+    // A pause blocks adding leverage but not unwinding it.
+    await program.methods
+      .pause()
+      .accountsStrict({ roles: rolesPDA, guardian: user.publicKey, engine: enginePDA })
+      .signers([user])
+      .rpc();
+    await assert.rejects(
+      program.methods
+        .openLeverage(new BN(3_500), new BN(1))
+        .accountsStrict(leverageAccounts)
+        .remainingAccounts(swapAccounts(true))
+        .signers([user])
+        .rpc(),
+      /ProtocolPaused/
+    );
+
+    await program.methods
+      .deleverage(new BN(5_000_000), new BN(1))
+      .accountsStrict(leverageAccounts)
+      .remainingAccounts(swapAccounts(false))
+      .signers([user])
+      .rpc();
+
+    userData = await program.account.userData.fetch(userPDA);
+    deposit = await program.account.deposit.fetch(depositPDA);
+    assert.ok(userData.borrowedAmount.lt(leveragedDebt));
+    engine = await program.account.engine.fetch(enginePDA);
+    assert.strictEqual(engine.systemCollateralValue.toString(), deposit.tokenAmt.muln(10_000).toString());
+    assert.strictEqual(engine.systemDebt.toString(), userData.borrowedAmount.toString());
+  });
+
+  it('tops up another user\'s collateral', async () => {
+    await initializeConfig();
+    await depositCollateral();
+
+    const rescuer = fundedKeypair();
+    const rescuerATA = await getAssociatedTokenAddress(mint, rescuer.publicKey);
+    const tx = new Transaction().add(
+      createAssociatedTokenAccountInstruction(rescuer.publicKey, rescuerATA, rescuer.publicKey, mint)
+    );
+    tx.feePayer = rescuer.publicKey;
+    tx.recentBlockhash = context.lastBlockhash;
+    tx.sign(rescuer);
+    await banksClient.processTransaction(tx);
+    await mintTo(banksClient, user, mint, rescuerATA, user.publicKey, 25_000_000);
+
+    await program.methods
+      .depositFor(new BN(25_000_000))
+      .accountsStrict({
+        payer: rescuer.publicKey,
+        beneficiary: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        payerTokenAccount: rescuerATA,
+        userData: userPDA,
+        deposit: depositPDA,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        vault: vaultATA,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([rescuer])
+      .rpc();
+
+    const deposit = await program.account.deposit.fetch(depositPDA);
+    assert.strictEqual(deposit.tokenAmt.toNumber(), 125_000_000);
+  });
+
+  it('lets a delegate mint within its limit but not withdraw', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    const operator = fundedKeypair();
+    const [delegationPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('delegation'), enginePDA.toBuffer(), userPDA.toBuffer()],
+      program.programId
+    );
+    const DELEGATE_MINT = 4;
+
+    await program.methods
+      .setDelegate(operator.publicKey, DELEGATE_MINT, new BN(10_000))
+      .accountsStrict({
+        owner: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        userData: userPDA,
+        positionNft: null,
+        delegation: delegationPDA,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    const mintAccounts = {
+      engine: enginePDA,
+      userData: userPDA,
+      tokenMint: mint,
+      user: operator.publicKey,
+      owner: user.publicKey,
+      delegation: delegationPDA,
+      positionNft: null,
+      dscMint: dscMint,
+      deposit: depositPDA,
+      config: configPDA,
+      price: pricePDA,
+      positionIndex: positionIndexPDA,
+      userDscAccount: userDSCAccount,
+      systemProgram: SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+    };
+
+    await program.methods
+      .mintDsc(new BN(10_000))
+      .accountsStrict(mintAccounts)
+      .signers([operator])
+      .rpc();
+
+    const userData = await program.account.userData.fetch(userPDA);
+    assert.strictEqual(userData.borrowedAmount.toNumber(), 10_000);
+
+    await assert.rejects(
+      program.methods
+        .mintDsc(new BN(1))
+        .accountsStrict(mintAccounts)
+        .signers([operator])
+        .rpc(),
+      /DelegateMintLimit/
+    );
+
+    await assert.rejects(
+      program.methods
+        .withdrawCollateralAmount(new BN(1_000_000))
+        .accountsStrict({
+          user: operator.publicKey,
+          owner: user.publicKey,
+          delegation: delegationPDA,
+          positionNft: null,
+          userData: userPDA,
+          engine: enginePDA,
+          tokenMint: mint,
+          dscMint: dscMint,
+          deposit: depositPDA,
+          price: pricePDA,
+          config: configPDA,
+          positionIndex: positionIndexPDA,
+          vault: vaultATA,
+          userTokenAccount: userATA,
+          userDscAccount: await getAssociatedTokenAddress(
+            dscMint,
+            operator.publicKey,
+            false,
+            TOKEN_2022_PROGRAM_ID
+          ),
+          systemProgram: SystemProgram.programId,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([operator])
+        .rpc(),
+      /DelegateNotAllowed/
+    );
+  });
+
+  it('hands control of a tokenized position to the NFT holder', async () => {
+    await initializeConfig();
+    await depositCollateral();
+
+    const [positionMint] = PublicKey.findProgramAddressSync(
+      [Buffer.from('position_mint'), userPDA.toBuffer()],
+      program.programId
+    );
+    const ownerNft = await getAssociatedTokenAddress(
+      positionMint,
+      user.publicKey,
+      false,
+      TOKEN_2022_PROGRAM_ID
+    );
+
+    await program.methods
+      .tokenizePosition()
+      .accountsStrict({
+        owner: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        userData: userPDA,
+        positionMint,
+        ownerNftAccount: ownerNft,
+        nftTokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const buyer = fundedKeypair();
+    const buyerNft = await getAssociatedTokenAddress(
+      positionMint,
+      buyer.publicKey,
+      false,
+      TOKEN_2022_PROGRAM_ID
+    );
+    const buyerATA = await getAssociatedTokenAddress(mint, buyer.publicKey);
+    const tx = new Transaction().add(
+      createAssociatedTokenAccountInstruction(
+        user.publicKey,
+        buyerNft,
+        buyer.publicKey,
+        positionMint,
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createAssociatedTokenAccountInstruction(user.publicKey, buyerATA, buyer.publicKey, mint),
+      createTransferCheckedInstruction(
+        ownerNft,
+        positionMint,
+        buyerNft,
+        user.publicKey,
+        1,
+        0,
+        [],
+        TOKEN_2022_PROGRAM_ID
+      )
+    );
+    tx.feePayer = user.publicKey;
+    tx.recentBlockhash = context.lastBlockhash;
+    tx.sign(user);
+    await banksClient.processTransaction(tx);
+
+    const withdrawAccounts = (signer: PublicKey, nft: PublicKey | null, to: PublicKey) => ({
+      user: signer,
+      owner: user.publicKey,
+      delegation: null,
+      positionNft: nft,
+      userData: userPDA,
+      engine: enginePDA,
+      tokenMint: mint,
+      dscMint: dscMint,
+      deposit: depositPDA,
+      price: pricePDA,
+      config: configPDA,
+      positionIndex: positionIndexPDA,
+      vault: vaultATA,
+      userTokenAccount: to,
+      userDscAccount: getAssociatedTokenAddressSync(dscMint, signer, false, TOKEN_2022_PROGRAM_ID),
+      systemProgram: SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+    });
+
+    await assert.rejects(
+      program.methods
+        .withdrawCollateralAmount(new BN(10_000_000))
+        .accountsStrict(withdrawAccounts(user.publicKey, null, userATA))
+        .signers([user])
+        .rpc(),
+      /NotPositionHolder/
+    );
+
+    await program.methods
+      .withdrawCollateralAmount(new BN(10_000_000))
+      .accountsStrict(withdrawAccounts(buyer.publicKey, buyerNft, buyerATA))
+      .signers([buyer])
+      .rpc();
+
+    const deposit = await program.account.deposit.fetch(depositPDA);
+    assert.strictEqual(deposit.tokenAmt.toNumber(), 90_000_000);
+  });
+
+  it('lets a keeper execute a triggered repay order for a tip', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    await program.methods
+      .mintDsc(new BN(10_000))
+      .accountsStrict({
+        engine: enginePDA,
+        userData: userPDA,
+        tokenMint: mint,
+        user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
+        dscMint: dscMint,
+        deposit: depositPDA,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        userDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const [orderPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('order'), enginePDA.toBuffer(), userPDA.toBuffer()],
+      program.programId
+    );
+    const escrowDsc = getAssociatedTokenAddressSync(dscMint, orderPDA, true, TOKEN_2022_PROGRAM_ID);
+    const escrowCollateral = getAssociatedTokenAddressSync(mint, orderPDA, true, TOKEN_PROGRAM_ID);
+    const ORDER_REPAY = 0;
+    // Far above any reachable health factor, so the order is live at once.
+    const trigger = new BN(2).pow(new BN(62));
+
+    await program.methods
+      .placeOrder(ORDER_REPAY, trigger, new BN(5_000), new BN(0), new BN(1_000_000))
+      .accountsStrict({
+        owner: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        dscMint: dscMint,
+        userData: userPDA,
+        positionNft: null,
+        order: orderPDA,
+        escrowDsc,
+        escrowCollateral,
+        ownerDscAccount: userDSCAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const keeper = fundedKeypair();
+    const executeAccounts = {
+      keeper: keeper.publicKey,
+      engine: enginePDA,
+      tokenMint: mint,
+      dscMint: dscMint,
+      userData: userPDA,
+      positionNft: null,
+      deposit: depositPDA,
+      config: configPDA,
+      price: pricePDA,
+      positionIndex: positionIndexPDA,
+      vault: vaultATA,
+      order: orderPDA,
+      escrowDsc,
+      escrowCollateral,
+      swapProgram: null,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+    };
+    const keeperBefore = await banksClient.getBalance(keeper.publicKey);
+
+    await program.methods
+      .executeOrder()
+      .accountsStrict(executeAccounts)
+      .signers([keeper])
+      .rpc();
+
+    const userData = await program.account.userData.fetch(userPDA);
+    const keeperAfter = await banksClient.getBalance(keeper.publicKey);
+    assert.strictEqual(userData.borrowedAmount.toNumber(), 5_000);
+    assert.ok(keeperAfter > keeperBefore);
+
+    // The compute budget instruction keeps the retry from being a duplicate
+    // of the first transaction.
+    await assert.rejects(
+      program.methods
+        .executeOrder()
+        .accountsStrict(executeAccounts)
+        .preInstructions([ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })])
+        .signers([keeper])
+        .rpc(),
+      /OrderInactive/
+    );
+
+    await program.methods
+      .cancelOrder()
+      .accountsStrict({
+        authority: user.publicKey,
+        owner: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        dscMint: dscMint,
+        order: orderPDA,
+        userData: userPDA,
+        positionNft: null,
+        escrowDsc,
+        escrowCollateral,
+        ownerDscAccount: userDSCAccount,
+        ownerTokenAccount: userATA,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    assert.strictEqual(await banksClient.getAccount(orderPDA), null);
+  });
+
+  it('retires orders placed before a position changed hands', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+    await mintDsc(new BN(10_000));
+
+    const [orderPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('order'), enginePDA.toBuffer(), userPDA.toBuffer()],
+      program.programId
+    );
+    const escrowDsc = getAssociatedTokenAddressSync(dscMint, orderPDA, true, TOKEN_2022_PROGRAM_ID);
+    const escrowCollateral = getAssociatedTokenAddressSync(mint, orderPDA, true, TOKEN_PROGRAM_ID);
+    const ORDER_REPAY = 0;
+    const trigger = new BN(2).pow(new BN(62));
+    const placeAccounts = (owner: PublicKey, nft: PublicKey | null, ownerDsc: PublicKey) => ({
+      owner,
+      engine: enginePDA,
+      tokenMint: mint,
+      dscMint: dscMint,
+      userData: userPDA,
+      positionNft: nft,
+      order: orderPDA,
+      escrowDsc,
+      escrowCollateral,
+      ownerDscAccount: ownerDsc,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      systemProgram: SystemProgram.programId,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+    });
+
+    await program.methods
+      .placeOrder(ORDER_REPAY, trigger, new BN(5_000), new BN(0), new BN(1_000_000))
+      .accountsStrict(placeAccounts(user.publicKey, null, userDSCAccount))
+      .signers([user])
+      .rpc();
+
+    const [positionMint] = PublicKey.findProgramAddressSync(
+      [Buffer.from('position_mint'), userPDA.toBuffer()],
+      program.programId
+    );
+    const ownerNft = getAssociatedTokenAddressSync(positionMint, user.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    await program.methods
+      .tokenizePosition()
+      .accountsStrict({
+        owner: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        userData: userPDA,
+        positionMint,
+        ownerNftAccount: ownerNft,
+        nftTokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const buyer = fundedKeypair();
+    const buyerNft = getAssociatedTokenAddressSync(positionMint, buyer.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    const buyerDsc = getAssociatedTokenAddressSync(dscMint, buyer.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    const tx = new Transaction().add(
+      createAssociatedTokenAccountInstruction(
+        user.publicKey,
+        buyerNft,
+        buyer.publicKey,
+        positionMint,
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createAssociatedTokenAccountInstruction(
+        user.publicKey,
+        buyerDsc,
+        buyer.publicKey,
+        dscMint,
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createTransferCheckedInstruction(
+        ownerNft,
+        positionMint,
+        buyerNft,
+        user.publicKey,
+        1,
+        0,
+        [],
+        TOKEN_2022_PROGRAM_ID
+      )
+    );
+    tx.feePayer = user.publicKey;
+    tx.recentBlockhash = context.lastBlockhash;
+    tx.sign(user);
+    await banksClient.processTransaction(tx);
+
+    const keeper = fundedKeypair();
+    await program.methods
+      .executeOrder()
+      .accountsStrict({
+        keeper: keeper.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        dscMint: dscMint,
+        userData: userPDA,
+        positionNft: buyerNft,
+        deposit: depositPDA,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        vault: vaultATA,
+        order: orderPDA,
+        escrowDsc,
+        escrowCollateral,
+        swapProgram: null,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([keeper])
+      .rpc();
+
+    const order = await program.account.protectionOrder.fetch(orderPDA);
+    const userData = await program.account.userData.fetch(userPDA);
+    assert.equal(order.active, false);
+    assert.equal(userData.borrowedAmount.toNumber(), 10_000);
+
+    const sellerDscBefore = await tokenBalance(userDSCAccount);
+    await program.methods
+      .cancelOrder()
+      .accountsStrict({
+        authority: buyer.publicKey,
+        owner: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        dscMint: dscMint,
+        order: orderPDA,
+        userData: userPDA,
+        positionNft: buyerNft,
+        escrowDsc,
+        escrowCollateral,
+        ownerDscAccount: userDSCAccount,
+        ownerTokenAccount: userATA,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([buyer])
+      .rpc();
+    assert.equal(await tokenBalance(userDSCAccount), sellerDscBefore + BigInt(5_000));
+
+    const ORDER_SELL_COLLATERAL = 1;
+    await program.methods
+      .placeOrder(ORDER_SELL_COLLATERAL, trigger, new BN(1_000), new BN(0), new BN(0))
+      .accountsStrict(placeAccounts(buyer.publicKey, buyerNft, buyerDsc))
+      .signers([buyer])
+      .rpc();
+
+    const replaced = await program.account.protectionOrder.fetch(orderPDA);
+    assert.ok(replaced.owner.equals(buyer.publicKey));
+    assert.equal(replaced.active, true);
+  });
+
+  it('flash mints through a callback and burns the loan back with a rounded-up fee', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+    await mintDsc(new BN(10_000));
+    await governance({ setFlashMint: { cap: new BN(1_000_000), feeBps: new BN(9) } });
+
+    // The memo program stands in for a receiver's callback: it runs while
+    // the loan is out and leaves the balance alone.
+    const MEMO_PROGRAM_ID = new PublicKey('MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr');
+    const flash = (receiver: Keypair, amount: number) =>
+      program.methods
+        .flashMint(new BN(amount), Buffer.from('flash'))
+        .accountsStrict({
+          engine: enginePDA,
+          receiver: receiver.publicKey,
+          dscMint: dscMint,
+          receiverDscAccount: getAssociatedTokenAddressSync(dscMint, receiver.publicKey, false, TOKEN_2022_PROGRAM_ID),
+          callbackProgram: MEMO_PROGRAM_ID,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        })
+        .signers([receiver])
+        .rpc();
+
+    const before = await tokenBalance(userDSCAccount);
+    // 9 bps of 1_000 is 0.9, charged as 1.
+    await flash(user, 1_000);
+    assert.strictEqual(await tokenBalance(userDSCAccount), before - 1n);
+
+    await flash(user, 100_000);
+    assert.strictEqual(await tokenBalance(userDSCAccount), before - 91n);
+
+    // Without DSC for the fee the closing burn fails and the loan unwinds.
+    const broke = fundedKeypair();
+    await assert.rejects(flash(broke, 1_000));
+    const brokeDsc = getAssociatedTokenAddressSync(dscMint, broke.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    assert.strictEqual(await tokenBalance(brokeDsc), 0n);
+
+    await assert.rejects(flash(user, 1_000_001), /FlashMintCapExceeded/);
+  });
+
+  it('soft-liquidates a slice of a position inside the band', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    await governance({
+      setSoftLiquidation: { band: new BN(10), discountBps: new BN(200), maxRepayBps: new BN(5_000) },
+    });
+
+    // Health factor 5 with min 1 and a band of 10: six tenths into the band.
+    await program.methods
+      .mintDsc(new BN(100_000_000_000))
+      .accountsStrict({
+        engine: enginePDA,
+        userData: userPDA,
+        tokenMint: mint,
+        user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
+        dscMint: dscMint,
+        deposit: depositPDA,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        userDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const [insuranceFundPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('insurance'), enginePDA.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+    const insuranceVault = getAssociatedTokenAddressSync(mint, insuranceFundPDA, true, TOKEN_PROGRAM_ID);
+    await program.methods
+      .initInsuranceFund()
+      .accountsStrict({
+        engine: enginePDA,
+        roles: rolesPDA,
+        admin: user.publicKey,
+        tokenMint: mint,
+        insuranceFund: insuranceFundPDA,
+        insuranceVault,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const keeper = fundedKeypair();
+    const keeperDsc = getAssociatedTokenAddressSync(dscMint, keeper.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    const DSC_DECIMALS = 6;
+    const tx = new Transaction().add(
+      createAssociatedTokenAccountInstruction(
+        user.publicKey,
+        keeperDsc,
+        keeper.publicKey,
+        dscMint,
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createTransferCheckedInstruction(
+        userDSCAccount,
+        dscMint,
+        keeperDsc,
+        user.publicKey,
+        40_000_000_000,
+        DSC_DECIMALS,
+        [],
+        TOKEN_2022_PROGRAM_ID
+      )
+    );
+    tx.feePayer = user.publicKey;
+    tx.recentBlockhash = context.lastBlockhash;
+    tx.sign(user);
+    await banksClient.processTransaction(tx);
+
+    const liquidateAccounts = {
+      engine: enginePDA,
+      userData: userPDA,
+      deposit: depositPDA,
+      liquidator: keeper.publicKey,
+      tokenMint: mint,
+      config: configPDA,
+      positionIndex: positionIndexPDA,
+      vault: vaultATA,
+      insuranceFund: insuranceFundPDA,
+      insuranceVault,
+      liquidatorTokenAccount: getAssociatedTokenAddressSync(mint, keeper.publicKey),
+      dscMint: dscMint,
+      liquidatorDscAccount: keeperDsc,
+      price: pricePDA,
+      systemProgram: SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+    };
+
+    await assert.rejects(
+      program.methods
+        .softLiquidate(new BN(40_000_000_000))
+        .accountsStrict(liquidateAccounts)
+        .signers([keeper])
+        .rpc(),
+      /SoftLiquidationCapExceeded/
+    );
+
+    await program.methods
+      .softLiquidate(new BN(10_000_000_000))
+      .accountsStrict(liquidateAccounts)
+      .signers([keeper])
+      .rpc();
+
+    const userData = await program.account.userData.fetch(userPDA);
+    const deposit = await program.account.deposit.fetch(depositPDA);
+    assert.strictEqual(userData.borrowedAmount.toNumber(), 90_000_000_000);
+    assert.strictEqual(deposit.tokenAmt.toNumber(), 100_000_000 - 1_020_000);
+  });
+
+  it('routes the insurance share of the configured liquidation bonus into the fund', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    await governance({
+      setEngineParams: {
+        liquidationThreshold: new BN(50),
+        minHealthFactor: new BN(1_000_000),
+        liquidationBonus: new BN(20),
+        feePercent: new BN(8),
+      },
+    });
+    await governance({ setLiquidationInsurance: { bps: new BN(5_000) } });
+
+    await mintDsc(new BN(100_000_000_000));
+    const { insuranceFund, insuranceVault } = await initInsuranceFund();
+    const { keeper, keeperDsc, keeperCollateral } = await keeperWithDsc(100_000_000_000);
+
+    // A tenth of the price halves the health factor to 0.5.
+    await setPrice(new BN(10_000_000));
+
+    const liquidateAccounts = {
+      engine: enginePDA,
+      userData: userPDA,
+      deposit: depositPDA,
+      liquidator: keeper.publicKey,
+      tokenMint: mint,
+      config: configPDA,
+      positionIndex: positionIndexPDA,
+      vault: vaultATA,
+      insuranceFund,
+      insuranceVault,
+      liquidatorTokenAccount: keeperCollateral,
+      dscMint: dscMint,
+      liquidatorDscAccount: keeperDsc,
+      price: pricePDA,
+      systemProgram: SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+    };
+
+    // 40k DSC buys 40 tokens plus an 8 token bonus, half of which is insured.
+    await program.methods
+      .liquidateUser(new BN(40_000_000_000))
+      .accountsStrict(liquidateAccounts)
+      .signers([keeper])
+      .rpc();
+
+    let fund = await program.account.insuranceFund.fetch(insuranceFund);
+    assert.strictEqual(fund.balance.toNumber(), 4_000_000);
+    assert.strictEqual(await tokenBalance(insuranceVault), 4_000_000n);
+    assert.strictEqual(await tokenBalance(keeperCollateral), 44_000_000n);
+
+    // Without the fund's accounts the liquidator keeps the whole bonus.
+    await program.methods
+      .liquidateUser(new BN(20_000_000_000))
+      .accountsStrict({ ...liquidateAccounts, insuranceFund: null, insuranceVault: null })
+      .signers([keeper])
+      .rpc();
+
+    fund = await program.account.insuranceFund.fetch(insuranceFund);
+    assert.strictEqual(fund.balance.toNumber(), 4_000_000);
+    assert.strictEqual(await tokenBalance(keeperCollateral), 68_000_000n);
+
+    // Paying the fund out takes a timelocked governance action.
+    const userBefore = await tokenBalance(userATA);
+    await governance(
+      { withdrawInsurance: { tokenMint: mint, amount: new BN(3_000_000), destination: userATA } },
+      { insuranceFund, insuranceVault, destination: userATA, tokenMint: mint, tokenProgram: TOKEN_PROGRAM_ID }
+    );
+
+    fund = await program.account.insuranceFund.fetch(insuranceFund);
+    assert.strictEqual(fund.balance.toNumber(), 1_000_000);
+    assert.strictEqual(await tokenBalance(insuranceVault), 1_000_000n);
+    assert.strictEqual(await tokenBalance(userATA), userBefore + 3_000_000n);
+  });
+
+  it('blocks risk-adding paths while paused and keeps the exits open', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+    await governance({
+      setEngineParams: {
+        liquidationThreshold: new BN(50),
+        minHealthFactor: new BN(1_000_000),
+        liquidationBonus: new BN(10),
+        feePercent: new BN(8),
+      },
+    });
+    await mintDsc(new BN(100_000_000_000));
+    const { keeper, keeperDsc, keeperCollateral } = await keeperWithDsc(60_000_000_000);
+
+    await program.methods
+      .pause()
+      .accountsStrict({ roles: rolesPDA, guardian: user.publicKey, engine: enginePDA })
+      .signers([user])
+      .rpc();
+
+    await assert.rejects(depositCollateral(new BN(1_000)), /ProtocolPaused/);
+    await assert.rejects(mintDsc(new BN(1_000)), /ProtocolPaused/);
+
+    await program.methods
+      .repayDsc(new BN(1_000))
+      .accountsStrict({
+        payer: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        dscMint: dscMint,
+        userData: userPDA,
+        deposit: depositPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        payerDscAccount: userDSCAccount,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+    await program.methods
+      .withdrawCollateralAmount(new BN(1_000))
+      .accountsStrict({
+        user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
+        userData: userPDA,
+        engine: enginePDA,
+        tokenMint: mint,
+        dscMint: dscMint,
+        deposit: depositPDA,
+        price: pricePDA,
+        config: configPDA,
+        positionIndex: positionIndexPDA,
+        vault: vaultATA,
+        userTokenAccount: userATA,
+        userDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    let userData = await program.account.userData.fetch(userPDA);
+    assert.strictEqual(userData.borrowedAmount.toNumber(), 99_999_999_000);
+    assert.strictEqual((await program.account.deposit.fetch(depositPDA)).tokenAmt.toNumber(), 99_999_000);
+
+    // Underwater positions can still be liquidated.
+    await setPrice(new BN(10_000_000));
+    await program.methods
+      .liquidateUser(new BN(40_000_000_000))
+      .accountsStrict({
+        engine: enginePDA,
+        userData: userPDA,
+        deposit: depositPDA,
+        liquidator: keeper.publicKey,
+        tokenMint: mint,
+        config: configPDA,
+        positionIndex: positionIndexPDA,
+        vault: vaultATA,
+        insuranceFund: null,
+        insuranceVault: null,
+        liquidatorTokenAccount: keeperCollateral,
+        dscMint: dscMint,
+        liquidatorDscAccount: keeperDsc,
+        price: pricePDA,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([keeper])
+      .rpc();
+
+    userData = await program.account.userData.fetch(userPDA);
+    assert.strictEqual(userData.borrowedAmount.toNumber(), 59_999_999_000);
+    assert.strictEqual(await tokenBalance(keeperCollateral), 44_000_000n);
+  });
+
+  it('sweeps the insurance share of collected fees into the fund', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    await governance(
+      {
+        setFeeSplit: {
+          tokenMint: mint,
+          lpFeeBps: new BN(5_000),
+          treasuryFeeBps: new BN(3_000),
+          insuranceFeeBps: new BN(2_000),
+          savingsFeeBps: new BN(0),
+        },
+      },
+      { config: configPDA }
+    );
+    await mintDsc(new BN(100_000_000_000));
+    const { insuranceFund, insuranceVault } = await initInsuranceFund();
+
+    const sweepAccounts = {
+      engine: enginePDA,
+      tokenMint: mint,
+      config: configPDA,
+      vault: vaultATA,
+      insuranceFund,
+      insuranceVault,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    };
+    // Redemption fees are split like any other collateral fee.
+    await program.methods
+      .redeemDsc(new BN(10_000_000_000), new BN(10_000))
+      .accountsStrict({
+        redeemer: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        vault: vaultATA,
+        redeemerTokenAccount: userATA,
+        dscMint: dscMint,
+        redeemerDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([
+        { pubkey: userPDA, isSigner: false, isWritable: true },
+        { pubkey: depositPDA, isSigner: false, isWritable: true },
+      ])
+      .signers([user])
+      .rpc();
+
+    const collected = (await program.account.config.fetch(configPDA)).insuranceCollected;
+    assert.ok(collected.gtn(0));
+
+    await program.methods.sweepInsuranceFees().accountsStrict(sweepAccounts).rpc();
+
+    const fund = await program.account.insuranceFund.fetch(insuranceFund);
+    const config = await program.account.config.fetch(configPDA);
+    assert.ok(fund.balance.eq(collected));
+    assert.strictEqual(await tokenBalance(insuranceVault), BigInt(collected.toString()));
+    assert.strictEqual(config.insuranceCollected.toNumber(), 0);
+  });
+
+  it('funds savings from fees and accrues chi up to the buffer', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    const CHI = 1_000_000_000_000n;
+    const YEAR = 31_536_000n;
+    const RATE = 1_000n;
+    const [savingsPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('savings'), enginePDA.toBuffer()],
+      program.programId
+    );
+    const [savingsPositionPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('savings_position'), enginePDA.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    const savingsVault = getAssociatedTokenAddressSync(dscMint, savingsPDA, true, TOKEN_2022_PROGRAM_ID);
+
+    await program.methods
+      .initSavings(new BN(RATE.toString()))
+      .accountsStrict({
+        roles: rolesPDA,
+        engine: enginePDA,
+        admin: user.publicKey,
+        dscMint: dscMint,
+        savings: savingsPDA,
+        vault: savingsVault,
+        systemProgram: SystemProgram.programId,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    await governance(
+      {
+        setFeeSplit: {
+          tokenMint: mint,
+          lpFeeBps: new BN(5_000),
+          treasuryFeeBps: new BN(3_000),
+          insuranceFeeBps: new BN(0),
+          savingsFeeBps: new BN(2_000),
+        },
+      },
+      { config: configPDA }
+    );
+    await mintDsc(new BN(100_000_000_000));
+    await program.methods
+      .redeemDsc(new BN(10_000_000_000), new BN(10_000))
+      .accountsStrict({
+        redeemer: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        vault: vaultATA,
+        redeemerTokenAccount: userATA,
+        dscMint: dscMint,
+        redeemerDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([
+        { pubkey: userPDA, isSigner: false, isWritable: true },
+        { pubkey: depositPDA, isSigner: false, isWritable: true },
+      ])
+      .signers([user])
+      .rpc();
+
+    const collected = (await program.account.config.fetch(configPDA)).savingsCollected;
+    assert.ok(collected.gtn(0));
+
+    const dscBefore = await tokenBalance(userDSCAccount);
+    await program.methods
+      .sweepSavingsFees()
+      .accountsStrict({
+        buyer: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        dscMint: dscMint,
+        config: configPDA,
+        price: pricePDA,
+        vault: vaultATA,
+        savings: savingsPDA,
+        savingsVault,
+        buyerDscAccount: userDSCAccount,
+        buyerTokenAccount: userATA,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    // Fees are sold at the stored price: collateral * price / 1e4.
+    const feeDsc = (BigInt(collected.toString()) * 100_000_000n) / 10_000n;
+    let savings = await program.account.dscSavings.fetch(savingsPDA);
+    assert.equal(BigInt(savings.buffer.toString()), feeDsc);
+    assert.equal(await tokenBalance(userDSCAccount), dscBefore - feeDsc);
+    assert.ok((await program.account.config.fetch(configPDA)).savingsCollected.isZero());
+
+    await program.methods
+      .fundSavings(new BN(10_000_000))
+      .accountsStrict({
+        funder: user.publicKey,
+        engine: enginePDA,
+        dscMint: dscMint,
+        savings: savingsPDA,
+        vault: savingsVault,
+        funderDscAccount: userDSCAccount,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const lock = (amount: number) =>
+      program.methods
+        .lockDsc(new BN(amount))
+        .accountsStrict({
+          user: user.publicKey,
+          engine: enginePDA,
+          dscMint: dscMint,
+          savings: savingsPDA,
+          vault: savingsVault,
+          position: savingsPositionPDA,
+          userDscAccount: userDSCAccount,
+          systemProgram: SystemProgram.programId,
+          dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        })
+        .signers([user])
+        .rpc();
+    const warp = async (seconds: bigint) => {
+      const clock = await banksClient.getClock();
+      context.setClock(
+        new Clock(
+          clock.slot,
+          clock.epochStartTimestamp,
+          clock.epoch,
+          clock.leaderScheduleEpoch,
+          clock.unixTimestamp + seconds
+        )
+      );
+    };
+    // Mirrors drip: growth capped so the buffer always covers the shares.
+    const expectDrip = (chi: bigint, shares: bigint, buffer: bigint, elapsed: bigint) => {
+      let growth = (chi * RATE * elapsed) / (10_000n * YEAR);
+      let owed = (shares * growth) / CHI;
+      if (owed > buffer) {
+        growth = (buffer * CHI) / shares;
+        owed = (shares * growth) / CHI;
+      }
+      return { chi: chi + growth, buffer: buffer - owed, owed };
+    };
+
+    await lock(1_000_000_000);
+    savings = await program.account.dscSavings.fetch(savingsPDA);
+    let shares = BigInt(savings.totalShares.toString());
+    let buffer = BigInt(savings.buffer.toString());
+    assert.equal(BigInt(savings.chi.toString()), CHI);
+
+    // A hundredth of a year at 10% grows chi by a thousandth, well inside the buffer.
+    await warp(YEAR / 100n);
+    await lock(1_000);
+    let expected = expectDrip(CHI, shares, buffer, YEAR / 100n);
+    savings = await program.account.dscSavings.fetch(savingsPDA);
+    assert.equal(BigInt(savings.chi.toString()), CHI + CHI / 1_000n);
+    assert.equal(BigInt(savings.buffer.toString()), expected.buffer);
+
+    // A century owes far more than the buffer holds, so growth stops at it.
+    const chi = BigInt(savings.chi.toString());
+    shares = BigInt(savings.totalShares.toString());
+    buffer = BigInt(savings.buffer.toString());
+    await warp(100n * YEAR);
+    await lock(2_000);
+    expected = expectDrip(chi, shares, buffer, 100n * YEAR);
+    savings = await program.account.dscSavings.fetch(savingsPDA);
+    assert.ok(expected.owed <= buffer);
+    assert.equal(BigInt(savings.chi.toString()), expected.chi);
+    assert.equal(BigInt(savings.buffer.toString()), expected.buffer);
+    assert.ok(expected.chi < chi + (chi * RATE * 100n) / 10_000n);
+  });
+
+  it('writes off bad debt with the insurance fund at a fresh price', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    await governance({
+      setEngineParams: {
+        liquidationThreshold: new BN(50),
+        minHealthFactor: new BN(1_000_000),
+        liquidationBonus: new BN(10),
+        feePercent: new BN(8),
+      },
+    });
+    await governance({ setLiquidationInsurance: { bps: new BN(10_000) } });
+
+    await mintDsc(new BN(100_000_000_000));
+    const { insuranceFund, insuranceVault } = await initInsuranceFund();
+    const { keeper, keeperDsc, keeperCollateral } = await keeperWithDsc(100_000_000_000);
+
+    // Liquidating 90% at a tenth of the price fills the fund with 9 tokens and
+    // leaves 1 token against 10k DSC.
+    await setPrice(new BN(10_000_000));
+    await program.methods
+      .liquidateUser(new BN(90_000_000_000))
+      .accountsStrict({
+        engine: enginePDA,
+        userData: userPDA,
+        deposit: depositPDA,
+        liquidator: keeper.publicKey,
+        tokenMint: mint,
+        config: configPDA,
+        positionIndex: positionIndexPDA,
+        vault: vaultATA,
+        insuranceFund,
+        insuranceVault,
+        liquidatorTokenAccount: keeperCollateral,
+        dscMint: dscMint,
+        liquidatorDscAccount: keeperDsc,
+        price: pricePDA,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([keeper])
+      .rpc();
+    assert.strictEqual((await program.account.insuranceFund.fetch(insuranceFund)).balance.toNumber(), 9_000_000);
+
+    const coverAccounts = {
+      engine: enginePDA,
+      liquidator: keeper.publicKey,
+      tokenMint: mint,
+      userData: userPDA,
+      deposit: depositPDA,
+      price: pricePDA,
+      config: configPDA,
+      positionIndex: positionIndexPDA,
+      vault: vaultATA,
+      insuranceFund,
+      insuranceVault,
+      liquidatorTokenAccount: keeperCollateral,
+      dscMint: dscMint,
+      liquidatorDscAccount: keeperDsc,
+      systemProgram: SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+    };
+
+    const clock = await banksClient.getClock();
+    context.setClock(
+      new Clock(
+        clock.slot,
+        clock.epochStartTimestamp,
+        clock.epoch,
+        clock.leaderScheduleEpoch,
+        clock.unixTimestamp + 101n
+      )
+    );
+    await assert.rejects(
+      program.methods
+        .coverBadDebt()
+        .accountsStrict({
+          ...coverAccounts,
+          liquidator: user.publicKey,
+          liquidatorTokenAccount: userATA,
+          liquidatorDscAccount: userDSCAccount,
+        })
+        .signers([user])
+        .rpc(),
+      /StalePrice/
+    );
+
+    await setPrice(new BN(20_000_000));
+    const keeperBefore = await tokenBalance(keeperCollateral);
+    await program.methods.coverBadDebt().accountsStrict(coverAccounts).signers([keeper]).rpc();
+
+    // 10k DSC is 5 tokens at the new price, 5.5 with the bonus: 1 from the
+    // position and 4.5 from the fund.
+    const userData = await program.account.userData.fetch(userPDA);
+    const deposit = await program.account.deposit.fetch(depositPDA);
+    const fund = await program.account.insuranceFund.fetch(insuranceFund);
+    assert.strictEqual(userData.borrowedAmount.toNumber(), 0);
+    assert.strictEqual(deposit.tokenAmt.toNumber(), 0);
+    assert.strictEqual(fund.balance.toNumber(), 4_500_000);
+    assert.strictEqual((await tokenBalance(keeperCollateral)) - keeperBefore, 5_500_000n);
+    assert.strictEqual(await tokenBalance(keeperDsc), 0n);
+  });
+
+  it('enters recovery mode below the critical ratio and blocks minting', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    const mintAccounts = {
+      engine: enginePDA,
+      userData: userPDA,
+      tokenMint: mint,
+      user: user.publicKey,
+      owner: user.publicKey,
+      delegation: null,
+      positionNft: null,
+      dscMint: dscMint,
+      deposit: depositPDA,
+      config: configPDA,
+      price: pricePDA,
+      positionIndex: positionIndexPDA,
+      userDscAccount: userDSCAccount,
+      systemProgram: SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+    };
+    await program.methods
+      .mintDsc(new BN(10_000))
+      .accountsStrict(mintAccounts)
+      .signers([user])
+      .rpc();
+
+    const updateTotals = () =>
+      program.methods
+        .updateSystemTotals()
+        .accountsStrict({ engine: enginePDA, registry: registryPDA })
+        .remainingAccounts([
+          { pubkey: positionIndexPDA, isSigner: false, isWritable: false },
+          { pubkey: pricePDA, isSigner: false, isWritable: false },
+        ])
+        .rpc();
+
+    await updateTotals();
+    let engine = await program.account.engine.fetch(enginePDA);
+    assert.strictEqual(engine.systemDebt.toNumber(), 10_000);
+    assert.ok(!engine.recoveryMode);
+
+    // A ratio no position here can meet, so the system is undercollateralized.
+    await governance({ setCriticalCollateralRatio: { ratioBps: new BN(10).pow(new BN(13)) } });
+
+    await updateTotals();
+    engine = await program.account.engine.fetch(enginePDA);
+    assert.ok(engine.recoveryMode);
+
+    await assert.rejects(
+      program.methods
+        .mintDsc(new BN(1_000))
+        .accountsStrict(mintAccounts)
+        .signers([user])
+        .rpc(),
+      /RecoveryModeMint/
+    );
+  });
+
+  it('spreads the system totals crank over several calls', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+    await mintDsc(new BN(10_000));
+
+    const otherMint = await createMint(banksClient, user, user.publicKey, null, 6);
+    const pda = (seed: string) =>
+      PublicKey.findProgramAddressSync(
+        [Buffer.from(seed), enginePDA.toBuffer(), otherMint.toBuffer()],
+        program.programId
+      )[0];
+    const [otherConfig, otherPrice, otherIndex] = [pda('config'), pda('price'), pda('position_index')];
+    await program.methods
+      .startToken(new BN(100_000_000))
+      .accountsStrict({
+        engine: enginePDA,
+        roles: rolesPDA,
+        registry: registryPDA,
+        config: otherConfig,
+        price: otherPrice,
+        positionIndex: otherIndex,
+        tokenMint: otherMint,
+        vault: getAssociatedTokenAddressSync(otherMint, otherConfig, true),
+        admin: user.publicKey,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const crank = (index: PublicKey, price: PublicKey) =>
+      program.methods
+        .updateSystemTotals()
+        .accountsStrict({ engine: enginePDA, registry: registryPDA })
+        .remainingAccounts([
+          { pubkey: index, isSigner: false, isWritable: false },
+          { pubkey: price, isSigner: false, isWritable: false },
+        ])
+        .rpc();
+
+    // The first call only covers the first token, so the pass stays open.
+    await crank(positionIndexPDA, pricePDA);
+    let engine = await program.account.engine.fetch(enginePDA);
+    assert.strictEqual(engine.totalsCursor, 1);
+    assert.strictEqual(engine.pendingDebt.toNumber(), 10_000);
+    assert.strictEqual(engine.pendingCollateralValue.toNumber(), 1_000_000_000_000);
+
+    await crank(otherIndex, otherPrice);
+    engine = await program.account.engine.fetch(enginePDA);
+    assert.strictEqual(engine.totalsCursor, 0);
+    assert.ok(engine.pendingDebt.isZero());
+    assert.strictEqual(engine.systemDebt.toNumber(), 10_000);
+    assert.strictEqual(engine.systemCollateralValue.toNumber(), 1_000_000_000_000);
+  });
+
+  it('keeps system totals and recovery mode current without the crank', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    await mintDsc(new BN(10_000));
+    let engine = await program.account.engine.fetch(enginePDA);
+    assert.equal(engine.systemDebt.toNumber(), 10_000);
+    assert.equal(engine.systemCollateralValue.toNumber(), 1_000_000_000_000);
+    assert.equal(engine.recoveryMode, false);
+
+    // Deposits and repayments move the totals as well.
+    await depositCollateral(new BN(50_000_000));
+    await program.methods
+      .repayDsc(new BN(4_000))
+      .accountsStrict({
+        payer: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        dscMint: dscMint,
+        userData: userPDA,
+        deposit: depositPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        payerDscAccount: userDSCAccount,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+    engine = await program.account.engine.fetch(enginePDA);
+    assert.equal(engine.systemDebt.toNumber(), 6_000);
+    assert.equal(engine.systemCollateralValue.toNumber(), 1_500_000_000_000);
+
+    await governance({ setCriticalCollateralRatio: { ratioBps: new BN(10).pow(new BN(13)) } });
+    engine = await program.account.engine.fetch(enginePDA);
+    assert.equal(engine.recoveryMode, true);
+
+    await assert.rejects(mintDsc(new BN(1_000)), /RecoveryModeMint/);
+  });
+
+  it('full workflow: config -> engine -> deposit -> mint DSC', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    await program.methods
+      .mintDsc(new BN(10_000))
+      .accountsStrict({
+        engine: enginePDA,
+        userData: userPDA,
+        tokenMint: mint,
+        user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
+        dscMint: dscMint,
+        deposit: depositPDA,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        userDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+  });
+
+  it('withdraws collateral', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    // A high price keeps the fee, which rounds down twice, above zero.
+    await setPrice(new BN(10_000_000_000));
+    await mintDsc(new BN(2_000_000_000_000));
+
+    const userBefore = await tokenBalance(userATA);
+    await program.methods
+      .withdrawCollateral(new BN(1_000_000_000_000))
+      .accountsStrict({
+        user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
+        userData: userPDA,
+        engine: enginePDA,
+        tokenMint: mint,
+        dscMint: dscMint,
+        deposit: depositPDA,
+        price: pricePDA,
+        config: configPDA,
+        positionIndex: positionIndexPDA,
+        vault: vaultATA,
+        userTokenAccount: userATA,
+        userDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    // 1M DSC is 1_000_000 tokens at the price; the 8e-8 fee is 80 of them,
+    // kept in the vault and split 75/25 between LPs and the treasury.
+    const deposit = await program.account.deposit.fetch(depositPDA);
+    const userData = await program.account.userData.fetch(userPDA);
+    const config = await program.account.config.fetch(configPDA);
+    assert.strictEqual(deposit.tokenAmt.toNumber(), 99_000_000);
+    assert.strictEqual(userData.borrowedAmount.toString(), '1000000000000');
+    assert.strictEqual((await tokenBalance(userATA)) - userBefore, 999_920n);
+    assert.strictEqual(config.totalCollected.toNumber(), 60);
+    assert.strictEqual(config.treasuryCollected.toNumber(), 20);
+    assert.strictEqual(await tokenBalance(vaultATA), 99_000_080n);
+  });
+
+  it('moves the treasury share of fees into the treasury and pays the fee collector', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+    await setPrice(new BN(10_000_000_000));
+    await mintDsc(new BN(2_000_000_000_000));
+
+    await program.methods
+      .withdrawCollateral(new BN(1_000_000_000_000))
+      .accountsStrict({
+        user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
+        userData: userPDA,
+        engine: enginePDA,
+        tokenMint: mint,
+        dscMint: dscMint,
+        deposit: depositPDA,
+        price: pricePDA,
+        config: configPDA,
+        positionIndex: positionIndexPDA,
+        vault: vaultATA,
+        userTokenAccount: userATA,
+        userDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const [treasuryPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('treasury'), enginePDA.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+    const treasuryVault = getAssociatedTokenAddressSync(mint, treasuryPDA, true);
+    await program.methods
+      .initTreasury()
+      .accountsStrict({
+        engine: enginePDA,
+        roles: rolesPDA,
+        admin: user.publicKey,
+        tokenMint: mint,
+        treasury: treasuryPDA,
+        treasuryVault,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const vaultBefore = await tokenBalance(vaultATA);
+    await program.methods
+      .sweepTreasuryFees()
+      .accountsStrict({
+        engine: enginePDA,
+        tokenMint: mint,
+        config: configPDA,
+        vault: vaultATA,
+        treasury: treasuryPDA,
+        treasuryVault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    assert.strictEqual((await program.account.treasury.fetch(treasuryPDA)).balance.toNumber(), 20);
+    assert.strictEqual(await tokenBalance(treasuryVault), 20n);
+    assert.strictEqual(await tokenBalance(vaultATA), vaultBefore - 20n);
+    assert.ok((await program.account.config.fetch(configPDA)).treasuryCollected.isZero());
+
+    const collectorBefore = await tokenBalance(userATA);
+    await program.methods
+      .collectProtocolFees()
+      .accountsStrict({
+        engine: enginePDA,
+        roles: rolesPDA,
+        feeCollector: user.publicKey,
+        tokenMint: mint,
+        treasury: treasuryPDA,
+        treasuryVault,
+        treasuryTokenAccount: userATA,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    assert.strictEqual((await tokenBalance(userATA)) - collectorBefore, 20n);
+    assert.strictEqual(await tokenBalance(treasuryVault), 0n);
+    assert.ok((await program.account.treasury.fetch(treasuryPDA)).balance.isZero());
+  });
+
+  it('swaps through the PSM across decimals and sweeps its fees into the treasury', async () => {
+    // An 8-decimal stablecoin against 6-decimal DSC.
+    const stableMint = await createMint(banksClient, user, user.publicKey, null, 8);
+    const userStable = await createAssociatedTokenAccount(banksClient, user, stableMint, user.publicKey);
+    await mintTo(banksClient, user, stableMint, userStable, user, 1_000_000_050);
+
+    const [psmAssetPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('psm'), enginePDA.toBuffer(), stableMint.toBuffer()],
+      program.programId
+    );
+    const psmVault = getAssociatedTokenAddressSync(stableMint, enginePDA, true);
+    await program.methods
+      .addPsmAsset(new BN(10), new BN(20), new BN(1_000_000_000_000))
+      .accountsStrict({
+        roles: rolesPDA,
+        engine: enginePDA,
+        admin: user.publicKey,
+        stableMint,
+        psmAsset: psmAssetPDA,
+        vault: psmVault,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const swapAccounts = {
+      user: user.publicKey,
+      engine: enginePDA,
+      dscMint,
+      stableMint,
+      psmAsset: psmAssetPDA,
+      vault: psmVault,
+      userStableAccount: userStable,
+      userDscAccount: userDSCAccount,
+      systemProgram: SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+    };
+
+    // 10.0000005 in: the sub-DSC dust truncates, 10 bps comes off the
+    // 10 DSC, and both stay in the vault as fees.
+    await program.methods.psmSwapIn(new BN(1_000_000_050)).accountsStrict(swapAccounts).signers([user]).rpc();
+    assert.strictEqual(await tokenBalance(userDSCAccount), 9_990_000n);
+    let psmAsset = await program.account.psmAsset.fetch(psmAssetPDA);
+    assert.strictEqual(psmAsset.dscMinted.toNumber(), 9_990_000);
+    assert.strictEqual(psmAsset.feesCollected.toNumber(), 1_000_050);
+    assert.strictEqual((await program.account.engine.fetch(enginePDA)).psmDebt.toNumber(), 9_990_000);
+
+    // 5 DSC out at 20 bps pays 4.99 of the stablecoin; the 0.01 it would
+    // have paid without the fee stays behind.
+    await program.methods.psmSwapOut(new BN(5_000_000)).accountsStrict(swapAccounts).signers([user]).rpc();
+    assert.strictEqual(await tokenBalance(userStable), 499_000_000n);
+    assert.strictEqual(await tokenBalance(userDSCAccount), 4_990_000n);
+    psmAsset = await program.account.psmAsset.fetch(psmAssetPDA);
+    assert.strictEqual(psmAsset.dscMinted.toNumber(), 4_990_000);
+    assert.strictEqual(psmAsset.feesCollected.toNumber(), 2_000_050);
+    assert.strictEqual(await tokenBalance(psmVault), 501_000_050n);
+
+    const [treasuryPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('treasury'), enginePDA.toBuffer(), stableMint.toBuffer()],
+      program.programId
+    );
+    const treasuryVault = getAssociatedTokenAddressSync(stableMint, treasuryPDA, true);
+    await program.methods
+      .initTreasury()
+      .accountsStrict({
+        engine: enginePDA,
+        roles: rolesPDA,
+        admin: user.publicKey,
+        tokenMint: stableMint,
+        treasury: treasuryPDA,
+        treasuryVault,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    await program.methods
+      .sweepPsmFees()
+      .accountsStrict({
+        engine: enginePDA,
+        stableMint,
+        psmAsset: psmAssetPDA,
+        vault: psmVault,
+        treasury: treasuryPDA,
+        treasuryVault,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .rpc();
+
+    // What is left in the vault backs exactly the DSC still out.
+    assert.strictEqual(await tokenBalance(psmVault), 499_000_000n);
+    assert.strictEqual(await tokenBalance(treasuryVault), 2_000_050n);
+    assert.strictEqual((await program.account.treasury.fetch(treasuryPDA)).balance.toNumber(), 2_000_050);
+    assert.ok((await program.account.psmAsset.fetch(psmAssetPDA)).feesCollected.isZero());
+
+    // Fees and the cap are changed through the timelock.
+    await governance(
+      { setPsmAsset: { stableMint, feeInBps: new BN(10), feeOutBps: new BN(20), cap: new BN(5_000_000) } },
+      { psmAsset: psmAssetPDA }
+    );
+    await assert.rejects(
+      program.methods.psmSwapIn(new BN(100_000_000)).accountsStrict(swapAccounts).signers([user]).rpc(),
+      /PsmCapExceeded/
+    );
+  });
+
+  it('redeems DSC against the riskiest position', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    await program.methods
+      .mintDsc(new BN(5_000000))
+      .accountsStrict({
+        engine: enginePDA,
+        userData: userPDA,
+        tokenMint: mint,
+        user: user.publicKey,
+        owner: user.publicKey,
+        delegation: null,
+        positionNft: null,
+        dscMint: dscMint,
+        deposit: depositPDA,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        userDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const before = await program.account.userData.fetch(userPDA);
+
+    await program.methods
+      .redeemDsc(new BN(1_000000), new BN(10_000))
+      .accountsStrict({
+        redeemer: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        config: configPDA,
+        price: pricePDA,
+        positionIndex: positionIndexPDA,
+        vault: vaultATA,
+        redeemerTokenAccount: userATA,
+        dscMint: dscMint,
+        redeemerDscAccount: userDSCAccount,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .remainingAccounts([
+        { pubkey: userPDA, isSigner: false, isWritable: true },
+        { pubkey: depositPDA, isSigner: false, isWritable: true },
+      ])
+      .signers([user])
+      .rpc();
+
+    const after = await program.account.userData.fetch(userPDA);
+    assert.strictEqual(before.borrowedAmount.sub(after.borrowedAmount).toNumber(), 1_000_000);
+  });
+  it('queues a timelocked action and lets the guardian cancel it', async () => {
+    await initializeConfig();
+
+    const engine = await program.account.engine.fetch(enginePDA);
+    const [pendingActionPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('pending_action'), enginePDA.toBuffer(), engine.actionCount.toArrayLike(Buffer, 'le', 8)],
+      program.programId
+    );
+
+    await program.methods
+      .queueAction({
+        setCollateralStatus: { tokenMint: mint, status: 1 },
+      })
+      .accountsStrict({
+        roles: rolesPDA,
+        engine: enginePDA,
+        proposer: user.publicKey,
+        pendingAction: pendingActionPDA,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    await assert.rejects(
+      program.methods
+        .executeAction()
+        .accountsStrict({
+          executor: user.publicKey,
+          engine: enginePDA,
+          roles: rolesPDA,
+          pendingAction: pendingActionPDA,
+          proposer: user.publicKey,
+          config: configPDA,
+          registry: registryPDA,
+          psmAsset: null,
+          savings: null,
+          insuranceFund: null,
+          insuranceVault: null,
+          destination: null,
+          tokenMint: null,
+          tokenProgram: null,
+        })
+        .signers([user])
+        .rpc(),
+      /TimelockNotElapsed/
+    );
+
+    await program.methods
+      .cancelAction()
+      .accountsStrict({
+        engine: enginePDA,
+        roles: rolesPDA,
+        guardian: user.publicKey,
+        pendingAction: pendingActionPDA,
+        proposer: user.publicKey,
+      })
+      .signers([user])
+      .rpc();
+
+    const config = await program.account.config.fetch(configPDA);
+    const closed = await banksClient.getAccount(pendingActionPDA);
+    assert.strictEqual(config.status, 0);
+    assert.strictEqual(closed, null);
+  });
+  it('lets only the risk manager queue risk parameter changes', async () => {
+    await initializeConfig();
+    const risk = fundedKeypair();
+    await governance({ setRole: { role: { riskManager: {} }, holder: risk.publicKey } });
+
+    const params = {
+      setEngineParams: {
+        liquidationThreshold: new BN(50),
+        minHealthFactor: new BN(1_000_000),
+        liquidationBonus: new BN(5),
+        feePercent: new BN(8),
+      },
+    };
+    const engine = await program.account.engine.fetch(enginePDA);
+    const [pendingActionPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('pending_action'), enginePDA.toBuffer(), engine.actionCount.toArrayLike(Buffer, 'le', 8)],
+      program.programId
+    );
+    const queue = (signer: Keypair) =>
+      program.methods
+        .queueAction(params)
+        .accountsStrict({
+          roles: rolesPDA,
+          engine: enginePDA,
+          proposer: signer.publicKey,
+          pendingAction: pendingActionPDA,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([signer])
+        .rpc();
+
+    await assert.rejects(queue(user), /UnauthorizedUser/);
+    await queue(risk);
+
+    const clock = await banksClient.getClock();
+    context.setClock(
+      new Clock(
+        clock.slot,
+        clock.epochStartTimestamp,
+        clock.epoch,
+        clock.leaderScheduleEpoch,
+        clock.unixTimestamp + BigInt(engine.timelockDelay.toString())
+      )
+    );
+    await program.methods
+      .executeAction()
+      .accountsStrict({
+        executor: user.publicKey,
+        engine: enginePDA,
+        roles: rolesPDA,
+        pendingAction: pendingActionPDA,
+        proposer: risk.publicKey,
+        config: null,
+        registry: null,
+        psmAsset: null,
+        savings: null,
+        insuranceFund: null,
+        insuranceVault: null,
+        destination: null,
+        tokenMint: null,
+        tokenProgram: null,
+      })
+      .signers([user])
+      .rpc();
+
+    const updated = await program.account.engine.fetch(enginePDA);
+    assert.ok(updated.liquidationBonus.eq(new BN(5)));
+  });
+  it('lets only the configured oracle update a price', async () => {
+    await initializeConfig();
+    const oracle = fundedKeypair();
+    const priceAccounts = (signer: PublicKey) => ({
+      roles: rolesPDA,
+      oracleUpdater: signer,
+      engine: enginePDA,
+      price: pricePDA,
+      config: configPDA,
+    });
+
+    await governance({ setOracle: { tokenMint: mint, oracle: oracle.publicKey } }, { config: configPDA });
+
+    await assert.rejects(
+      program.methods
+        .updatePrice(new BN(90_000_000))
+        .accountsStrict(priceAccounts(user.publicKey))
+        .signers([user])
+        .rpc()
+    );
+    await program.methods
+      .updatePrice(new BN(90_000_000))
+      .accountsStrict(priceAccounts(oracle.publicKey))
+      .signers([oracle])
+      .rpc();
+
+    const price = await program.account.price.fetch(pricePDA);
+    assert.ok(price.price.eq(new BN(90_000_000)));
+  });
+  it('rejects a fee split that does not add up when it is queued', async () => {
+    await initializeConfig();
+
+    const engine = await program.account.engine.fetch(enginePDA);
+    const [pendingActionPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('pending_action'), enginePDA.toBuffer(), engine.actionCount.toArrayLike(Buffer, 'le', 8)],
+      program.programId
+    );
+    await assert.rejects(
+      program.methods
+        .queueAction({
+          setFeeSplit: {
+            tokenMint: mint,
+            lpFeeBps: new BN(5_000),
+            treasuryFeeBps: new BN(2_000),
+            insuranceFeeBps: new BN(2_000),
+            savingsFeeBps: new BN(0),
+          },
+        })
+        .accountsStrict({
+          roles: rolesPDA,
+          engine: enginePDA,
+          proposer: user.publicKey,
+          pendingAction: pendingActionPDA,
+          systemProgram: SystemProgram.programId,
+        })
+        .signers([user])
+        .rpc()
+    );
+
+    await governance(
+      {
+        setFeeSplit: {
+          tokenMint: mint,
+          lpFeeBps: new BN(5_000),
+          treasuryFeeBps: new BN(3_000),
+          insuranceFeeBps: new BN(2_000),
+          savingsFeeBps: new BN(0),
+        },
+      },
+      { config: configPDA }
+    );
+    const config = await program.account.config.fetch(configPDA);
+    assert.strictEqual(config.insuranceFeeBps.toNumber(), 2_000);
+  });
+  it('rejects token listing from a non-admin', async () => {
+    const outsider = fundedKeypair();
+
+    await assert.rejects(
+      program.methods
+        .startToken(new BN(100_000_000))
+        .accountsStrict({
+          engine: enginePDA,
+          roles: rolesPDA,
+          registry: registryPDA,
+          config: configPDA,
+          price: pricePDA,
+          positionIndex: positionIndexPDA,
+          tokenMint: mint,
+          vault: vaultATA,
+          admin: outsider.publicKey,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        })
+        .signers([outsider])
+        .rpc(),
+      /UnauthorizedUser/
+    );
+  });
+  it('rejects multisig thresholds outside 1..=signers', async () => {
+    const signers = [fundedKeypair(), fundedKeypair(), fundedKeypair()].map(
+      (kp) => kp.publicKey
+    );
+    const cases: [PublicKey[], number][] = [
+      [signers, 0],
+      [signers, 4],
+      [[signers[0], signers[0]], 1],
+    ];
+
+    for (const [set, threshold] of cases) {
+      await assert.rejects(
+        program.methods
+          .setMultisig(set, threshold)
+          .accountsStrict({
+            roles: rolesPDA,
+            admin: user.publicKey,
+            engine: enginePDA,
+          })
+          .signers([user])
+          .rpc(),
+        /InvalidSignerSet/
+      );
+    }
+  });
+
+  it('unpauses only once the multisig threshold is met', async () => {
+    const [a, b, c] = [fundedKeypair(), fundedKeypair(), fundedKeypair()];
+    const outsider = fundedKeypair();
+
+    await program.methods
+      .setMultisig([a.publicKey, b.publicKey, c.publicKey], 2)
+      .accountsStrict({ roles: rolesPDA, admin: user.publicKey, engine: enginePDA })
+      .signers([user])
+      .rpc();
+
+    await program.methods
+      .pause()
+      .accountsStrict({ roles: rolesPDA, guardian: user.publicKey, engine: enginePDA })
+      .signers([user])
+      .rpc();
+
+    await assert.rejects(
+      program.methods
+        .unpause()
+        .accountsStrict({ roles: rolesPDA, admin: user.publicKey, engine: enginePDA })
+        .signers([user])
+        .rpc(),
+      /MultisigRequired/
+    );
+
+    const engine = await program.account.engine.fetch(enginePDA);
+    const [proposalPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('proposal'), enginePDA.toBuffer(), engine.proposalCount.toArrayLike(Buffer, 'le', 8)],
+      program.programId
+    );
+
+    await program.methods
+      .propose({ unpause: {} })
+      .accountsStrict({
+        proposer: a.publicKey,
+        engine: enginePDA,
+        proposal: proposalPDA,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([a])
+      .rpc();
+
+    const execute = (preInstructions: TransactionInstruction[] = []) =>
+      program.methods
+        .executeProposal()
+        .accountsStrict({
+          executor: user.publicKey,
+          engine: enginePDA,
+          roles: rolesPDA,
+          proposal: proposalPDA,
+          proposer: a.publicKey,
+          tokenMint: null,
+          treasury: null,
+          vault: null,
+          treasuryTokenAccount: null,
+          tokenProgram: null,
+        })
+        .preInstructions(preInstructions)
+        .signers([user])
+        .rpc();
+
+    const approve = (signer: Keypair) =>
+      program.methods
+        .approve()
+        .accountsStrict({
+          signer: signer.publicKey,
+          engine: enginePDA,
+          proposal: proposalPDA,
+        })
+        .signers([signer])
+        .rpc();
+
+    // The compute budget instruction keeps this attempt from being a
+    // duplicate of the successful execution below.
+    await assert.rejects(
+      execute([ComputeBudgetProgram.setComputeUnitLimit({ units: 400_000 })]),
+      /ThresholdNotMet/
+    );
+    await assert.rejects(approve(a), /AlreadyApproved/);
+    await assert.rejects(approve(outsider), /NotMultisigSigner/);
+
+    await approve(b);
+    await execute();
+
+    const after = await program.account.engine.fetch(enginePDA);
+    assert.ok(!after.paused);
+  });
+  it('settles positions and cashes out packed DSC after shutdown', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+
+    const mintAccounts = {
+      engine: enginePDA,
+      userData: userPDA,
+      tokenMint: mint,
+      user: user.publicKey,
+      owner: user.publicKey,
+      delegation: null,
+      positionNft: null,
+      dscMint: dscMint,
+      deposit: depositPDA,
+      config: configPDA,
+      price: pricePDA,
+      positionIndex: positionIndexPDA,
+      userDscAccount: userDSCAccount,
+      systemProgram: SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+    };
+
+    await program.methods
+      .mintDsc(new BN(5_000000))
+      .accountsStrict(mintAccounts)
+      .signers([user])
+      .rpc();
+
+    await program.methods
+      .emergencyShutdown()
+      .accountsStrict({
+        roles: rolesPDA,
+        admin: user.publicKey,
+        engine: enginePDA,
+        dscMint: dscMint,
+      })
+      .signers([user])
+      .rpc();
+
+    await assert.rejects(
+      program.methods
+        .mintDsc(new BN(1_000000))
+        .accountsStrict(mintAccounts)
+        .signers([user])
+        .rpc(),
+      /ShutdownActive/
+    );
+
+    const [settlementPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('settlement'), enginePDA.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+    const [bagPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('bag'), enginePDA.toBuffer(), user.publicKey.toBuffer()],
+      program.programId
+    );
+    const [claimPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('cash'), enginePDA.toBuffer(), user.publicKey.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .freezeCollateral()
+      .accountsStrict({
+        payer: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        price: pricePDA,
+        settlement: settlementPDA,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+
+    await program.methods
+      .settlePosition()
+      .accountsStrict({
+        engine: enginePDA,
+        tokenMint: mint,
+        settlement: settlementPDA,
+        price: pricePDA,
+        userData: userPDA,
+        deposit: depositPDA,
+        positionIndex: positionIndexPDA,
+      })
+      .rpc();
+
+    await program.methods
+      .reclaimCollateral()
+      .accountsStrict({
+        user: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        owner: user.publicKey,
+        userData: userPDA,
+        deposit: depositPDA,
+        positionNft: null,
+        config: configPDA,
+        vault: vaultATA,
+        userTokenAccount: userATA,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    await program.methods
+      .packDsc(new BN(5_000000))
+      .accountsStrict({
+        user: user.publicKey,
+        engine: enginePDA,
+        dscMint: dscMint,
+        userDscAccount: userDSCAccount,
+        bag: bagPDA,
+        systemProgram: SystemProgram.programId,
+        dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const clock = await banksClient.getClock();
+    context.setClock(
+      new Clock(
+        clock.slot,
+        clock.epochStartTimestamp,
+        clock.epoch,
+        clock.leaderScheduleEpoch,
+        clock.unixTimestamp + BigInt(86_400)
+      )
+    );
+
+    await program.methods
+      .cashCollateral()
+      .accountsStrict({
+        user: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        settlement: settlementPDA,
+        bag: bagPDA,
+        claim: claimPDA,
+        config: configPDA,
+        vault: vaultATA,
+        userTokenAccount: userATA,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const deposit = await program.account.deposit.fetch(depositPDA);
+    const settlement = await program.account.settlement.fetch(settlementPDA);
+    assert.ok(deposit.tokenAmt.isZero());
+    assert.ok(settlement.pool.isZero());
+  });
+  it('pays every cash-out from the live settlement pool', async () => {
+    await initializeConfig();
+    await depositCollateral();
+    await createUserDSCAccount();
+    await mintDsc(new BN(5_000000));
+    const { keeper, keeperDsc, keeperCollateral } = await keeperWithDsc(2_000000);
+
+    await program.methods
+      .emergencyShutdown()
+      .accountsStrict({
+        roles: rolesPDA,
+        admin: user.publicKey,
+        engine: enginePDA,
+        dscMint: dscMint,
+      })
+      .signers([user])
+      .rpc();
+
+    const [settlementPDA] = PublicKey.findProgramAddressSync(
+      [Buffer.from('settlement'), enginePDA.toBuffer(), mint.toBuffer()],
+      program.programId
+    );
+    await program.methods
+      .freezeCollateral()
+      .accountsStrict({
+        payer: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        price: pricePDA,
+        settlement: settlementPDA,
+        systemProgram: SystemProgram.programId,
+      })
+      .signers([user])
+      .rpc();
+    await program.methods
+      .settlePosition()
+      .accountsStrict({
+        engine: enginePDA,
+        tokenMint: mint,
+        settlement: settlementPDA,
+        price: pricePDA,
+        userData: userPDA,
+        deposit: depositPDA,
+        positionIndex: positionIndexPDA,
+      })
+      .rpc();
+    const pool = BigInt((await program.account.settlement.fetch(settlementPDA)).pool.toString());
+    const engine = await program.account.engine.fetch(enginePDA);
+    assert.equal(engine.shutdownDebt.toNumber(), 5_000000);
+
+    const clock = await banksClient.getClock();
+    context.setClock(
+      new Clock(
+        clock.slot,
+        clock.epochStartTimestamp,
+        clock.epoch,
+        clock.leaderScheduleEpoch,
+        clock.unixTimestamp + BigInt(86_400)
+      )
+    );
+
+    const packAndCash = async (holder: Keypair, holderDsc: PublicKey, to: PublicKey, amount: number) => {
+      const [bagPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from('bag'), enginePDA.toBuffer(), holder.publicKey.toBuffer()],
+        program.programId
+      );
+      const [claimPDA] = PublicKey.findProgramAddressSync(
+        [Buffer.from('cash'), enginePDA.toBuffer(), holder.publicKey.toBuffer(), mint.toBuffer()],
+        program.programId
+      );
+      await program.methods
+        .packDsc(new BN(amount))
+        .accountsStrict({
+          user: holder.publicKey,
+          engine: enginePDA,
+          dscMint: dscMint,
+          userDscAccount: holderDsc,
+          bag: bagPDA,
+          systemProgram: SystemProgram.programId,
+          dscTokenProgram: TOKEN_2022_PROGRAM_ID,
+        })
+        .signers([holder])
+        .rpc();
+      await program.methods
+        .cashCollateral()
+        .accountsStrict({
+          user: holder.publicKey,
+          engine: enginePDA,
+          tokenMint: mint,
+          settlement: settlementPDA,
+          bag: bagPDA,
+          claim: claimPDA,
+          config: configPDA,
+          vault: vaultATA,
+          userTokenAccount: to,
+          systemProgram: SystemProgram.programId,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        })
+        .signers([holder])
+        .rpc();
+    };
+
+    const userBefore = await tokenBalance(userATA);
+    await packAndCash(user, userDSCAccount, userATA, 3_000000);
+    const userShare = (pool * 3n) / 5n;
+    assert.equal(await tokenBalance(userATA), userBefore + userShare);
+
+    await packAndCash(keeper, keeperDsc, keeperCollateral, 2_000000);
+    assert.equal(await tokenBalance(keeperCollateral), pool - userShare);
+
+    const settlement = await program.account.settlement.fetch(settlementPDA);
+    assert.ok(settlement.pool.isZero());
+    assert.equal(settlement.cashed.toNumber(), 5_000000);
+  });
+
+  it('returns leftover collateral to the holder of a tokenized position after shutdown', async () => {
+    await initializeConfig();
+    await depositCollateral();
+
+    const [positionMint] = PublicKey.findProgramAddressSync(
+      [Buffer.from('position_mint'), userPDA.toBuffer()],
+      program.programId
+    );
+    const ownerNft = getAssociatedTokenAddressSync(positionMint, user.publicKey, false, TOKEN_2022_PROGRAM_ID);
+
+    await program.methods
+      .tokenizePosition()
+      .accountsStrict({
+        owner: user.publicKey,
+        engine: enginePDA,
+        tokenMint: mint,
+        userData: userPDA,
+        positionMint,
+        ownerNftAccount: ownerNft,
+        nftTokenProgram: TOKEN_2022_PROGRAM_ID,
+        systemProgram: SystemProgram.programId,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const buyer = fundedKeypair();
+    const buyerNft = getAssociatedTokenAddressSync(positionMint, buyer.publicKey, false, TOKEN_2022_PROGRAM_ID);
+    const buyerATA = getAssociatedTokenAddressSync(mint, buyer.publicKey);
+    const tx = new Transaction().add(
+      createAssociatedTokenAccountInstruction(
+        user.publicKey,
+        buyerNft,
+        buyer.publicKey,
+        positionMint,
+        TOKEN_2022_PROGRAM_ID
+      ),
+      createTransferCheckedInstruction(
+        ownerNft,
+        positionMint,
+        buyerNft,
+        user.publicKey,
+        1,
+        0,
+        [],
+        TOKEN_2022_PROGRAM_ID
+      )
+    );
+    tx.feePayer = user.publicKey;
+    tx.recentBlockhash = context.lastBlockhash;
+    tx.sign(user);
+    await banksClient.processTransaction(tx);
+
+    await program.methods
+      .emergencyShutdown()
+      .accountsStrict({
+        roles: rolesPDA,
+        admin: user.publicKey,
+        engine: enginePDA,
+        dscMint: dscMint,
+      })
+      .signers([user])
+      .rpc();
+
+    const reclaimAccounts = (signer: PublicKey, nft: PublicKey | null, to: PublicKey) => ({
+      user: signer,
+      engine: enginePDA,
+      tokenMint: mint,
+      owner: user.publicKey,
+      userData: userPDA,
+      deposit: depositPDA,
+      positionNft: nft,
+      config: configPDA,
+      vault: vaultATA,
+      userTokenAccount: to,
+      systemProgram: SystemProgram.programId,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+    });
+
+    await assert.rejects(
+      program.methods
+        .reclaimCollateral()
+        .accountsStrict(reclaimAccounts(user.publicKey, ownerNft, userATA))
+        .signers([user])
+        .rpc(),
+      /NotPositionHolder/
+    );
+
+    await program.methods
+      .reclaimCollateral()
+      .accountsStrict(reclaimAccounts(buyer.publicKey, buyerNft, buyerATA))
+      .signers([buyer])
+      .rpc();
+
+    const deposit = await program.account.deposit.fetch(depositPDA);
+    assert.ok(deposit.tokenAmt.isZero());
+    assert.equal(await tokenBalance(buyerATA), BigInt(100_000_000));
+  });
+  it('lists tokens in the registry and delists them', async () => {
+    await initializeConfig();
+    await depositCollateral();
+
+    let registry = await program.account.collateralRegistry.fetch(registryPDA);
+    assert.strictEqual(registry.count, 1);
+    assert.ok(registry.entries[0].mint.equals(mint));
+
+    await program.methods
+      .delistToken()
+      .accountsStrict({
+        engine: enginePDA,
+        roles: rolesPDA,
+        admin: user.publicKey,
+        config: configPDA,
+        registry: registryPDA,
+      })
+      .signers([user])
+      .rpc();
+
+    registry = await program.account.collateralRegistry.fetch(registryPDA);
+    assert.strictEqual(registry.entries[0].status, 2);
+    await assert.rejects(depositCollateral(new BN(1_000)), /CollateralNotActive/);
+  });
+  it('starts independent engines per market id', async () => {
+    const otherMarket = new BN(1);
+    const [otherEngine] = PublicKey.findProgramAddressSync(
+      [Buffer.from('engine'), otherMarket.toArrayLike(Buffer, 'le', 8)],
+      program.programId
+    );
+    const [otherRoles] = PublicKey.findProgramAddressSync(
+      [Buffer.from('roles'), otherEngine.toBuffer()],
+      program.programId
+    );
+    const [otherRegistry] = PublicKey.findProgramAddressSync(
+      [Buffer.from('registry'), otherEngine.toBuffer()],
+      program.programId
+    );
+    const [otherDscMint] = PublicKey.findProgramAddressSync(
+      [Buffer.from('dsc_mint'), otherEngine.toBuffer()],
+      program.programId
+    );
+
+    await program.methods
+      .startEngine(otherMarket, new BN(80), new BN(1), new BN(5), new BN(2), {
+        name: 'Credix Stablecoin',
+        symbol: 'DSC',
+        uri: 'https://ninad00.github.io/CredixSolana/dsc.json',
+      })
+      .accountsStrict({
+        engine: otherEngine,
+        roles: otherRoles,
+        registry: otherRegistry,
+        authority: user.publicKey,
+        dscMint: otherDscMint,
+        systemProgram: SystemProgram.programId,
+        tokenProgram: TOKEN_2022_PROGRAM_ID,
+      })
+      .signers([user])
+      .rpc();
+
+    const first = await program.account.engine.fetch(enginePDA);
+    const second = await program.account.engine.fetch(otherEngine);
+    assert.ok(second.marketId.eq(otherMarket));
+    assert.strictEqual(second.liquidationThreshold.toNumber(), 80);
+    assert.strictEqual(first.liquidationThreshold.toNumber(), 50);
+    assert.ok(!second.dscMint.equals(first.dscMint));
   });
 });